@@ -0,0 +1,499 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use crossbeam_channel::{Receiver, Sender};
+use tracing::{debug, error, warn};
+
+use crate::components::bookmarks::BookmarkStore;
+use crate::components::clock::Clock;
+use crate::components::config::ConfigLoaderHandle;
+use crate::components::listening_time::ListeningTime;
+use crate::components::tag_mapper::TagMapperHandle;
+use crate::components::volume::Volume;
+use crate::effects::{Effect, Interpreter, PlaybackEvent};
+use crate::input_controller::{button, Input};
+use crate::player::Player;
+
+// Interval at which the player is ticked, e.g. for checking the sleep timer.
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+// Interprets effects until the effect channel is closed.
+pub fn run_interpreter<I: Interpreter>(interpreter: &mut I, effect_rx: Receiver<Effect>) {
+    for effect in effect_rx {
+        debug!("interpreting effect {:?}", effect);
+        if let Err(err) = interpreter.interprete(effect.clone()) {
+            error!("interpreting effect {:?} failed: {}", effect, err);
+        }
+    }
+}
+
+// State the application logic is operating on.
+pub struct Components {
+    pub tag_mapper: TagMapperHandle,
+    pub bookmarks: BookmarkStore,
+    pub volume: Volume,
+    pub listening_time: ListeningTime,
+    pub clock: Arc<dyn Clock>,
+}
+
+// Executes the application logic. Returns once the input channel is closed.
+pub fn run(
+    config: ConfigLoaderHandle,
+    input: Receiver<Input>,
+    playback_events: Receiver<PlaybackEvent>,
+    effect_tx: Sender<Effect>,
+    components: Components,
+) -> Result<()> {
+    let Components {
+        tag_mapper,
+        bookmarks,
+        volume,
+        listening_time,
+        clock,
+    } = components;
+    let ticks = clock.ticker(TICK_INTERVAL);
+    let mut player = Player::new(
+        effect_tx.clone(),
+        config.clone(),
+        tag_mapper,
+        bookmarks,
+        volume,
+        listening_time,
+        clock,
+    )?;
+    loop {
+        crossbeam_channel::select! {
+            recv(input) -> input_ev => {
+                let input_ev = match input_ev {
+                    Ok(input_ev) => input_ev,
+                    Err(_) => {
+                        player.shutdown();
+                        return Ok(());
+                    }
+                };
+                handle_input(&config, &mut player, input_ev, &effect_tx);
+            }
+            recv(playback_events) -> event => {
+                let event = event.context("Receiving playback event")?;
+                handle_playback_event(&mut player, event);
+            }
+            recv(ticks) -> _ => handle_tick(&mut player),
+        }
+    }
+}
+
+fn handle_input(
+    config: &ConfigLoaderHandle,
+    player: &mut Player,
+    input_ev: Input,
+    effect_tx: &Sender<Effect>,
+) {
+    debug!("Processing input event: {:?}", input_ev);
+    let res = process_ev(config.clone(), player, input_ev.clone(), effect_tx.clone());
+    match res {
+        Err(err) => {
+            error!("Failed to process input event {:?}: {}", input_ev, err);
+        }
+        Ok(effects) => {
+            for effect in effects {
+                if let Err(err) = effect_tx.send(effect.clone()) {
+                    error!("Failed to send output effect {:?}: {}", effect, err);
+                }
+            }
+        }
+    }
+}
+
+fn handle_playback_event(player: &mut Player, event: PlaybackEvent) {
+    if let Err(err) = player.playback_event(event.clone()) {
+        error!("Failed to process playback event {:?}: {}", event, err);
+    }
+}
+
+fn handle_tick(player: &mut Player) {
+    if let Err(err) = player.tick() {
+        error!("Failed to process tick: {}", err);
+    }
+}
+
+pub fn process_ev(
+    config_loader: ConfigLoaderHandle,
+    player: &mut Player,
+    input: Input,
+    _output: Sender<Effect>,
+) -> Result<Vec<Effect>> {
+    let config = config_loader.get();
+
+    match input {
+        Input::Button(cmd) => match cmd {
+            button::Command::VolumeUp => {
+                player.volume_up_command()?;
+                Ok(config
+                    .volume_up_command
+                    .into_iter()
+                    .map(Effect::GenericCommand)
+                    .collect())
+            }
+            button::Command::VolumeDown => {
+                player.volume_down_command()?;
+                Ok(config
+                    .volume_down_command
+                    .into_iter()
+                    .map(Effect::GenericCommand)
+                    .collect())
+            }
+            button::Command::PauseContinue => {
+                player.pause_continue_command()?;
+                Ok(vec![])
+            }
+            button::Command::SleepTimer => {
+                player.sleep_timer_command()?;
+                Ok(vec![])
+            }
+            button::Command::NextTrack => {
+                player.next_track_command()?;
+                Ok(vec![])
+            }
+            button::Command::PreviousTrack => {
+                player.previous_track_command()?;
+                Ok(vec![])
+            }
+            button::Command::Stop => {
+                player.stop_command()?;
+                Ok(vec![])
+            }
+            button::Command::SeekForward => {
+                player.seek_forward_command()?;
+                Ok(vec![])
+            }
+            button::Command::SeekBackward => {
+                player.seek_backward_command()?;
+                Ok(vec![])
+            }
+            button::Command::Shutdown => {
+                player.stop_command()?;
+                player.shutdown();
+                if config.shutdown_command.is_none() {
+                    warn!("Ignoring shutdown command, no shutdown command configured");
+                }
+                Ok(config
+                    .shutdown_command
+                    .into_iter()
+                    .map(Effect::GenericCommand)
+                    .collect())
+            }
+        },
+        Input::Playback(request) => {
+            player.playback(request.clone())?;
+            Ok(vec![])
+        }
+        Input::Volume(level) => {
+            player.set_volume_command(level.0)?;
+            Ok(vec![])
+        }
+        Input::Number(number) => {
+            player.number_command(&number.0)?;
+            Ok(vec![])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::thread;
+    use std::time::Instant;
+
+    use crate::components::clock::SimulatedClock;
+    use crate::components::rfid::{Tag, Uid};
+    use crate::components::tag_mapper::{ResumePolicy, TagConf};
+    use crate::effects::recording::{RecordingInterpreter, RecordingInterpreterHandle};
+    use crate::effects::PlaybackPosition;
+    use crate::model::config::Config;
+    use crate::player::PlaybackRequest;
+
+    const TAG: &str = "tag";
+
+    fn config() -> Config {
+        Config {
+            sleep_timer_minutes: 2,
+            ..Config::default()
+        }
+    }
+
+    fn tag_conf() -> TagConf {
+        TagConf {
+            tag_id: TAG.to_string(),
+            uris: vec!["a.mp3".to_string(), "b.mp3".to_string()],
+            resume: Some(ResumePolicy::Always),
+            ..TagConf::default()
+        }
+    }
+
+    fn components(config: &Config, clock: &SimulatedClock) -> Components {
+        let mappings = HashMap::from([(TAG.to_string(), tag_conf())]);
+        Components {
+            tag_mapper: TagMapperHandle::from_mappings(mappings),
+            bookmarks: BookmarkStore::new(None).unwrap(),
+            volume: Volume::new(config).unwrap(),
+            listening_time: ListeningTime::new(config).unwrap(),
+            clock: Arc::new(clock.clone()),
+        }
+    }
+
+    fn present_tag() -> Input {
+        Input::Playback(PlaybackRequest::Start(Tag {
+            uid: Uid::from_id(TAG.to_string()),
+        }))
+    }
+
+    fn remove_tag() -> Input {
+        Input::Playback(PlaybackRequest::Stop)
+    }
+
+    fn is_playback(effect: &Effect) -> bool {
+        matches!(
+            effect,
+            Effect::Play(_)
+                | Effect::PlayContinue(_, _)
+                | Effect::Stop
+                | Effect::FadeOut(_)
+                | Effect::FadeIn(_)
+        )
+    }
+
+    // Playback related effects recorded so far, with the simulated time they were
+    // interpreted at.
+    fn playback_effects(recording: &RecordingInterpreterHandle) -> Vec<(Instant, Effect)> {
+        recording
+            .effects()
+            .into_iter()
+            .filter(|recorded| is_playback(&recorded.effect))
+            .map(|recorded| (recorded.at, recorded.effect))
+            .collect()
+    }
+
+    // Runs the application logic on the calling thread, handling each input together
+    // with all effects, playback events and ticks resulting from it, the way `run` does.
+    struct Harness {
+        config: ConfigLoaderHandle,
+        clock: SimulatedClock,
+        player: Player,
+        ticks: Receiver<Instant>,
+        effect_tx: Sender<Effect>,
+        effect_rx: Receiver<Effect>,
+        playback_rx: Receiver<PlaybackEvent>,
+        interpreter: RecordingInterpreter,
+        recording: RecordingInterpreterHandle,
+    }
+
+    impl Harness {
+        fn new(config: Config) -> Self {
+            let clock = SimulatedClock::new();
+            let Components {
+                tag_mapper,
+                bookmarks,
+                volume,
+                listening_time,
+                clock: shared_clock,
+            } = components(&config, &clock);
+            let config = ConfigLoaderHandle::from_config(config);
+            let (effect_tx, effect_rx) = crossbeam_channel::unbounded();
+            let (playback_tx, playback_rx) = crossbeam_channel::unbounded();
+            let interpreter = RecordingInterpreter::new(shared_clock.clone(), playback_tx);
+            let recording = interpreter.handle();
+            let ticks = shared_clock.ticker(TICK_INTERVAL);
+            let player = Player::new(
+                effect_tx.clone(),
+                config.clone(),
+                tag_mapper,
+                bookmarks,
+                volume,
+                listening_time,
+                shared_clock,
+            )
+            .unwrap();
+            let mut harness = Harness {
+                config,
+                clock,
+                player,
+                ticks,
+                effect_tx,
+                effect_rx,
+                playback_rx,
+                interpreter,
+                recording,
+            };
+            harness.settle();
+            harness
+        }
+
+        fn settle(&mut self) {
+            loop {
+                if let Ok(effect) = self.effect_rx.try_recv() {
+                    let _ = self.interpreter.interprete(effect);
+                } else if let Ok(event) = self.playback_rx.try_recv() {
+                    handle_playback_event(&mut self.player, event);
+                } else if self.ticks.try_recv().is_ok() {
+                    handle_tick(&mut self.player);
+                } else {
+                    return;
+                }
+            }
+        }
+
+        fn input(&mut self, input: Input) {
+            handle_input(&self.config, &mut self.player, input, &self.effect_tx);
+            self.settle();
+        }
+
+        fn advance(&mut self, duration: Duration) {
+            self.clock.advance(duration);
+            self.settle();
+        }
+    }
+
+    #[test]
+    fn paused_playback_continues_at_the_simulated_position() {
+        let mut harness = Harness::new(config());
+        let start = harness.clock.now();
+        harness.input(present_tag());
+        harness.advance(Duration::from_secs(30));
+        harness.input(remove_tag());
+        harness.advance(Duration::from_secs(60));
+        harness.input(present_tag());
+
+        let position = PlaybackPosition {
+            track: 0,
+            offset: Duration::from_secs(30),
+        };
+        assert_eq!(
+            playback_effects(&harness.recording),
+            vec![
+                (start, Effect::Play(tag_conf())),
+                (start + Duration::from_secs(30), Effect::Stop),
+                (
+                    start + Duration::from_secs(90),
+                    Effect::PlayContinue(tag_conf(), position)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn paused_playback_continues_at_the_reported_track() {
+        let mut harness = Harness::new(config());
+        harness.input(present_tag());
+        harness.advance(Duration::from_secs(100));
+        harness
+            .recording
+            .start_track(1, Duration::ZERO, Some(Duration::from_secs(200)));
+        harness.settle();
+        harness.advance(Duration::from_secs(20));
+        harness.input(remove_tag());
+        harness.recording.take_effects();
+        harness.input(present_tag());
+
+        let position = PlaybackPosition {
+            track: 1,
+            offset: Duration::from_secs(20),
+        };
+        assert_eq!(
+            playback_effects(&harness.recording)
+                .into_iter()
+                .map(|(_, effect)| effect)
+                .collect::<Vec<_>>(),
+            vec![Effect::PlayContinue(tag_conf(), position)]
+        );
+    }
+
+    #[test]
+    fn ticks_drive_the_sleep_timer() {
+        let mut harness = Harness::new(config());
+        let start = harness.clock.now();
+        harness.input(present_tag());
+        harness.input(Input::Button(button::Command::SleepTimer));
+        // Less than a tick interval passes, the sleep timer is not even looked at.
+        harness.clock.advance(Duration::from_millis(500));
+        harness.settle();
+        assert_eq!(playback_effects(&harness.recording).len(), 1);
+
+        harness.advance(Duration::from_millis(59_500));
+        harness.advance(Duration::from_secs(60));
+        assert_eq!(
+            playback_effects(&harness.recording),
+            vec![
+                (start, Effect::Play(tag_conf())),
+                (
+                    start + Duration::from_secs(60),
+                    Effect::FadeOut(Duration::from_secs(60))
+                ),
+                (start + Duration::from_secs(120), Effect::Stop),
+            ]
+        );
+    }
+
+    // Polls the recording until an effect matching the predicate has been interpreted.
+    fn wait_for(recording: &RecordingInterpreterHandle, predicate: impl Fn(&Effect) -> bool) {
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        while !recording
+            .effects()
+            .iter()
+            .any(|recorded| predicate(&recorded.effect))
+        {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "timed out waiting for effect, got {:?}",
+                recording.effects()
+            );
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn run_ticks_with_the_injected_clock_and_returns_once_inputs_close() {
+        let config = config();
+        let clock = SimulatedClock::new();
+        let components = components(&config, &clock);
+        let (input_tx, input_rx) = crossbeam_channel::unbounded();
+        let (effect_tx, effect_rx) = crossbeam_channel::unbounded();
+        let (playback_tx, playback_rx) = crossbeam_channel::unbounded();
+        let mut interpreter = RecordingInterpreter::new(components.clock.clone(), playback_tx);
+        let recording = interpreter.handle();
+        let interpreter_thread =
+            thread::spawn(move || run_interpreter(&mut interpreter, effect_rx));
+        let app_thread = thread::spawn(move || {
+            run(
+                ConfigLoaderHandle::from_config(config),
+                input_rx,
+                playback_rx,
+                effect_tx,
+                components,
+            )
+        });
+
+        input_tx.send(present_tag()).unwrap();
+        input_tx
+            .send(Input::Button(button::Command::SleepTimer))
+            .unwrap();
+        // Inputs are processed in order, the volume change tells that the sleep timer has
+        // been armed.
+        input_tx
+            .send(Input::Button(button::Command::VolumeDown))
+            .unwrap();
+        wait_for(&recording, |effect| *effect == Effect::SetVolume(90));
+        assert!(!recording
+            .effects()
+            .iter()
+            .any(|recorded| recorded.effect == Effect::Stop));
+
+        clock.advance(Duration::from_secs(120));
+        wait_for(&recording, |effect| *effect == Effect::Stop);
+
+        drop(input_tx);
+        app_thread.join().unwrap().unwrap();
+        interpreter_thread.join().unwrap();
+    }
+}
@@ -2,7 +2,6 @@ use anyhow::Result;
 use std::thread;
 use std::time::Duration;
 use tracing::info;
-use tracing_subscriber;
 
 use rustberry::components::rfid::RfidController;
 
@@ -53,9 +53,9 @@ fn main() -> Result<()> {
                 if let Ok(uid) = mfrc522.select(&atqa) {
                     println!("Ok: UID: {:?}", uid.as_bytes());
 
-                    if uid.as_bytes() == &CARD_UID {
+                    if uid.as_bytes() == CARD_UID {
                         println!("CARD");
-                    } else if uid.as_bytes() == &TAG_UID {
+                    } else if uid.as_bytes() == TAG_UID {
                         println!("TAG");
                     }
 
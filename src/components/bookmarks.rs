@@ -0,0 +1,99 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tracing::{debug, info, warn};
+
+use crate::components::json_file;
+use crate::effects::PlaybackPosition;
+
+type TagID = String;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Bookmark {
+    pub track: usize,
+    pub offset: Duration,
+    pub paused_at: SystemTime,
+}
+
+impl Bookmark {
+    pub fn new(position: PlaybackPosition, paused_at: SystemTime) -> Self {
+        Bookmark {
+            track: position.track,
+            offset: position.offset,
+            paused_at,
+        }
+    }
+
+    pub fn position(&self) -> PlaybackPosition {
+        PlaybackPosition {
+            track: self.track,
+            offset: self.offset,
+        }
+    }
+}
+
+// Bookmarks are stored as JSON object keyed by tag UID:
+//
+// {"0a1b2c3d": {"track": 3, "offset": {"secs": 312, "nanos": 0}, "paused_at": {...}}}
+//
+
+#[derive(Debug, Clone)]
+pub struct BookmarkStore {
+    file: Option<PathBuf>,
+    bookmarks: Arc<RwLock<HashMap<TagID, Bookmark>>>,
+}
+
+impl BookmarkStore {
+    fn load(file: &Path) -> Result<HashMap<TagID, Bookmark>> {
+        Ok(json_file::load(file, "bookmarks")?.unwrap_or_default())
+    }
+
+    fn persist(&self, bookmarks: &HashMap<TagID, Bookmark>) -> Result<()> {
+        let file = match self.file {
+            Some(ref file) => file,
+            None => return Ok(()),
+        };
+        json_file::store(file, bookmarks, "bookmarks")
+    }
+
+    pub fn new(file: Option<&Path>) -> Result<Self> {
+        let bookmarks = match file {
+            Some(file) => {
+                info!("Using bookmarks file {}", file.display());
+                Self::load(file)?
+            }
+            None => {
+                warn!("No bookmarks file configured, bookmarks will not survive restarts");
+                HashMap::new()
+            }
+        };
+        Ok(BookmarkStore {
+            file: file.map(|file| file.to_path_buf()),
+            bookmarks: Arc::new(RwLock::new(bookmarks)),
+        })
+    }
+
+    pub fn get(&self, tag_id: &str) -> Option<Bookmark> {
+        let r = self.bookmarks.read().unwrap();
+        r.get(tag_id).cloned()
+    }
+
+    pub fn set(&self, tag_id: &str, bookmark: Bookmark) -> Result<()> {
+        debug!("Setting bookmark for tag {}: {:?}", tag_id, bookmark);
+        let mut w = self.bookmarks.write().unwrap();
+        w.insert(tag_id.to_string(), bookmark);
+        self.persist(&w)
+    }
+
+    pub fn remove(&self, tag_id: &str) -> Result<()> {
+        let mut w = self.bookmarks.write().unwrap();
+        if w.remove(tag_id).is_some() {
+            debug!("Removed bookmark for tag {}", tag_id);
+            self.persist(&w)?;
+        }
+        Ok(())
+    }
+}
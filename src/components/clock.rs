@@ -0,0 +1,156 @@
+use crossbeam_channel::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+// Wall clock time in the local time zone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalTime {
+    // Days since the epoch.
+    pub day: i64,
+    // Seconds since midnight.
+    pub seconds: u32,
+}
+
+impl LocalTime {
+    pub fn new(system_time: SystemTime, utc_offset: i64) -> Self {
+        let since_epoch = match system_time.duration_since(UNIX_EPOCH) {
+            Ok(duration) => duration.as_secs() as i64,
+            Err(err) => -(err.duration().as_secs() as i64),
+        };
+        let local = since_epoch + utc_offset;
+        LocalTime {
+            day: local.div_euclid(SECONDS_PER_DAY),
+            seconds: local.rem_euclid(SECONDS_PER_DAY) as u32,
+        }
+    }
+}
+
+// Source of the current time. The player consults the clock instead of reading the
+// system time directly, so that time can be simulated.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+    fn system_time(&self) -> SystemTime;
+    // Offset of the local time zone to UTC in seconds.
+    fn utc_offset(&self) -> i64;
+    // Returns a channel receiving the current time whenever `interval` has passed.
+    fn ticker(&self, interval: Duration) -> Receiver<Instant>;
+
+    fn local_time(&self) -> LocalTime {
+        LocalTime::new(self.system_time(), self.utc_offset())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    // Determined anew each time, since it changes with daylight saving time.
+    fn utc_offset(&self) -> i64 {
+        let now = unsafe { libc::time(std::ptr::null_mut()) };
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        let res = unsafe { libc::localtime_r(&now, &mut tm) };
+        if res.is_null() {
+            return 0;
+        }
+        tm.tm_gmtoff as i64
+    }
+
+    fn ticker(&self, interval: Duration) -> Receiver<Instant> {
+        crossbeam_channel::tick(interval)
+    }
+}
+
+#[derive(Debug)]
+struct SimulatedTicker {
+    interval: Duration,
+    next: Instant,
+    tx: Sender<Instant>,
+}
+
+// A clock which only advances when told to. Clones share the same time. The local time
+// zone is UTC, unless configured otherwise. Tickers fire while the clock is advanced,
+// once for every interval passed.
+#[derive(Debug, Clone)]
+pub struct SimulatedClock {
+    time: Arc<Mutex<(Instant, SystemTime)>>,
+    tickers: Arc<Mutex<Vec<SimulatedTicker>>>,
+    utc_offset: i64,
+}
+
+impl SimulatedClock {
+    pub fn new() -> Self {
+        Self::starting_at(SystemTime::now())
+    }
+
+    pub fn starting_at(system_time: SystemTime) -> Self {
+        SimulatedClock {
+            time: Arc::new(Mutex::new((Instant::now(), system_time))),
+            tickers: Arc::new(Mutex::new(vec![])),
+            utc_offset: 0,
+        }
+    }
+
+    pub fn with_utc_offset(mut self, utc_offset: i64) -> Self {
+        self.utc_offset = utc_offset;
+        self
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let now = {
+            let mut time = self.time.lock().unwrap();
+            time.0 += duration;
+            time.1 += duration;
+            time.0
+        };
+        let mut tickers = self.tickers.lock().unwrap();
+        tickers.retain_mut(|ticker| {
+            while ticker.next <= now {
+                if ticker.tx.send(ticker.next).is_err() {
+                    return false;
+                }
+                ticker.next += ticker.interval;
+            }
+            true
+        });
+    }
+}
+
+impl Default for SimulatedClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn now(&self) -> Instant {
+        self.time.lock().unwrap().0
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.time.lock().unwrap().1
+    }
+
+    fn utc_offset(&self) -> i64 {
+        self.utc_offset
+    }
+
+    fn ticker(&self, interval: Duration) -> Receiver<Instant> {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        self.tickers.lock().unwrap().push(SimulatedTicker {
+            interval: interval.max(Duration::from_nanos(1)),
+            next: self.now() + interval,
+            tx,
+        });
+        rx
+    }
+}
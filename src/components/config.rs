@@ -1,9 +1,10 @@
 use crate::model;
 use anyhow::{Context, Result};
-use std::default::Default;
 use std::io;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
+
+use crossbeam_channel::{Receiver, Sender};
 
 use tokio::time::{sleep, Duration};
 use tracing::level_filters::LevelFilter;
@@ -17,11 +18,13 @@ pub struct ConfigLoader {
     cfg_file: PathBuf,
     cfg: Arc<RwLock<model::config::Config>>,
     reload_handle: reload::Handle<LevelFilter, Registry>,
+    subscribers: Arc<Mutex<Vec<Sender<Config>>>>,
 }
 
 #[derive(Clone)]
 pub struct ConfigLoaderHandle {
     cfg: Arc<RwLock<model::config::Config>>,
+    subscribers: Arc<Mutex<Vec<Sender<Config>>>>,
 }
 
 impl ConfigLoaderHandle {
@@ -29,6 +32,21 @@ impl ConfigLoaderHandle {
         let read_guard = self.cfg.read().unwrap();
         read_guard.clone()
     }
+
+    // Returns a handle to the given configuration, which is never reloaded.
+    pub fn from_config(cfg: Config) -> Self {
+        ConfigLoaderHandle {
+            cfg: Arc::new(RwLock::new(cfg)),
+            subscribers: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    // Returns a channel receiving the configuration whenever a reload changes it.
+    pub fn subscribe(&self) -> Receiver<Config> {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
 }
 
 impl ConfigLoader {
@@ -72,6 +90,12 @@ impl ConfigLoader {
         }
     }
 
+    // Subscribers which have gone away are dropped.
+    fn notify_subscribers(&self, cfg: &Config) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|subscriber| subscriber.send(cfg.clone()).is_ok());
+    }
+
     async fn loader_loop(self) {
         let cfg_file = self.cfg_file.as_path();
         info!("Config loader loop started");
@@ -80,13 +104,15 @@ impl ConfigLoader {
                 "Attempting to reload configuration from {}",
                 cfg_file.display()
             );
-            match Self::load_cfg(&cfg_file).await {
+            match Self::load_cfg(cfg_file).await {
                 Ok(cfg_part) => {
                     let cfg_prev = self.get();
-                    let mut cfg = cfg_prev.clone();
-                    cfg.merge_partial(cfg_part);
+                    let cfg = Config::from_partial(cfg_part);
                     self.log_level_hook(&cfg_prev, &cfg);
-                    self.set(cfg);
+                    self.set(cfg.clone());
+                    if cfg != cfg_prev {
+                        self.notify_subscribers(&cfg);
+                    }
                 }
                 Err(err) => {
                     if let Some(io_err) = err.downcast_ref::<io::Error>() {
@@ -111,22 +137,23 @@ impl ConfigLoader {
 
     fn handle(&self) -> ConfigLoaderHandle {
         let cfg = self.cfg.clone();
-        ConfigLoaderHandle { cfg }
+        let subscribers = self.subscribers.clone();
+        ConfigLoaderHandle { cfg, subscribers }
     }
 
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(
         cfg_file: &Path,
         reload_handle: reload::Handle<LevelFilter, Registry>,
     ) -> Result<ConfigLoaderHandle> {
         let cfg_file = cfg_file.to_path_buf();
-        let mut cfg = model::config::Config::default();
-        let cfg_from_file = Self::load_cfg_sync(&cfg_file)?;
-        cfg.merge_partial(cfg_from_file);
+        let cfg = Config::from_partial(Self::load_cfg_sync(&cfg_file)?);
         let cfg = Arc::new(RwLock::new(cfg));
         let cfg_loader = ConfigLoader {
             cfg_file,
             cfg,
             reload_handle,
+            subscribers: Arc::new(Mutex::new(vec![])),
         };
         let handle = cfg_loader.handle();
         if let Err(err) = cfg_loader.spawn_async_loader() {
@@ -0,0 +1,95 @@
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+// Helpers for the small JSON files used to persist state across restarts. `what`
+// describes the content of the file in log and error messages.
+
+// Reads the JSON file at `file`. Returns `None` if it does not exist yet.
+pub fn load<T: DeserializeOwned>(file: &Path, what: &str) -> Result<Option<T>> {
+    let content = match fs::read_to_string(file) {
+        Ok(cnt) => cnt,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            debug!("No {} found at {}", what, file.display());
+            return Ok(None);
+        }
+        Err(err) => {
+            return Err(err).with_context(|| format!("Reading {} at {}", what, file.display()));
+        }
+    };
+    let value = serde_json::from_str(&content)
+        .with_context(|| format!("JSON unmarshalling {} at {}", what, file.display()))?;
+    Ok(Some(value))
+}
+
+// Writes `value` as JSON to `file`. Writes to a temporary file first, which is synced
+// and then renamed, so that a power loss does not leave a truncated file behind.
+pub fn store<T: Serialize>(file: &Path, value: &T, what: &str) -> Result<()> {
+    let content =
+        serde_json::to_string(value).with_context(|| format!("JSON marshalling {}", what))?;
+    let tmp_file = tmp_path(file);
+    write_synced(&tmp_file, content.as_bytes())
+        .with_context(|| format!("Writing {} to {}", what, tmp_file.display()))?;
+    fs::rename(&tmp_file, file)
+        .with_context(|| format!("Renaming {} file to {}", what, file.display()))?;
+    // Make the rename itself durable.
+    let dir = match file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .with_context(|| format!("Syncing directory {}", dir.display()))?;
+    Ok(())
+}
+
+// `state.json` becomes `state.json.tmp`, so that files which only differ in their
+// extension do not share a temporary file.
+fn tmp_path(file: &Path) -> PathBuf {
+    let mut name = file.as_os_str().to_owned();
+    name.push(".tmp");
+    PathBuf::from(name)
+}
+
+fn write_synced(file: &Path, content: &[u8]) -> io::Result<()> {
+    let mut file = File::create(file)?;
+    file.write_all(content)?;
+    file.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn tmp_path_keeps_the_extension() {
+        assert_eq!(
+            tmp_path(Path::new("/var/lib/state.json")),
+            Path::new("/var/lib/state.json.tmp")
+        );
+        assert_ne!(
+            tmp_path(Path::new("state.json")),
+            tmp_path(Path::new("state.yaml"))
+        );
+    }
+
+    #[test]
+    fn stored_values_are_loaded() {
+        let dir = std::env::temp_dir().join(format!("rustberry-json-file-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("state.json");
+        let value: BTreeMap<String, u32> = vec![("a".to_string(), 1)].into_iter().collect();
+
+        assert_eq!(load::<BTreeMap<String, u32>>(&file, "state").unwrap(), None);
+        store(&file, &value, "state").unwrap();
+        assert_eq!(load(&file, "state").unwrap(), Some(value));
+        assert!(!tmp_path(&file).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,142 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+
+use crate::components::json_file;
+use crate::model::config::Config;
+
+// Playback time accumulated since the last write is persisted once it exceeds this, so
+// that a power loss does not reset the budget.
+const PERSIST_INTERVAL: Duration = Duration::from_secs(60);
+
+// The listening time of the current day is stored as JSON object:
+//
+// {"day": 19650, "used_secs": 1834, "extra_secs": 900}
+//
+// `day` counts the days since the epoch in local time.
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct PersistedUsage {
+    day: i64,
+    used_secs: u64,
+    extra_secs: u64,
+}
+
+// Accounts playback time against the configured daily budget.
+#[derive(Debug)]
+pub struct ListeningTime {
+    file: Option<PathBuf>,
+    day: i64,
+    used: Duration,
+    // Granted on top of the configured budget for the day.
+    extra: Duration,
+    // Time used as of the last write.
+    persisted: Duration,
+}
+
+impl ListeningTime {
+    fn load(file: &Path) -> Result<Option<PersistedUsage>> {
+        json_file::load(file, "listening time")
+    }
+
+    fn persist(&self) -> Result<()> {
+        let file = match self.file {
+            Some(ref file) => file,
+            None => return Ok(()),
+        };
+        let usage = PersistedUsage {
+            day: self.day,
+            used_secs: self.used.as_secs(),
+            extra_secs: self.extra.as_secs(),
+        };
+        json_file::store(file, &usage, "listening time")
+    }
+
+    pub fn new(config: &Config) -> Result<Self> {
+        let file = config.listening_time_file.as_ref().map(PathBuf::from);
+        let usage = match file {
+            Some(ref file) => {
+                info!("Using listening time file {}", file.display());
+                Self::load(file)?
+            }
+            None => {
+                if config.daily_listening_minutes.is_some() {
+                    warn!("No listening time file configured, the budget resets on restarts");
+                }
+                None
+            }
+        };
+        let (day, used, extra) = match usage {
+            Some(usage) => (
+                usage.day,
+                Duration::from_secs(usage.used_secs),
+                Duration::from_secs(usage.extra_secs),
+            ),
+            None => (0, Duration::from_secs(0), Duration::from_secs(0)),
+        };
+        Ok(ListeningTime {
+            file,
+            day,
+            used,
+            extra,
+            persisted: used,
+        })
+    }
+
+    fn save(&mut self) {
+        match self.persist() {
+            Ok(()) => self.persisted = self.used,
+            Err(err) => error!("Failed to persist listening time: {:#}", err),
+        }
+    }
+
+    // Starts accounting anew once the day has changed.
+    fn roll_over(&mut self, today: i64) {
+        if today != self.day {
+            debug!("Resetting listening time for day {}", today);
+            self.day = today;
+            self.used = Duration::from_secs(0);
+            self.extra = Duration::from_secs(0);
+            self.save();
+        }
+    }
+
+    // Returns the listening time left today, None if the time is not limited.
+    pub fn remaining(&self, config: &Config, today: i64) -> Option<Duration> {
+        let budget = Duration::from_secs(config.daily_listening_minutes? * 60);
+        if today != self.day {
+            return Some(budget);
+        }
+        Some((budget + self.extra).saturating_sub(self.used))
+    }
+
+    pub fn record(&mut self, config: &Config, played: Duration, today: i64) {
+        if config.daily_listening_minutes.is_none() {
+            return;
+        }
+        self.roll_over(today);
+        self.used += played;
+        if self.used.saturating_sub(self.persisted) >= PERSIST_INTERVAL {
+            self.save();
+        }
+    }
+
+    // Persists time recorded since the last write, e.g. once playback stops.
+    pub fn flush(&mut self) {
+        if self.used != self.persisted {
+            self.save();
+        }
+    }
+
+    pub fn grant(&mut self, extra: Duration, today: i64) {
+        self.roll_over(today);
+        self.extra += extra;
+        info!(
+            "Granted {} minutes of extra listening time",
+            extra.as_secs() / 60
+        );
+        self.save();
+    }
+}
@@ -0,0 +1,188 @@
+use embedded_hal_1::spi::SpiDevice;
+use serde::Deserialize;
+
+// Driver for the MCP3008 (10 bit) and MCP3208 (12 bit) ADCs. Both have eight channels,
+// which are read single-ended.
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Model {
+    #[default]
+    Mcp3008,
+    Mcp3208,
+}
+
+impl Model {
+    // Largest value a conversion yields.
+    pub fn full_scale(&self) -> u16 {
+        match self {
+            Model::Mcp3008 => 0x3ff,
+            Model::Mcp3208 => 0xfff,
+        }
+    }
+}
+
+pub const CHANNELS: u8 = 8;
+
+#[derive(Debug)]
+pub enum Error<E> {
+    Spi(E),
+    InvalidChannel(u8),
+}
+
+impl<E: std::fmt::Debug> std::fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Spi(err) => write!(f, "SPI Error: {:?}", err),
+            Error::InvalidChannel(channel) => write!(f, "Invalid ADC channel: {}", channel),
+        }
+    }
+}
+
+impl<E: std::fmt::Debug> std::error::Error for Error<E> {}
+
+pub struct Mcp3x08<SPI> {
+    spi: SPI,
+    model: Model,
+}
+
+impl<SPI: SpiDevice> Mcp3x08<SPI> {
+    pub fn new(spi: SPI, model: Model) -> Self {
+        Mcp3x08 { spi, model }
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    pub fn read(&mut self, channel: u8) -> Result<u16, Error<SPI::Error>> {
+        if channel >= CHANNELS {
+            return Err(Error::InvalidChannel(channel));
+        }
+        // The start bit is followed by the single-ended flag and the channel. The result
+        // is clocked out along with the remaining bits.
+        let mut buf = match self.model {
+            Model::Mcp3008 => [0x01, 0x80 | channel << 4, 0x00],
+            Model::Mcp3208 => [0x06 | channel >> 2, (channel & 0x03) << 6, 0x00],
+        };
+        self.spi.transfer_in_place(&mut buf).map_err(Error::Spi)?;
+        let value = (buf[1] as u16) << 8 | buf[2] as u16;
+        Ok(value & self.model.full_scale())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal_1::spi::{ErrorKind, ErrorType, Operation};
+
+    // Records the bytes sent in each transfer and answers with the given bytes.
+    struct MockSpi {
+        response: [u8; 3],
+        sent: Vec<Vec<u8>>,
+        fail: bool,
+    }
+
+    impl MockSpi {
+        fn new(response: [u8; 3]) -> Self {
+            MockSpi {
+                response,
+                sent: vec![],
+                fail: false,
+            }
+        }
+    }
+
+    impl ErrorType for MockSpi {
+        type Error = ErrorKind;
+    }
+
+    impl SpiDevice for MockSpi {
+        fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), ErrorKind> {
+            if self.fail {
+                return Err(ErrorKind::Other);
+            }
+            for operation in operations {
+                match operation {
+                    Operation::TransferInPlace(buf) => {
+                        self.sent.push(buf.to_vec());
+                        buf.copy_from_slice(&self.response);
+                    }
+                    operation => panic!("unexpected SPI operation {:?}", operation),
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn sends_mcp3008_commands() {
+        let mut adc = Mcp3x08::new(MockSpi::new([0; 3]), Model::Mcp3008);
+        for channel in 0..CHANNELS {
+            adc.read(channel).unwrap();
+        }
+        assert_eq!(
+            adc.spi.sent,
+            (0..CHANNELS)
+                .map(|channel| vec![0x01, 0x80 | channel << 4, 0x00])
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(adc.spi.sent[5], vec![0x01, 0b1101_0000, 0x00]);
+    }
+
+    #[test]
+    fn sends_mcp3208_commands() {
+        let mut adc = Mcp3x08::new(MockSpi::new([0; 3]), Model::Mcp3208);
+        adc.read(0).unwrap();
+        adc.read(3).unwrap();
+        adc.read(5).unwrap();
+        adc.read(7).unwrap();
+        assert_eq!(
+            adc.spi.sent,
+            vec![
+                vec![0b0000_0110, 0b0000_0000, 0x00],
+                vec![0b0000_0110, 0b1100_0000, 0x00],
+                vec![0b0000_0111, 0b0100_0000, 0x00],
+                vec![0b0000_0111, 0b1100_0000, 0x00],
+            ]
+        );
+    }
+
+    #[test]
+    fn decodes_10_bit_results() {
+        // Bits clocked out before the null bit are undefined.
+        let cases = [
+            ([0xff, 0b1111_1110, 0xff], 0x2ff),
+            ([0xff, 0b1111_1011, 0x00], 0x300),
+            ([0x00, 0x00, 0x01], 0x001),
+            ([0x00, 0x03, 0xff], 0x3ff),
+        ];
+        for (response, expected) in cases {
+            let mut adc = Mcp3x08::new(MockSpi::new(response), Model::Mcp3008);
+            assert_eq!(adc.read(0).unwrap(), expected, "response {:x?}", response);
+        }
+    }
+
+    #[test]
+    fn decodes_12_bit_results() {
+        let cases = [
+            ([0xff, 0b1110_1010, 0xbc], 0xabc),
+            ([0x00, 0x0f, 0xff], 0xfff),
+            ([0xff, 0xf0, 0x00], 0x000),
+        ];
+        for (response, expected) in cases {
+            let mut adc = Mcp3x08::new(MockSpi::new(response), Model::Mcp3208);
+            assert_eq!(adc.read(0).unwrap(), expected, "response {:x?}", response);
+        }
+    }
+
+    #[test]
+    fn reports_errors() {
+        let mut adc = Mcp3x08::new(MockSpi::new([0; 3]), Model::Mcp3008);
+        assert!(matches!(adc.read(8), Err(Error::InvalidChannel(8))));
+        assert!(adc.spi.sent.is_empty());
+
+        adc.spi.fail = true;
+        assert!(matches!(adc.read(0), Err(Error::Spi(ErrorKind::Other))));
+    }
+}
@@ -1,3 +1,13 @@
+pub mod bookmarks;
+pub mod clock;
 pub mod config;
+pub mod json_file;
+pub mod listening_time;
+pub mod mcp3x08;
+pub mod mpr121;
 pub mod rfid;
+pub mod schedule;
+pub mod sleep_timer;
+pub mod spi;
 pub mod tag_mapper;
+pub mod volume;
@@ -0,0 +1,290 @@
+use embedded_hal_1::i2c::I2c;
+
+// Driver for the MPR121 capacitive touch controller with twelve electrodes.
+
+pub const DEFAULT_ADDRESS: u8 = 0x5a;
+pub const ELECTRODES: u8 = 12;
+
+const REG_TOUCH_STATUS: u8 = 0x00;
+// Filter settings for rising, falling and touched data, see the data sheet.
+const REG_MHD_RISING: u8 = 0x2b;
+const REG_TOUCH_THRESHOLD: u8 = 0x41;
+const REG_RELEASE_THRESHOLD: u8 = 0x42;
+const REG_DEBOUNCE: u8 = 0x5b;
+const REG_CONFIG1: u8 = 0x5c;
+const REG_CONFIG2: u8 = 0x5d;
+const REG_ELECTRODE_CONFIG: u8 = 0x5e;
+const REG_SOFT_RESET: u8 = 0x80;
+
+const SOFT_RESET: u8 = 0x63;
+// Value of CONFIG2 after a reset, used to recognize the chip.
+const CONFIG2_RESET_VALUE: u8 = 0x24;
+const FILTER_SETTINGS: [u8; 11] = [
+    0x01, 0x01, 0x0e, 0x00, // rising
+    0x01, 0x05, 0x01, 0x00, // falling
+    0x00, 0x00, 0x00, // touched
+];
+// 16 uA charge current, 0.5 us charge time, 1 ms sample interval.
+const CONFIG1: u8 = 0x10;
+const CONFIG2: u8 = 0x20;
+// Run mode with all electrodes enabled and baseline tracking initialized from the first
+// readings.
+const RUN_MODE: u8 = 0x80 | ELECTRODES;
+
+// Touch and release thresholds of an electrode. A touch is detected once the reading
+// drops by more than the touch threshold below the baseline, it is released once the
+// drop falls below the release threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Thresholds {
+    pub touch: u8,
+    pub release: u8,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Thresholds {
+            touch: 12,
+            release: 6,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error<E> {
+    I2c(E),
+    NotFound(u8),
+    InvalidElectrode(u8),
+}
+
+impl<E: std::fmt::Debug> std::fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::I2c(err) => write!(f, "I2C Error: {:?}", err),
+            Error::NotFound(address) => write!(f, "No MPR121 found at address {:#x}", address),
+            Error::InvalidElectrode(electrode) => write!(f, "Invalid electrode: {}", electrode),
+        }
+    }
+}
+
+impl<E: std::fmt::Debug> std::error::Error for Error<E> {}
+
+pub struct Mpr121<I2C> {
+    i2c: I2C,
+    address: u8,
+}
+
+impl<I2C: I2c> Mpr121<I2C> {
+    pub fn new(i2c: I2C, address: u8) -> Self {
+        Mpr121 { i2c, address }
+    }
+
+    fn write_register(&mut self, register: u8, value: u8) -> Result<(), Error<I2C::Error>> {
+        self.i2c
+            .write(self.address, &[register, value])
+            .map_err(Error::I2c)
+    }
+
+    fn read_register(&mut self, register: u8) -> Result<u8, Error<I2C::Error>> {
+        let mut buf = [0];
+        self.i2c
+            .write_read(self.address, &[register], &mut buf)
+            .map_err(Error::I2c)?;
+        Ok(buf[0])
+    }
+
+    // Resets the chip and starts sampling with the given thresholds per electrode.
+    pub fn init(&mut self, thresholds: &[Thresholds]) -> Result<(), Error<I2C::Error>> {
+        self.write_register(REG_SOFT_RESET, SOFT_RESET)?;
+        if self.read_register(REG_CONFIG2)? != CONFIG2_RESET_VALUE {
+            return Err(Error::NotFound(self.address));
+        }
+        // Settings may only be changed in stop mode.
+        self.write_register(REG_ELECTRODE_CONFIG, 0x00)?;
+        for electrode in 0..ELECTRODES {
+            let setting = thresholds
+                .get(electrode as usize)
+                .copied()
+                .unwrap_or_default();
+            self.set_thresholds(electrode, setting)?;
+        }
+        for (i, value) in FILTER_SETTINGS.iter().enumerate() {
+            self.write_register(REG_MHD_RISING + i as u8, *value)?;
+        }
+        self.write_register(REG_DEBOUNCE, 0x00)?;
+        self.write_register(REG_CONFIG1, CONFIG1)?;
+        self.write_register(REG_CONFIG2, CONFIG2)?;
+        self.write_register(REG_ELECTRODE_CONFIG, RUN_MODE)?;
+        Ok(())
+    }
+
+    fn set_thresholds(
+        &mut self,
+        electrode: u8,
+        thresholds: Thresholds,
+    ) -> Result<(), Error<I2C::Error>> {
+        if electrode >= ELECTRODES {
+            return Err(Error::InvalidElectrode(electrode));
+        }
+        self.write_register(REG_TOUCH_THRESHOLD + 2 * electrode, thresholds.touch)?;
+        self.write_register(REG_RELEASE_THRESHOLD + 2 * electrode, thresholds.release)?;
+        Ok(())
+    }
+
+    // Returns the touched electrodes as bit mask.
+    pub fn touched(&mut self) -> Result<u16, Error<I2C::Error>> {
+        let mut buf = [0; 2];
+        self.i2c
+            .write_read(self.address, &[REG_TOUCH_STATUS], &mut buf)
+            .map_err(Error::I2c)?;
+        Ok(u16::from_le_bytes(buf) & ((1 << ELECTRODES) - 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal_1::i2c::{ErrorKind, ErrorType, NoAcknowledgeSource, Operation};
+
+    const ADDRESS: u8 = 0x5b;
+
+    // Register file of a simulated MPR121, recording the registers written.
+    struct MockI2c {
+        registers: [u8; 256],
+        written: Vec<(u8, u8)>,
+        // Whether the chip answers at all, and whether it is an MPR121.
+        present: bool,
+        mpr121: bool,
+    }
+
+    impl MockI2c {
+        fn new() -> Self {
+            MockI2c {
+                registers: [0; 256],
+                written: vec![],
+                present: true,
+                mpr121: true,
+            }
+        }
+    }
+
+    impl ErrorType for MockI2c {
+        type Error = ErrorKind;
+    }
+
+    impl I2c for MockI2c {
+        fn transaction(
+            &mut self,
+            address: u8,
+            operations: &mut [Operation<'_>],
+        ) -> Result<(), ErrorKind> {
+            if !self.present || address != ADDRESS {
+                return Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address));
+            }
+            // The register pointer auto-increments.
+            let mut register = 0usize;
+            for operation in operations {
+                match operation {
+                    Operation::Write(bytes) => {
+                        register = bytes[0] as usize;
+                        for value in &bytes[1..] {
+                            self.written.push((register as u8, *value));
+                            self.registers[register] = *value;
+                            register += 1;
+                        }
+                        if bytes == &[REG_SOFT_RESET, SOFT_RESET] && self.mpr121 {
+                            self.registers = [0; 256];
+                            self.registers[REG_CONFIG2 as usize] = CONFIG2_RESET_VALUE;
+                        }
+                    }
+                    Operation::Read(buf) => {
+                        for value in buf.iter_mut() {
+                            *value = self.registers[register];
+                            register += 1;
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn init_configures_all_electrodes() {
+        let mut mpr121 = Mpr121::new(MockI2c::new(), ADDRESS);
+        let thresholds = [
+            Thresholds {
+                touch: 20,
+                release: 10,
+            },
+            Thresholds {
+                touch: 8,
+                release: 4,
+            },
+        ];
+        mpr121.init(&thresholds).unwrap();
+
+        let mut expected = vec![(REG_SOFT_RESET, SOFT_RESET), (REG_ELECTRODE_CONFIG, 0x00)];
+        expected.extend([(0x41, 20), (0x42, 10), (0x43, 8), (0x44, 4)]);
+        for electrode in 2..ELECTRODES {
+            expected.push((0x41 + 2 * electrode, 12));
+            expected.push((0x42 + 2 * electrode, 6));
+        }
+        expected.extend((0x2b..).zip(FILTER_SETTINGS));
+        expected.extend([
+            (REG_DEBOUNCE, 0x00),
+            (REG_CONFIG1, 0x10),
+            (REG_CONFIG2, 0x20),
+            // Run mode, last, as settings are only accepted in stop mode.
+            (REG_ELECTRODE_CONFIG, 0x8c),
+        ]);
+        assert_eq!(mpr121.i2c.written, expected);
+        // The last threshold register, of electrode 11.
+        assert_eq!(mpr121.i2c.registers[0x58], 6);
+    }
+
+    #[test]
+    fn init_recognizes_the_chip() {
+        let mut i2c = MockI2c::new();
+        i2c.mpr121 = false;
+        let mut mpr121 = Mpr121::new(i2c, ADDRESS);
+        assert!(matches!(mpr121.init(&[]), Err(Error::NotFound(ADDRESS))));
+        // Nothing is configured on an unknown chip.
+        assert_eq!(mpr121.i2c.written, vec![(REG_SOFT_RESET, SOFT_RESET)]);
+
+        let mut i2c = MockI2c::new();
+        i2c.present = false;
+        let mut mpr121 = Mpr121::new(i2c, ADDRESS);
+        assert!(matches!(
+            mpr121.init(&[]),
+            Err(Error::I2c(ErrorKind::NoAcknowledge(_)))
+        ));
+
+        let mut mpr121 = Mpr121::new(MockI2c::new(), DEFAULT_ADDRESS);
+        assert!(matches!(mpr121.init(&[]), Err(Error::I2c(_))));
+    }
+
+    #[test]
+    fn decodes_touch_status() {
+        let mut mpr121 = Mpr121::new(MockI2c::new(), ADDRESS);
+        mpr121.init(&[]).unwrap();
+        assert_eq!(mpr121.touched().unwrap(), 0);
+
+        // The status of electrodes 0-7 is followed by that of electrodes 8-11. The
+        // upper bits report the proximity electrode and over-current.
+        mpr121.i2c.registers[0] = 0b1010_0101;
+        mpr121.i2c.registers[1] = 0b1001_1010;
+        assert_eq!(mpr121.touched().unwrap(), 0b1010_1010_0101);
+
+        mpr121.i2c.registers[0] = 0x00;
+        mpr121.i2c.registers[1] = 0xff;
+        assert_eq!(mpr121.touched().unwrap(), 0x0f00);
+    }
+
+    #[test]
+    fn rejects_invalid_electrodes() {
+        let mut mpr121 = Mpr121::new(MockI2c::new(), ADDRESS);
+        let res = mpr121.set_thresholds(ELECTRODES, Thresholds::default());
+        assert!(matches!(res, Err(Error::InvalidElectrode(12))));
+        assert!(mpr121.i2c.written.is_empty());
+    }
+}
@@ -4,17 +4,17 @@ use std::fmt;
 use std::sync::{Arc, Mutex};
 use tracing::{debug, info, trace};
 
-use hal::spidev::{SpiModeFlags, SpidevOptions};
-use hal::SpidevDevice;
-use linux_embedded_hal as hal;
+use linux_embedded_hal::SpidevDevice;
 use mfrc522::comm::blocking::spi::{DummyDelay, SpiInterface};
 use mfrc522::{self, Initialized, Mfrc522};
 
+use crate::components::spi;
+
 #[derive(Clone)]
 pub struct RfidController {
     pub mfrc522: Arc<Mutex<Mfrc522<SpiInterface<SpidevDevice, DummyDelay>, Initialized>>>,
 }
-#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
 pub struct Uid(String);
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct Tag {
@@ -27,28 +27,21 @@ impl fmt::Display for Uid {
     }
 }
 
-impl Default for Uid {
-    fn default() -> Self {
-        Self(Default::default())
-    }
-}
-
 impl Uid {
     pub fn from_bytes(bs: &[u8]) -> Uid {
-        return Uid(hex::encode(bs));
+        Uid(hex::encode(bs))
+    }
+
+    // Identifies tag mapper entries not backed by a physical tag, e.g. numbers entered on
+    // a keypad.
+    pub fn from_id(id: String) -> Uid {
+        Uid(id)
     }
 }
 
 impl RfidController {
     pub fn new() -> Result<Self> {
-        let mut spi =
-            SpidevDevice::open("/dev/spidev0.0").context("Opening SPI device /dev/spidev0.0")?;
-        let options = SpidevOptions::new()
-            .max_speed_hz(1_000_000)
-            .mode(SpiModeFlags::SPI_MODE_0)
-            .build();
-        spi.configure(&options).context("Configuring SPI device")?;
-
+        let spi = spi::open("/dev/spidev0.0")?;
         let itf = SpiInterface::new(spi);
         let mut mfrc522 = Mfrc522::new(itf)
             .init()
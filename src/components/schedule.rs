@@ -0,0 +1,221 @@
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::fmt;
+
+// Time windows restricting playback, configured in the main configuration as e.g.:
+//
+// schedule:
+//   - from: "20:00"
+//     until: "06:30"
+//     limit: no_playback
+//   - from: "18:00"
+//     until: "20:00"
+//     limit: !max_volume 40
+//
+// Windows may span midnight. If windows overlap, the first one listed applies.
+
+// Seconds since midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(try_from = "String")]
+pub struct TimeOfDay(u32);
+
+impl TimeOfDay {
+    pub fn from_seconds(seconds: u32) -> Self {
+        TimeOfDay(seconds % (24 * 60 * 60))
+    }
+}
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = anyhow::Error;
+
+    // Parses times like "06:30".
+    fn try_from(s: String) -> Result<Self> {
+        let (hours, minutes) = s
+            .split_once(':')
+            .filter(|(hours, minutes)| is_two_digits(hours) && is_two_digits(minutes))
+            .ok_or_else(|| anyhow!("expected time as HH:MM, got {:?}", s))?;
+        let hours: u32 = hours
+            .parse()
+            .with_context(|| format!("parsing hours of {:?}", s))?;
+        let minutes: u32 = minutes
+            .parse()
+            .with_context(|| format!("parsing minutes of {:?}", s))?;
+        if hours > 23 || minutes > 59 {
+            return Err(anyhow!("time out of range: {:?}", s));
+        }
+        Ok(TimeOfDay(hours * 3600 + minutes * 60))
+    }
+}
+
+fn is_two_digits(s: &str) -> bool {
+    s.len() == 2 && s.bytes().all(|b| b.is_ascii_digit())
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.0 / 3600, self.0 % 3600 / 60)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduleLimit {
+    // Only tags flagged as lullabies may be played.
+    NoPlayback,
+    // Volume level in percent.
+    MaxVolume(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct ScheduleWindow {
+    pub from: TimeOfDay,
+    pub until: TimeOfDay,
+    pub limit: ScheduleLimit,
+}
+
+impl ScheduleWindow {
+    pub fn contains(&self, time: TimeOfDay) -> bool {
+        if self.from <= self.until {
+            self.from <= time && time < self.until
+        } else {
+            self.from <= time || time < self.until
+        }
+    }
+}
+
+impl fmt::Display for ScheduleWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{} ", self.from, self.until)?;
+        match self.limit {
+            ScheduleLimit::NoPlayback => write!(f, "(no playback)"),
+            ScheduleLimit::MaxVolume(level) => write!(f, "(max. volume {}%)", level),
+        }
+    }
+}
+
+pub fn active_window(schedule: &[ScheduleWindow], time: TimeOfDay) -> Option<ScheduleWindow> {
+    schedule
+        .iter()
+        .find(|window| window.contains(time))
+        .copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(s: &str) -> TimeOfDay {
+        TimeOfDay::try_from(s.to_string()).unwrap()
+    }
+
+    fn window(from: &str, until: &str, limit: ScheduleLimit) -> ScheduleWindow {
+        ScheduleWindow {
+            from: time(from),
+            until: time(until),
+            limit,
+        }
+    }
+
+    #[test]
+    fn times_of_day_are_parsed() {
+        assert_eq!(time("00:00"), TimeOfDay::from_seconds(0));
+        assert_eq!(time("07:05"), TimeOfDay::from_seconds(7 * 3600 + 5 * 60));
+        assert_eq!(time("23:59"), TimeOfDay::from_seconds(24 * 3600 - 60));
+        assert_eq!(time("06:30").to_string(), "06:30");
+        assert_eq!(TimeOfDay::from_seconds(24 * 3600), time("00:00"));
+
+        for invalid in &[
+            "24:00", "23:60", "7:5", "7:05", "07:5", "0700", "07-00", "07:00:00", " 7:05", "+7:05",
+            "aa:bb", "",
+        ] {
+            assert!(
+                TimeOfDay::try_from(invalid.to_string()).is_err(),
+                "{:?} accepted",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn schedules_are_deserialized() {
+        let schedule: Vec<ScheduleWindow> = serde_yaml::from_str(
+            r#"
+- from: "19:00"
+  until: "07:00"
+  limit: no_playback
+- from: "18:00"
+  until: "20:00"
+  limit: !max_volume 40
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            schedule,
+            vec![
+                window("19:00", "07:00", ScheduleLimit::NoPlayback),
+                window("18:00", "20:00", ScheduleLimit::MaxVolume(40)),
+            ]
+        );
+
+        for invalid in &["24:00", "7:5"] {
+            let yaml = format!(
+                "from: \"{}\"\nuntil: \"07:00\"\nlimit: no_playback",
+                invalid
+            );
+            let err = serde_yaml::from_str::<ScheduleWindow>(&yaml).unwrap_err();
+            assert!(err.to_string().contains(invalid), "{}", err);
+        }
+    }
+
+    #[test]
+    fn windows_spanning_midnight() {
+        let night = window("19:00", "07:00", ScheduleLimit::NoPlayback);
+        let cases = [
+            ("18:59", false),
+            ("19:00", true),
+            ("23:59", true),
+            ("00:00", true),
+            ("06:59", true),
+            ("07:00", false),
+            ("12:00", false),
+        ];
+        for (t, expected) in cases.iter() {
+            assert_eq!(night.contains(time(t)), *expected, "{}", t);
+        }
+    }
+
+    #[test]
+    fn windows_within_a_day() {
+        let evening = window("18:00", "20:00", ScheduleLimit::MaxVolume(40));
+        let cases = [
+            ("17:59", false),
+            ("18:00", true),
+            ("19:59", true),
+            ("20:00", false),
+            ("00:00", false),
+        ];
+        for (t, expected) in cases.iter() {
+            assert_eq!(evening.contains(time(t)), *expected, "{}", t);
+        }
+        // A window ending when it starts is empty.
+        let empty = window("18:00", "18:00", ScheduleLimit::NoPlayback);
+        assert!(!empty.contains(time("18:00")));
+    }
+
+    #[test]
+    fn first_overlapping_window_applies() {
+        let night = window("19:00", "07:00", ScheduleLimit::NoPlayback);
+        let evening = window("18:00", "20:00", ScheduleLimit::MaxVolume(40));
+        let schedule = [night, evening];
+        assert_eq!(active_window(&schedule, time("17:00")), None);
+        assert_eq!(active_window(&schedule, time("18:30")), Some(evening));
+        assert_eq!(active_window(&schedule, time("19:30")), Some(night));
+        assert_eq!(active_window(&schedule, time("20:30")), Some(night));
+        assert_eq!(
+            active_window(&[evening, night], time("19:30")),
+            Some(evening)
+        );
+        assert_eq!(active_window(&[], time("19:30")), None);
+    }
+}
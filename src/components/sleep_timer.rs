@@ -0,0 +1,295 @@
+use std::time::{Duration, Instant};
+
+use crate::components::tag_mapper::SleepTimerSetting;
+
+// Playback is faded out over this duration before the sleep timer stops it.
+pub const FADE_OUT_DURATION: Duration = Duration::from_secs(60);
+// Volume is restored over this duration if the timer is disarmed while fading out.
+pub const FADE_IN_DURATION: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SleepTimerAction {
+    // Fade out over the given duration, which is the time left until the timer expires.
+    FadeOut(Duration),
+    Stop,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SleepTimer {
+    #[default]
+    Disarmed,
+    Deadline {
+        at: Instant,
+        fading: bool,
+    },
+    // Expires once the current track finishes. The end of the track is only known once
+    // the track has started and its duration could be determined.
+    EndOfTrack {
+        at: Option<Instant>,
+        fading: bool,
+    },
+}
+
+impl SleepTimer {
+    pub fn armed(setting: SleepTimerSetting, now: Instant) -> Self {
+        match setting {
+            SleepTimerSetting::Minutes(minutes) => SleepTimer::Deadline {
+                at: now + Duration::from_secs(minutes * 60),
+                fading: false,
+            },
+            SleepTimerSetting::EndOfTrack => SleepTimer::EndOfTrack {
+                at: None,
+                fading: false,
+            },
+        }
+    }
+
+    pub fn is_armed(&self) -> bool {
+        *self != SleepTimer::Disarmed
+    }
+
+    pub fn is_fading(&self) -> bool {
+        matches!(
+            self,
+            SleepTimer::Deadline { fading: true, .. } | SleepTimer::EndOfTrack { fading: true, .. }
+        )
+    }
+
+    // To be called whenever playback (re)starts, since that resets the volume.
+    pub fn playback_started(&mut self) {
+        match self {
+            SleepTimer::Deadline { fading, .. } | SleepTimer::EndOfTrack { fading, .. } => {
+                *fading = false
+            }
+            SleepTimer::Disarmed => {}
+        }
+    }
+
+    // `remaining` is the time left until the end of the track which has just started, if
+    // known.
+    pub fn track_started(&mut self, remaining: Option<Duration>, now: Instant) {
+        if let SleepTimer::EndOfTrack { at, .. } = self {
+            *at = remaining.map(|remaining| now + remaining);
+        }
+    }
+
+    pub fn track_finished(&mut self) -> Option<SleepTimerAction> {
+        match self {
+            SleepTimer::EndOfTrack { .. } => {
+                *self = SleepTimer::Disarmed;
+                Some(SleepTimerAction::Stop)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn tick(&mut self, now: Instant) -> Option<SleepTimerAction> {
+        let (at, fading) = match self {
+            SleepTimer::Deadline { at, fading } => (*at, fading),
+            SleepTimer::EndOfTrack {
+                at: Some(at),
+                fading,
+            } => (*at, fading),
+            _ => return None,
+        };
+        if now >= at {
+            *self = SleepTimer::Disarmed;
+            return Some(SleepTimerAction::Stop);
+        }
+        let remaining = at - now;
+        if !*fading && remaining <= FADE_OUT_DURATION {
+            *fading = true;
+            return Some(SleepTimerAction::FadeOut(remaining));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minutes(minutes: u64) -> Duration {
+        Duration::from_secs(minutes * 60)
+    }
+
+    // Ticks once per second from `from` until `until`, returning the actions along with
+    // the seconds elapsed since `from`.
+    fn run(timer: &mut SleepTimer, from: Instant, until: Duration) -> Vec<(u64, SleepTimerAction)> {
+        (0..=until.as_secs())
+            .filter_map(|secs| {
+                timer
+                    .tick(from + Duration::from_secs(secs))
+                    .map(|action| (secs, action))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn deadline_fades_out_during_the_last_minute() {
+        let start = Instant::now();
+        let mut timer = SleepTimer::armed(SleepTimerSetting::Minutes(5), start);
+        assert!(timer.is_armed());
+        assert!(!timer.is_fading());
+        assert_eq!(
+            run(&mut timer, start, minutes(10)),
+            vec![
+                (240, SleepTimerAction::FadeOut(FADE_OUT_DURATION)),
+                (300, SleepTimerAction::Stop),
+            ]
+        );
+        assert_eq!(timer, SleepTimer::Disarmed);
+    }
+
+    #[test]
+    fn fade_out_covers_the_remaining_time() {
+        let start = Instant::now();
+        let mut timer = SleepTimer::armed(SleepTimerSetting::Minutes(5), start);
+        // Ticks may be delayed, e.g. while the system is busy.
+        let late = start + minutes(5) - Duration::from_secs(20);
+        assert_eq!(
+            timer.tick(late),
+            Some(SleepTimerAction::FadeOut(Duration::from_secs(20)))
+        );
+        assert!(timer.is_fading());
+        assert_eq!(timer.tick(late + Duration::from_secs(10)), None);
+        assert_eq!(
+            timer.tick(late + Duration::from_secs(30)),
+            Some(SleepTimerAction::Stop)
+        );
+    }
+
+    #[test]
+    fn short_timers_fade_out_right_away() {
+        let start = Instant::now();
+        let mut timer = SleepTimer::armed(SleepTimerSetting::Minutes(0), start);
+        assert_eq!(timer.tick(start), Some(SleepTimerAction::Stop));
+
+        let mut timer = SleepTimer::Deadline {
+            at: start + Duration::from_secs(30),
+            fading: false,
+        };
+        assert_eq!(
+            timer.tick(start),
+            Some(SleepTimerAction::FadeOut(Duration::from_secs(30)))
+        );
+    }
+
+    #[test]
+    fn restarted_playback_fades_out_again() {
+        let start = Instant::now();
+        let mut timer = SleepTimer::armed(SleepTimerSetting::Minutes(5), start);
+        let fading = start + minutes(4) + Duration::from_secs(30);
+        assert_eq!(
+            timer.tick(fading),
+            Some(SleepTimerAction::FadeOut(Duration::from_secs(30)))
+        );
+        // E.g. paused and continued, which restores the volume.
+        timer.playback_started();
+        assert!(!timer.is_fading());
+        assert_eq!(
+            timer.tick(fading + Duration::from_secs(10)),
+            Some(SleepTimerAction::FadeOut(Duration::from_secs(20)))
+        );
+    }
+
+    #[test]
+    fn cancelling_and_rearming() {
+        let start = Instant::now();
+        let mut timer = SleepTimer::armed(SleepTimerSetting::Minutes(5), start);
+        assert_eq!(
+            timer.tick(start + minutes(4)),
+            Some(SleepTimerAction::FadeOut(FADE_OUT_DURATION))
+        );
+
+        // Re-arming starts over, fading in has to be taken care of by the caller.
+        let rearmed = start + minutes(4) + Duration::from_secs(30);
+        timer = SleepTimer::armed(SleepTimerSetting::Minutes(5), rearmed);
+        assert!(!timer.is_fading());
+        assert_eq!(
+            run(&mut timer, rearmed, minutes(10)),
+            vec![
+                (240, SleepTimerAction::FadeOut(FADE_OUT_DURATION)),
+                (300, SleepTimerAction::Stop),
+            ]
+        );
+
+        let mut timer = SleepTimer::armed(SleepTimerSetting::Minutes(5), start);
+        assert!(timer.is_armed());
+        timer = SleepTimer::Disarmed;
+        assert!(!timer.is_armed());
+        assert!(run(&mut timer, start, minutes(10)).is_empty());
+        assert_eq!(timer.track_finished(), None);
+    }
+
+    #[test]
+    fn deadline_outlasts_finished_tracks() {
+        let start = Instant::now();
+        let mut timer = SleepTimer::armed(SleepTimerSetting::Minutes(5), start);
+        timer.track_started(Some(minutes(1)), start);
+        assert_eq!(timer.track_finished(), None);
+        timer.track_started(Some(minutes(10)), start + minutes(1));
+        assert_eq!(
+            run(&mut timer, start, minutes(10)),
+            vec![
+                (240, SleepTimerAction::FadeOut(FADE_OUT_DURATION)),
+                (300, SleepTimerAction::Stop),
+            ]
+        );
+    }
+
+    #[test]
+    fn end_of_track_waits_for_the_track_to_start() {
+        let start = Instant::now();
+        let mut timer = SleepTimer::armed(SleepTimerSetting::EndOfTrack, start);
+        assert!(timer.is_armed());
+        // The end of the track is not known yet.
+        assert!(run(&mut timer, start, minutes(10)).is_empty());
+
+        timer.track_started(Some(minutes(3)), start);
+        assert_eq!(
+            run(&mut timer, start, minutes(2)),
+            vec![(120, SleepTimerAction::FadeOut(FADE_OUT_DURATION))]
+        );
+        assert_eq!(timer.track_finished(), Some(SleepTimerAction::Stop));
+        assert_eq!(timer, SleepTimer::Disarmed);
+    }
+
+    #[test]
+    fn end_of_track_stops_when_the_track_ends_early() {
+        let start = Instant::now();
+        let mut timer = SleepTimer::armed(SleepTimerSetting::EndOfTrack, start);
+        timer.track_started(Some(minutes(3)), start);
+        assert_eq!(timer.tick(start + minutes(1)), None);
+        // E.g. the duration of the track was estimated wrongly.
+        assert_eq!(timer.track_finished(), Some(SleepTimerAction::Stop));
+        assert!(run(&mut timer, start, minutes(5)).is_empty());
+    }
+
+    #[test]
+    fn end_of_track_with_unknown_duration() {
+        let start = Instant::now();
+        let mut timer = SleepTimer::armed(SleepTimerSetting::EndOfTrack, start);
+        timer.track_started(None, start);
+        assert!(run(&mut timer, start, minutes(10)).is_empty());
+        assert_eq!(timer.track_finished(), Some(SleepTimerAction::Stop));
+    }
+
+    #[test]
+    fn end_of_track_follows_the_current_track() {
+        let start = Instant::now();
+        let mut timer = SleepTimer::armed(SleepTimerSetting::EndOfTrack, start);
+        timer.track_started(Some(minutes(3)), start);
+        // Skipped to a shorter track, which fades out right away.
+        let skipped = start + minutes(1);
+        timer.track_started(Some(Duration::from_secs(45)), skipped);
+        assert_eq!(
+            timer.tick(skipped),
+            Some(SleepTimerAction::FadeOut(Duration::from_secs(45)))
+        );
+        assert_eq!(
+            timer.tick(skipped + Duration::from_secs(45)),
+            Some(SleepTimerAction::Stop)
+        );
+    }
+}
@@ -0,0 +1,19 @@
+use anyhow::{Context, Result};
+
+use hal::spidev::{SpiModeFlags, SpidevOptions};
+use hal::SpidevDevice;
+use linux_embedded_hal as hal;
+
+// Opens an SPI device in mode 0 at 1 MHz, which suits both the MFRC522 and the
+// MCP3008/MCP3208 ADCs.
+pub fn open(path: &str) -> Result<SpidevDevice> {
+    let mut spi =
+        SpidevDevice::open(path).with_context(|| format!("Opening SPI device {}", path))?;
+    let options = SpidevOptions::new()
+        .max_speed_hz(1_000_000)
+        .mode(SpiModeFlags::SPI_MODE_0)
+        .build();
+    spi.configure(&options)
+        .with_context(|| format!("Configuring SPI device {}", path))?;
+    Ok(spi)
+}
@@ -3,19 +3,107 @@ use serde::Deserialize;
 use std::collections::HashMap;
 use std::fs;
 use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
 use tracing::{debug, info, warn};
 
+use crate::effects::replay_gain::Decibels;
+
 type TagID = String;
 
 #[derive(Default, Debug, Deserialize, Clone, Eq, PartialEq)]
 pub struct TagConf {
+    // Filled in from the mapping key when loading the configuration.
+    #[serde(skip)]
+    pub tag_id: TagID,
+    #[serde(default)]
     pub uris: Vec<String>,
+    // Without a resume policy, playback is only resumed from the in-memory pause state
+    // of the last played tag and no bookmarks are recorded.
+    #[serde(default)]
+    pub resume: Option<ResumePolicy>,
+    #[serde(default)]
+    pub shuffle: bool,
+    #[serde(default)]
+    pub repeat: Repeat,
+    // Armed when playback of the tag starts. A tag without URIs arms the sleep timer for
+    // the current playback instead.
+    #[serde(default)]
+    pub sleep_timer: Option<SleepTimerSetting>,
+    // Overrides the globally configured crossfade between tracks, e.g. zero for live
+    // albums.
+    #[serde(default)]
+    pub crossfade_ms: Option<u64>,
+    // Gain in dB applied instead of the ReplayGain of the files, for odd recordings.
+    #[serde(default)]
+    pub gain: Option<Decibels>,
+    // Lullabies may be played during quiet hours which disallow playback otherwise.
+    #[serde(default)]
+    pub lullaby: bool,
+    // Granted on top of the daily listening time. A tag without URIs granting extra time
+    // serves as admin card.
+    #[serde(default)]
+    pub extra_listening_minutes: Option<u64>,
 }
 
 impl TagConf {
     pub fn is_empty(&self) -> bool {
         self.uris.is_empty()
     }
+
+    // Returns true if bookmarks need to be recorded for the tag, i.e. if its resume
+    // policy may ever continue at one.
+    pub fn keeps_bookmarks(&self) -> bool {
+        matches!(self.resume, Some(policy) if policy != ResumePolicy::Never)
+    }
+
+    // Returns true for tags which control the sleep timer instead of starting playback.
+    pub fn is_sleep_timer_tag(&self) -> bool {
+        self.uris.is_empty() && self.sleep_timer.is_some()
+    }
+
+    // Returns true for admin cards granting extra listening time.
+    pub fn is_listening_time_tag(&self) -> bool {
+        self.uris.is_empty() && self.extra_listening_minutes.is_some()
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ResumePolicy {
+    Never,
+    Always,
+    WithinHours(u64),
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Repeat {
+    #[default]
+    None,
+    One,
+    All,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SleepTimerSetting {
+    Minutes(u64),
+    EndOfTrack,
+}
+
+impl ResumePolicy {
+    // Returns true if playback paused at `paused_at` should be resumed at `now`.
+    pub fn allows_resume(&self, paused_at: SystemTime, now: SystemTime) -> bool {
+        match self {
+            ResumePolicy::Never => false,
+            ResumePolicy::Always => true,
+            ResumePolicy::WithinHours(hours) => match now.duration_since(paused_at) {
+                Ok(elapsed) => elapsed < Duration::from_secs(hours * 60 * 60),
+                // Clock went backwards, the bookmark is from the "future".
+                Err(_) => true,
+            },
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -52,6 +140,28 @@ impl TagMapperConfiguration {
 //     uris:
 //       - foo.ogg
 //       - bar.ogg
+//       - albums/some-album/
+//       - audiobooks/some-book/*.mp3
+//       - playlists/favourites.m3u
+//       - https://radio.example.org/stream.mp3
+//     resume: !within_hours 12
+//     shuffle: false
+//     repeat: all
+//     sleep_timer: !minutes 30
+//   67890:
+//     sleep_timer: end_of_track
+//
+// URIs naming a directory or containing a glob pattern are expanded at play time
+// into the naturally sorted list of supported audio files. URIs naming an M3U, M3U8
+// or PLS playlist are expanded into the files listed in the playlist. HTTP(S) URIs are
+// streamed, which includes endless Icecast/Shoutcast streams. Files outside of the
+// audio base directory are skipped. This is checked on the path alone, symlinks within
+// the base directory are trusted and may point anywhere.
+// Supported resume policies: `never`, `always` and `!within_hours N`.
+// Supported repeat modes: `none` (default), `one` and `all`.
+// Supported sleep timer settings: `!minutes N` and `end_of_track`. A tag with a sleep
+// timer setting but without URIs (like 67890 above) arms the sleep timer for whatever
+// is currently playing.
 //
 
 impl TagMapper {
@@ -70,12 +180,16 @@ impl TagMapper {
             }
         };
 
-        let conf: TagMapperConfiguration = serde_yaml::from_str(&content).with_context(|| {
-            format!(
-                "YAML unmarshalling tag_mapper configuration at {}",
-                self.file
-            )
-        })?;
+        let mut conf: TagMapperConfiguration =
+            serde_yaml::from_str(&content).with_context(|| {
+                format!(
+                    "YAML unmarshalling tag_mapper configuration at {}",
+                    self.file
+                )
+            })?;
+        for (tag_id, tag_conf) in conf.mappings.iter_mut() {
+            tag_conf.tag_id = tag_id.clone();
+        }
         let mut w = self.conf.write().unwrap();
         *w = conf;
         Ok(())
@@ -88,15 +202,17 @@ impl TagMapper {
 
     fn new(filename: &str) -> Self {
         let empty_conf = Arc::new(RwLock::new(TagMapperConfiguration::new()));
-        let tag_mapper = TagMapper {
+        TagMapper {
             file: filename.to_string(),
             conf: empty_conf,
-        };
-        tag_mapper
+        }
     }
 
     pub fn new_initialized(filename: &str) -> Result<TagMapperHandle> {
-        info!("Initializing tag mapper, using tag mapper configuration file {}", filename);
+        info!(
+            "Initializing tag mapper, using tag mapper configuration file {}",
+            filename
+        );
         let mut tag_mapper = Self::new(filename);
         tag_mapper.refresh()?;
         let handle = tag_mapper.handle();
@@ -111,9 +227,18 @@ impl TagMapper {
 }
 
 impl TagMapperHandle {
+    // Returns a handle to the given mappings, which are never reloaded.
+    pub fn from_mappings(mut mappings: HashMap<TagID, TagConf>) -> Self {
+        for (tag_id, tag_conf) in mappings.iter_mut() {
+            tag_conf.tag_id = tag_id.clone();
+        }
+        let conf = Arc::new(RwLock::new(TagMapperConfiguration { mappings }));
+        TagMapperHandle { conf }
+    }
+
     pub fn lookup(&self, tag_id: &TagID) -> Option<TagConf> {
         let r = self.conf.read().unwrap();
-        return r.mappings.get(tag_id).cloned();
+        r.mappings.get(tag_id).cloned()
     }
 
     pub fn debug_dump(&self) {
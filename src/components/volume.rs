@@ -0,0 +1,260 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tracing::{debug, error, info, warn};
+
+use crate::components::json_file;
+use crate::model::config::Config;
+
+// Volume levels are percentages of the full output volume of the audio pipeline.
+pub const FULL_VOLUME: u32 = 100;
+
+// The current level is stored as JSON object:
+//
+// {"level": 40}
+//
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct PersistedVolume {
+    level: u32,
+}
+
+// Changes of the level are persisted by `persist`, which is called periodically, so
+// that turning a knob does not write to the SD card for every step.
+#[derive(Debug)]
+pub struct Volume {
+    file: Option<PathBuf>,
+    level: u32,
+    dirty: bool,
+}
+
+impl Volume {
+    fn load(file: &Path) -> Result<Option<u32>> {
+        let persisted: Option<PersistedVolume> = json_file::load(file, "volume level")?;
+        Ok(persisted.map(|persisted| persisted.level))
+    }
+
+    fn store(file: &Path, level: u32) -> Result<()> {
+        json_file::store(file, &PersistedVolume { level }, "volume level")
+    }
+
+    // Persists the level if it has changed since it was last persisted.
+    pub fn persist(&mut self) {
+        if !self.dirty {
+            return;
+        }
+        if let Some(ref file) = self.file {
+            if let Err(err) = Self::store(file, self.level) {
+                error!("Failed to persist volume level: {:#}", err);
+                return;
+            }
+        }
+        self.dirty = false;
+    }
+
+    // The configured startup volume takes precedence over the persisted level.
+    pub fn new(config: &Config) -> Result<Self> {
+        let file = config.volume_file.as_ref().map(PathBuf::from);
+        let persisted = match file {
+            Some(ref file) => {
+                info!("Using volume file {}", file.display());
+                Self::load(file)?
+            }
+            None => {
+                warn!("No volume file configured, the volume level will not survive restarts");
+                None
+            }
+        };
+        let level = config
+            .startup_volume
+            .or(persisted)
+            .unwrap_or(FULL_VOLUME)
+            .min(config.max_volume)
+            .min(FULL_VOLUME);
+        info!("Starting with volume level {}%", level);
+        Ok(Volume {
+            file,
+            level,
+            dirty: false,
+        })
+    }
+
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+
+    // The level to output, limited to the configured maximum, which may have been lowered
+    // since the level was set, and the given cap.
+    pub fn output_level(&self, config: &Config, cap: Option<u32>) -> u32 {
+        self.level
+            .min(config.max_volume)
+            .min(cap.unwrap_or(FULL_VOLUME))
+    }
+
+    // Sets the volume level, limited to the configured maximum and the given cap. Returns
+    // the level actually set.
+    pub fn set(&mut self, config: &Config, level: u32, cap: Option<u32>) -> u32 {
+        let level = level
+            .min(config.max_volume)
+            .min(cap.unwrap_or(FULL_VOLUME))
+            .min(FULL_VOLUME);
+        if level != self.level {
+            debug!("Changing volume level from {}% to {}%", self.level, level);
+            self.level = level;
+            self.dirty = true;
+        }
+        self.level
+    }
+
+    // A level above the cap is kept, so that it is restored once the cap is lifted.
+    pub fn up(&mut self, config: &Config, cap: Option<u32>) -> u32 {
+        if matches!(cap, Some(cap) if self.level >= cap) {
+            return self.level;
+        }
+        let level = self.level.saturating_add(config.volume_step);
+        self.set(config, level, cap)
+    }
+
+    pub fn down(&mut self, config: &Config, cap: Option<u32>) -> u32 {
+        // Step down from the maximum, in case that has been lowered below the current level.
+        let level = self
+            .level
+            .min(config.max_volume)
+            .min(cap.unwrap_or(FULL_VOLUME))
+            .saturating_sub(config.volume_step);
+        self.set(config, level, cap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn max_volume(max_volume: u32) -> Config {
+        Config {
+            max_volume,
+            volume_step: 10,
+            ..Config::default()
+        }
+    }
+
+    fn at_level(level: u32) -> Volume {
+        Volume {
+            file: None,
+            level,
+            dirty: false,
+        }
+    }
+
+    fn scratch_file(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rustberry-volume-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join(name);
+        let _ = std::fs::remove_file(&file);
+        file
+    }
+
+    #[test]
+    fn level_never_exceeds_max_volume() {
+        let config = max_volume(60);
+        let mut volume = at_level(50);
+        assert_eq!(volume.up(&config, None), 60);
+        assert_eq!(volume.up(&config, None), 60);
+        assert_eq!(volume.set(&config, 80, None), 60);
+        assert_eq!(volume.set(&config, 200, None), 60);
+        assert_eq!(volume.output_level(&config, None), 60);
+        assert_eq!(volume.down(&config, None), 50);
+        assert_eq!(volume.set(&config, 0, None), 0);
+        assert_eq!(volume.down(&config, None), 0);
+
+        let mut volume = at_level(95);
+        assert_eq!(volume.up(&Config::default(), None), FULL_VOLUME);
+        assert_eq!(volume.set(&Config::default(), 200, None), FULL_VOLUME);
+    }
+
+    #[test]
+    fn level_never_exceeds_cap() {
+        let config = max_volume(80);
+        let mut volume = at_level(30);
+        assert_eq!(volume.up(&config, Some(40)), 40);
+        assert_eq!(volume.up(&config, Some(40)), 40);
+        assert_eq!(volume.set(&config, 70, Some(40)), 40);
+        assert_eq!(volume.output_level(&config, Some(40)), 40);
+
+        // Levels set without a cap are kept, but not output above it.
+        assert_eq!(volume.set(&config, 70, None), 70);
+        assert_eq!(volume.output_level(&config, Some(40)), 40);
+        assert_eq!(volume.up(&config, Some(40)), 70);
+        assert_eq!(volume.output_level(&config, None), 70);
+        assert_eq!(volume.down(&config, Some(40)), 30);
+    }
+
+    #[test]
+    fn lowered_max_volume_is_applied() {
+        let mut volume = at_level(90);
+        let config = max_volume(50);
+        assert_eq!(volume.level(), 90);
+        assert_eq!(volume.output_level(&config, None), 50);
+        assert_eq!(volume.output_level(&config, Some(30)), 30);
+        assert_eq!(volume.down(&config, None), 40);
+        let mut volume = at_level(90);
+        assert_eq!(volume.up(&config, None), 50);
+    }
+
+    #[test]
+    fn startup_volume_overrides_persisted_level() {
+        let file = scratch_file("startup.json");
+        Volume::store(&file, 70).unwrap();
+        let config = Config {
+            volume_file: Some(file.to_string_lossy().to_string()),
+            ..max_volume(80)
+        };
+        assert_eq!(Volume::new(&config).unwrap().level(), 70);
+        let startup = Config {
+            startup_volume: Some(30),
+            ..config.clone()
+        };
+        assert_eq!(Volume::new(&startup).unwrap().level(), 30);
+        let lowered = Config {
+            max_volume: 50,
+            ..config.clone()
+        };
+        assert_eq!(Volume::new(&lowered).unwrap().level(), 50);
+        let too_loud = Config {
+            startup_volume: Some(90),
+            ..config
+        };
+        assert_eq!(Volume::new(&too_loud).unwrap().level(), 80);
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(
+            Volume::new(&Config::default()).unwrap().level(),
+            FULL_VOLUME
+        );
+    }
+
+    #[test]
+    fn changes_are_persisted_on_demand() {
+        let file = scratch_file("persist.json");
+        let config = Config {
+            volume_file: Some(file.to_string_lossy().to_string()),
+            ..max_volume(100)
+        };
+        let mut volume = Volume::new(&config).unwrap();
+        for level in 0..50 {
+            volume.set(&config, level, None);
+        }
+        assert_eq!(Volume::load(&file).unwrap(), None);
+        volume.persist();
+        assert_eq!(Volume::load(&file).unwrap(), Some(49));
+
+        // Unchanged levels are not written again.
+        std::fs::remove_file(&file).unwrap();
+        volume.set(&config, 49, None);
+        volume.persist();
+        assert_eq!(Volume::load(&file).unwrap(), None);
+        volume.set(&config, 20, None);
+        volume.persist();
+        assert_eq!(Volume::new(&config).unwrap().level(), 20);
+        std::fs::remove_file(&file).unwrap();
+    }
+}
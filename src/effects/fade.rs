@@ -0,0 +1,364 @@
+use rodio::source::SeekError;
+use rodio::{Sample, Source};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Number of samples after which a fading source picks up changes to its control.
+const SYNC_INTERVAL: usize = 256;
+
+/// What a source does once it has been faded out to silence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AfterFade {
+    // Keep playing silently.
+    Continue,
+    // Emit silence without advancing the underlying source, i.e. pause.
+    Hold,
+    // End the source, letting the sink proceed with the next one.
+    End,
+}
+
+#[derive(Debug)]
+struct Shared {
+    gain: f32,
+    target: f32,
+    duration: Duration,
+    // Change of gain per second. Determined by the playing source once it picks up a new
+    // target, since only that source knows the current gain.
+    speed: Option<f32>,
+    after: AfterFade,
+}
+
+// Controls the gain of all sources wrapped by it. Only one of them is expected to play
+// at any time, the gain is handed over from one source to the next.
+#[derive(Debug, Clone)]
+pub struct FadeControl {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl FadeControl {
+    pub fn new(gain: f32) -> Self {
+        FadeControl {
+            shared: Arc::new(Mutex::new(Shared {
+                gain,
+                target: gain,
+                duration: Duration::ZERO,
+                speed: None,
+                after: AfterFade::Continue,
+            })),
+        }
+    }
+
+    // Ramps the gain linearly to `target` over `duration`. The ramp proceeds only while
+    // samples are being played.
+    pub fn fade_to(&self, target: f32, duration: Duration, after: AfterFade) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.target = target;
+        shared.duration = duration;
+        shared.speed = None;
+        shared.after = after;
+    }
+
+    pub fn wrap<S>(&self, source: S) -> Faded<S>
+    where
+        S: Source,
+        S::Item: Sample,
+    {
+        Faded {
+            inner: source,
+            control: self.clone(),
+            started: false,
+            gain: 0.0,
+            target: 0.0,
+            step: 0.0,
+            after: AfterFade::Continue,
+            until_sync: 0,
+            frame_pos: 0,
+            holding: false,
+        }
+    }
+}
+
+// Silent until faded in.
+impl Default for FadeControl {
+    fn default() -> Self {
+        Self::new(0.0)
+    }
+}
+
+pub struct Faded<S> {
+    inner: S,
+    control: FadeControl,
+    started: bool,
+    gain: f32,
+    target: f32,
+    // Change of gain per sample.
+    step: f32,
+    after: AfterFade,
+    until_sync: usize,
+    // Position within the current frame of interleaved samples. Silence is only entered
+    // and left on frame boundaries, otherwise the channels would end up swapped.
+    frame_pos: u16,
+    holding: bool,
+}
+
+impl<S> Faded<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    fn sync(&mut self) {
+        let mut shared = self.control.shared.lock().unwrap();
+        if self.started {
+            shared.gain = self.gain;
+        } else {
+            self.gain = shared.gain;
+            self.started = true;
+        }
+        self.target = shared.target;
+        self.after = shared.after;
+        let speed = match shared.speed {
+            Some(speed) => speed,
+            None => {
+                let speed = if shared.duration.is_zero() {
+                    f32::INFINITY
+                } else {
+                    (shared.target - self.gain).abs() / shared.duration.as_secs_f32()
+                };
+                shared.speed = Some(speed);
+                speed
+            }
+        };
+        let samples_per_sec = self.inner.sample_rate() as f32 * self.inner.channels() as f32;
+        self.step = speed / samples_per_sec;
+    }
+}
+
+impl<S> Iterator for Faded<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    type Item = S::Item;
+
+    fn next(&mut self) -> Option<S::Item> {
+        if self.frame_pos == 0 {
+            if self.until_sync == 0 {
+                self.sync();
+                self.until_sync = SYNC_INTERVAL;
+            }
+            self.holding = false;
+            if self.gain <= 0.0 && self.target <= 0.0 {
+                match self.after {
+                    AfterFade::Continue => {}
+                    AfterFade::Hold => self.holding = true,
+                    AfterFade::End => {
+                        self.control.shared.lock().unwrap().gain = 0.0;
+                        return None;
+                    }
+                }
+            }
+        }
+        self.until_sync = self.until_sync.saturating_sub(1);
+        self.frame_pos += 1;
+        if self.frame_pos >= self.inner.channels() {
+            self.frame_pos = 0;
+        }
+        if self.holding {
+            return Some(S::Item::zero_value());
+        }
+
+        let sample = match self.inner.next() {
+            Some(sample) => sample,
+            None => {
+                // Hand over the gain to the next source.
+                self.control.shared.lock().unwrap().gain = self.gain;
+                return None;
+            }
+        };
+        if self.gain < self.target {
+            self.gain = (self.gain + self.step).min(self.target);
+        } else if self.gain > self.target {
+            self.gain = (self.gain - self.step).max(self.target);
+        }
+        Some(sample.amplify(self.gain))
+    }
+}
+
+impl<S> Source for Faded<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const RATE: u32 = 1000;
+
+    fn constant(channels: u16, len: usize) -> SamplesBuffer<f32> {
+        SamplesBuffer::new(channels, RATE, vec![1.0; len])
+    }
+
+    // Yields 1, 2, 3, ... and counts the samples taken from it.
+    struct Counting {
+        channels: u16,
+        taken: Arc<AtomicUsize>,
+    }
+
+    impl Iterator for Counting {
+        type Item = f32;
+
+        fn next(&mut self) -> Option<f32> {
+            Some(self.taken.fetch_add(1, Ordering::SeqCst) as f32 + 1.0)
+        }
+    }
+
+    impl Source for Counting {
+        fn current_frame_len(&self) -> Option<usize> {
+            None
+        }
+
+        fn channels(&self) -> u16 {
+            self.channels
+        }
+
+        fn sample_rate(&self) -> u32 {
+            RATE
+        }
+
+        fn total_duration(&self) -> Option<Duration> {
+            None
+        }
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-4,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn gain_is_ramped_linearly() {
+        let control = FadeControl::new(0.0);
+        control.fade_to(1.0, Duration::from_millis(100), AfterFade::Continue);
+        let samples: Vec<f32> = control.wrap(constant(1, 1000)).collect();
+        assert_eq!(samples.len(), 1000);
+        assert_close(samples[0], 0.01);
+        assert_close(samples[49], 0.5);
+        assert_close(samples[99], 1.0);
+        assert!(samples[100..].iter().all(|sample| *sample == 1.0));
+    }
+
+    #[test]
+    fn stereo_ramps_take_the_same_time() {
+        let control = FadeControl::new(1.0);
+        control.fade_to(0.5, Duration::from_millis(100), AfterFade::Continue);
+        let samples: Vec<f32> = control.wrap(constant(2, 1000)).collect();
+        assert_close(samples[0], 0.9975);
+        assert_close(samples[199], 0.5);
+        assert!(samples[200..].iter().all(|sample| *sample == 0.5));
+    }
+
+    #[test]
+    fn zero_duration_applies_target_right_away() {
+        let control = FadeControl::new(0.0);
+        control.fade_to(0.8, Duration::ZERO, AfterFade::Continue);
+        let samples: Vec<f32> = control.wrap(constant(1, 10)).collect();
+        assert_eq!(samples, vec![0.8; 10]);
+    }
+
+    #[test]
+    fn continue_plays_silently() {
+        let control = FadeControl::new(1.0);
+        control.fade_to(0.0, Duration::from_millis(100), AfterFade::Continue);
+        let samples: Vec<f32> = control.wrap(constant(1, 1000)).collect();
+        assert_eq!(samples.len(), 1000);
+        assert_close(samples[0], 0.99);
+        assert!(samples[101..].iter().all(|sample| *sample == 0.0));
+    }
+
+    #[test]
+    fn end_terminates_once_silent() {
+        let control = FadeControl::new(1.0);
+        control.fade_to(0.0, Duration::from_millis(100), AfterFade::End);
+        let mut faded = control.wrap(constant(1, 1000));
+        let samples: Vec<f32> = faded.by_ref().collect();
+        assert!((100..=101).contains(&samples.len()), "{}", samples.len());
+        assert_close(samples[0], 0.99);
+        assert_eq!(faded.next(), None);
+
+        // The next source does not start before being faded in.
+        assert_eq!(control.wrap(constant(1, 10)).next(), None);
+        control.fade_to(1.0, Duration::ZERO, AfterFade::Continue);
+        assert_eq!(control.wrap(constant(1, 10)).next(), Some(1.0));
+    }
+
+    #[test]
+    fn hold_does_not_advance_the_source() {
+        for channels in [1, 2].iter().copied() {
+            let taken = Arc::new(AtomicUsize::new(0));
+            let control = FadeControl::new(1.0);
+            control.fade_to(0.0, Duration::from_millis(100), AfterFade::Hold);
+            let mut faded = control.wrap(Counting {
+                channels,
+                taken: taken.clone(),
+            });
+            for _ in 0..10_000 {
+                faded.next().unwrap();
+            }
+            let held_at = taken.load(Ordering::SeqCst);
+            let frames = 100 * channels as usize;
+            assert!(
+                (frames..=frames + channels as usize).contains(&held_at),
+                "{}",
+                held_at
+            );
+            // Silence starts on a frame boundary.
+            assert_eq!(held_at % channels as usize, 0);
+            assert_eq!(faded.next(), Some(0.0));
+            assert_eq!(taken.load(Ordering::SeqCst), held_at);
+
+            // Resumed playback continues where it held.
+            control.fade_to(1.0, Duration::ZERO, AfterFade::Continue);
+            let resumed = faded.find(|sample| *sample != 0.0).unwrap();
+            assert_eq!(resumed, held_at as f32 + 1.0);
+        }
+    }
+
+    #[test]
+    fn gain_is_handed_over_to_the_next_source() {
+        let control = FadeControl::new(0.0);
+        control.fade_to(1.0, Duration::from_secs(1), AfterFade::Continue);
+        let first: Vec<f32> = control.wrap(constant(1, 500)).collect();
+        assert_close(*first.last().unwrap(), 0.5);
+        let second: Vec<f32> = control.wrap(constant(1, 1000)).collect();
+        assert_close(second[0], 0.501);
+        assert_close(second[499], 1.0);
+    }
+}
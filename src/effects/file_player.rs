@@ -1,54 +1,613 @@
 use anyhow::{anyhow, Context, Result};
 use cpal::traits::HostTrait;
-use rodio::{Device, DeviceTrait, OutputStream, OutputStreamHandle, Sink};
+use crossbeam_channel::{Receiver, Sender};
+use rodio::source::EmptyCallback;
+use rodio::{Device, DeviceTrait, OutputStream, OutputStreamHandle, Sink, Source};
 use std::convert::From;
 use std::fs::File;
 use std::io::BufReader;
-use std::sync::Arc;
-use tracing::{debug, info, warn};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
 
 use std::path::{Path, PathBuf};
+use url::Url;
 
 use crate::components::config::ConfigLoaderHandle;
+use crate::components::tag_mapper::TagConf;
+use crate::components::volume;
+use crate::effects::fade::{AfterFade, FadeControl};
+use crate::effects::http_stream::{self, HttpStream, StreamMetadata};
+use crate::effects::playlist::{Playlist, ShuffleSeeds};
+use crate::effects::replay_gain::{Normalization, ReplayGain};
+use crate::effects::track_list::{self, Track};
+use crate::effects::track_source::{BoxedSource, Decoded, Handover, TrackSource};
+use crate::effects::{PlaybackEvent, PlaybackEventKind, PlaybackPosition};
+
+const FULL_VOLUME: f32 = 1.0;
+// Decoded when a track is queued, well before playback reaches it.
+const PREFETCH_DURATION: Duration = Duration::from_secs(1);
+
+// Emitted from within the audio thread, consumed by the queue feeder.
+#[derive(Debug, Clone)]
+enum QueueEvent {
+    TrackStarted {
+        generation: u64,
+        track: usize,
+        offset: Duration,
+        duration: Option<Duration>,
+    },
+    TrackFinished {
+        generation: u64,
+        track: usize,
+    },
+    QueueExhausted {
+        generation: u64,
+    },
+    StreamMetadata {
+        generation: u64,
+        metadata: StreamMetadata,
+    },
+}
+
+// The sink only ever contains the current track and the track following it. The
+// remaining tracks are appended by the queue feeder once their predecessor starts playing.
+#[derive(Debug, Default)]
+struct Queue {
+    playlist: Playlist,
+    // Incremented whenever the sink is refilled, used for discarding stale events.
+    generation: u64,
+    // Number of playback requests received, used for tagging playback events.
+    request: u64,
+    current_track: Option<usize>,
+    current_duration: Option<Duration>,
+    // Controls the gain of the sources of the current generation.
+    fade: FadeControl,
+    crossfade: Duration,
+    normalization: Normalization,
+    // Hands the next track to be queued over to the last track in the sink.
+    handover: Option<Handover>,
+}
+
+impl Queue {
+    fn emit(&self, playback_tx: &Sender<PlaybackEvent>, kind: PlaybackEventKind) {
+        let event = PlaybackEvent {
+            request: self.request,
+            kind,
+        };
+        if let Err(err) = playback_tx.send(event) {
+            error!("Failed to send playback event: {}", err);
+        }
+    }
+}
 
 pub struct FilePlayer {
     base_dir: PathBuf,
+    config: ConfigLoaderHandle,
     pub sink: Arc<Sink>,
-    file_path: Option<PathBuf>,
-    output_stream: OutputStream,
-    output_stream_handle: OutputStreamHandle,
+    queue: Arc<Mutex<Queue>>,
+    replay_gain: ReplayGain,
+    shuffle_seeds: ShuffleSeeds,
+    events_tx: Sender<QueueEvent>,
+    playback_tx: Sender<PlaybackEvent>,
+    _output_stream: OutputStream,
+    _output_stream_handle: OutputStreamHandle,
 }
 
-// const FROM_BEGINNING: Duration = Duration::from_secs(0);
-
 impl FilePlayer {
-    pub fn queue(&self) -> Result<()> {
-        debug!("FilePlayer: queue");
-        let path = if let Some(ref file_path) = self.file_path {
-            file_path.clone()
-        } else {
-            warn!("cannot queue without file name");
-            return Ok(());
+    fn seek_source<S>(mut source: S, track: &Track, offset: Duration) -> BoxedSource
+    where
+        S: Source<Item = i16> + Send + 'static,
+    {
+        if offset == Duration::from_secs(0) {
+            return Box::new(source);
+        }
+        match source.try_seek(offset) {
+            Ok(()) => Box::new(source),
+            Err(err) => {
+                warn!(
+                    "Failed to seek to {:?} in {}, skipping instead: {}",
+                    offset, track, err
+                );
+                Box::new(source.skip_duration(offset))
+            }
+        }
+    }
+
+    // Opens the given track at `offset`. Returns the source together with the total
+    // duration of the track, if known.
+    fn open_track(
+        track: &Track,
+        offset: Duration,
+        generation: u64,
+        events_tx: &Sender<QueueEvent>,
+        gain: impl FnOnce(&Path) -> f32,
+    ) -> Result<(BoxedSource, Option<Duration>)> {
+        match track {
+            Track::File(path) => {
+                let file = File::open(path)
+                    .with_context(|| format!("opening audio file {}", path.display()))?;
+                let source = rodio::Decoder::new(BufReader::new(file))
+                    .with_context(|| format!("decoding audio file {}", path.display()))?;
+                let duration = source.total_duration();
+                let source = Self::seek_source(source, track, offset);
+                let factor = gain(path);
+                if factor == 1.0 {
+                    return Ok((source, duration));
+                }
+                Ok((Box::new(source.amplify(factor)), duration))
+            }
+            Track::Stream(url) => {
+                let events_tx = events_tx.clone();
+                let on_metadata = Box::new(move |metadata| {
+                    let event = QueueEvent::StreamMetadata {
+                        generation,
+                        metadata,
+                    };
+                    if let Err(err) = events_tx.send(event) {
+                        error!("Failed to send queue event: {}", err);
+                    }
+                });
+                let stream = HttpStream::open(url, on_metadata)
+                    .with_context(|| format!("opening stream {}", url))?;
+                let source = rodio::Decoder::new(stream)
+                    .with_context(|| format!("decoding stream {}", url))?;
+                let duration = source.total_duration();
+                Ok((Self::seek_source(source, track, offset), duration))
+            }
+        }
+    }
+
+    // Appends a callback to the sink, which sends the given event once playback reaches it.
+    fn append_event(sink: &Sink, events_tx: &Sender<QueueEvent>, event: QueueEvent) {
+        let events_tx = events_tx.clone();
+        sink.append(EmptyCallback::<f32>::new(Box::new(move || {
+            if let Err(err) = events_tx.send(event.clone()) {
+                error!("Failed to send queue event: {}", err);
+            }
+        })));
+    }
+
+    // Opens the given track and decodes its beginning. Called without holding the queue
+    // lock, since opening a stream or probing a file may block for a while.
+    fn prepare_track(
+        entry: &Track,
+        offset: Duration,
+        generation: u64,
+        normalization: Normalization,
+        replay_gain: &ReplayGain,
+        events_tx: &Sender<QueueEvent>,
+    ) -> Result<(Decoded, Option<Duration>)> {
+        let (source, duration) = Self::open_track(entry, offset, generation, events_tx, |path| {
+            replay_gain.factor(path, normalization)
+        })?;
+        Ok((Decoded::prefetch(source, PREFETCH_DURATION), duration))
+    }
+
+    // Appends the given prepared track to the sink, announcing its start and its end as
+    // queue events.
+    fn append_track(
+        sink: &Sink,
+        queue: &mut Queue,
+        events_tx: &Sender<QueueEvent>,
+        position: PlaybackPosition,
+        decoded: Decoded,
+        duration: Option<Duration>,
+    ) {
+        let PlaybackPosition { track, offset } = position;
+        let source = match &queue.handover {
+            Some(handover) => TrackSource::after(handover, decoded, queue.crossfade),
+            None => TrackSource::new(decoded, queue.crossfade),
+        };
+        let generation = queue.generation;
+        let on_start = {
+            let events_tx = events_tx.clone();
+            Box::new(move |played| {
+                let event = QueueEvent::TrackStarted {
+                    generation,
+                    track,
+                    offset: offset + played,
+                    duration,
+                };
+                if let Err(err) = events_tx.send(event) {
+                    error!("Failed to send queue event: {}", err);
+                }
+            })
         };
-        let file = BufReader::new(File::open(path).unwrap());
-        let source = rodio::Decoder::new(BufReader::new(file))?;
-        self.sink.stop();
-        self.sink.append(source);
+        let on_finish = {
+            let events_tx = events_tx.clone();
+            Box::new(move || {
+                let event = QueueEvent::TrackFinished { generation, track };
+                if let Err(err) = events_tx.send(event) {
+                    error!("Failed to send queue event: {}", err);
+                }
+            })
+        };
+        let source = source.on_start(on_start).on_finish(on_finish);
+        queue.handover = Some(source.handover());
+        sink.append(queue.fade.wrap(source));
+        debug!("FilePlayer: appended track {}", track);
+    }
+
+    // Appends the first track which can be decoded, trying the track at `start` and then
+    // its successors. Tracks failing to decode are reported as playback events. The queue
+    // is only locked in between opening tracks, nothing is appended once `generation` has
+    // been replaced. Returns the appended track, None if no track could be appended.
+    fn append_from(
+        sink: &Sink,
+        queue: &Mutex<Queue>,
+        replay_gain: &ReplayGain,
+        events_tx: &Sender<QueueEvent>,
+        playback_tx: &Sender<PlaybackEvent>,
+        generation: u64,
+        start: Option<PlaybackPosition>,
+    ) -> Option<usize> {
+        let mut next = start;
+        let mut attempts = 0;
+        while let Some(position) = next {
+            let track = position.track;
+            let entry = {
+                let queue = queue.lock().unwrap();
+                if queue.generation != generation {
+                    return None;
+                }
+                queue
+                    .playlist
+                    .track(track)
+                    .map(|entry| (entry.clone(), queue.normalization))
+            };
+            let res = match entry {
+                Some((entry, normalization)) => Self::prepare_track(
+                    &entry,
+                    position.offset,
+                    generation,
+                    normalization,
+                    replay_gain,
+                    events_tx,
+                ),
+                None => Err(anyhow!("track {} not in playlist", track)),
+            };
+            let mut queue = queue.lock().unwrap();
+            if queue.generation != generation {
+                return None;
+            }
+            match res {
+                Ok((decoded, duration)) => {
+                    Self::append_track(sink, &mut queue, events_tx, position, decoded, duration);
+                    return Some(track);
+                }
+                Err(err) => {
+                    error!("Failed to queue track {}: {:#}", track, err);
+                    queue.emit(
+                        playback_tx,
+                        PlaybackEventKind::DecodeError {
+                            track,
+                            error: format!("{:#}", err),
+                        },
+                    );
+                }
+            }
+            attempts += 1;
+            if attempts >= queue.playlist.len() {
+                break;
+            }
+            next = queue
+                .playlist
+                .after_failure(track)
+                .map(|track| PlaybackPosition {
+                    track,
+                    offset: Duration::from_secs(0),
+                });
+        }
+        None
+    }
+
+    fn run_queue_feeder(
+        sink: Arc<Sink>,
+        queue: Arc<Mutex<Queue>>,
+        replay_gain: ReplayGain,
+        events_rx: Receiver<QueueEvent>,
+        events_tx: Sender<QueueEvent>,
+        playback_tx: Sender<PlaybackEvent>,
+    ) {
+        for event in events_rx {
+            debug!("FilePlayer: received queue event {:?}", event);
+            match event {
+                QueueEvent::TrackStarted {
+                    generation,
+                    track,
+                    offset,
+                    duration,
+                } => {
+                    let successor = {
+                        let mut queue = queue.lock().unwrap();
+                        if queue.generation != generation {
+                            continue;
+                        }
+                        info!("FilePlayer: playing track {}", track);
+                        queue.current_track = Some(track);
+                        queue.current_duration = duration;
+                        queue.emit(
+                            &playback_tx,
+                            PlaybackEventKind::TrackStarted {
+                                track,
+                                offset,
+                                duration,
+                            },
+                        );
+                        queue.playlist.successor(track)
+                    };
+                    let appended = Self::append_from(
+                        &sink,
+                        &queue,
+                        &replay_gain,
+                        &events_tx,
+                        &playback_tx,
+                        generation,
+                        successor.map(|track| PlaybackPosition {
+                            track,
+                            offset: Duration::from_secs(0),
+                        }),
+                    );
+                    let queue = queue.lock().unwrap();
+                    if appended.is_none() && queue.generation == generation {
+                        Self::append_event(
+                            &sink,
+                            &events_tx,
+                            QueueEvent::QueueExhausted { generation },
+                        );
+                    }
+                }
+                QueueEvent::TrackFinished { generation, track } => {
+                    let queue = queue.lock().unwrap();
+                    if queue.generation != generation {
+                        continue;
+                    }
+                    queue.emit(&playback_tx, PlaybackEventKind::TrackFinished { track });
+                }
+                QueueEvent::QueueExhausted { generation } => {
+                    let mut queue = queue.lock().unwrap();
+                    if queue.generation != generation {
+                        continue;
+                    }
+                    info!("FilePlayer: queue exhausted");
+                    queue.current_track = None;
+                    queue.emit(&playback_tx, PlaybackEventKind::QueueExhausted);
+                }
+                QueueEvent::StreamMetadata {
+                    generation,
+                    metadata,
+                } => {
+                    let queue = queue.lock().unwrap();
+                    if queue.generation != generation {
+                        continue;
+                    }
+                    queue.emit(&playback_tx, PlaybackEventKind::StreamMetadata(metadata));
+                }
+            }
+        }
+    }
+
+    pub fn queue(
+        &self,
+        tag_conf: &TagConf,
+        playlist: Playlist,
+        start: Option<PlaybackPosition>,
+    ) -> Result<()> {
+        debug!("FilePlayer: queue from {:?}", start);
+        let crossfade = self.crossfade_duration(tag_conf);
+        let normalization = Normalization {
+            mode: self.config.get().replay_gain,
+            gain: tag_conf.gain,
+        };
+        let (generation, start) = {
+            let mut queue = self.queue.lock().unwrap();
+            self.replace_generation(&mut queue);
+            queue.playlist = playlist;
+            queue.crossfade = crossfade;
+            queue.normalization = normalization;
+
+            let first = match queue.playlist.first() {
+                Some(first) => first,
+                None => {
+                    warn!("cannot queue without file names");
+                    queue.emit(&self.playback_tx, PlaybackEventKind::QueueExhausted);
+                    return Ok(());
+                }
+            };
+            let start = match start {
+                Some(start) if start.track < queue.playlist.len() => start,
+                Some(start) => {
+                    warn!(
+                        "Track {} out of range for playlist of length {}, starting from the beginning",
+                        start.track,
+                        queue.playlist.len()
+                    );
+                    PlaybackPosition {
+                        track: first,
+                        offset: Duration::from_secs(0),
+                    }
+                }
+                None => PlaybackPosition {
+                    track: first,
+                    offset: Duration::from_secs(0),
+                },
+            };
+            (queue.generation, start)
+        };
+        let appended = Self::append_from(
+            &self.sink,
+            &self.queue,
+            &self.replay_gain,
+            &self.events_tx,
+            &self.playback_tx,
+            generation,
+            Some(start),
+        );
+        let queue = self.queue.lock().unwrap();
+        if appended.is_none() && queue.generation == generation {
+            // Nothing to wait for, the sink is empty.
+            queue.emit(&self.playback_tx, PlaybackEventKind::QueueExhausted);
+        }
         Ok(())
     }
 
+    // Continues the current playlist at the given position, replacing the sources in
+    // the sink. The request is left as is, events of the new generation belong to it.
+    fn jump(&self, position: PlaybackPosition) -> Result<()> {
+        debug!("FilePlayer: jump to {:?}", position);
+        let generation = {
+            let mut queue = self.queue.lock().unwrap();
+            self.replace_generation(&mut queue);
+            // Already considered current, so that repeated skipping does not have to
+            // wait for the track to start.
+            queue.current_track = Some(position.track);
+            queue.current_duration = None;
+            queue.generation
+        };
+        let appended = Self::append_from(
+            &self.sink,
+            &self.queue,
+            &self.replay_gain,
+            &self.events_tx,
+            &self.playback_tx,
+            generation,
+            Some(position),
+        );
+        {
+            let mut queue = self.queue.lock().unwrap();
+            if queue.generation != generation {
+                // Superseded while opening the track.
+                return Ok(());
+            }
+            queue.current_track = appended;
+            if appended.is_none() {
+                queue.emit(&self.playback_tx, PlaybackEventKind::QueueExhausted);
+                return Ok(());
+            }
+        }
+        self.cont()
+    }
+
+    pub fn next_track(&self) -> Result<()> {
+        let next = {
+            let queue = self.queue.lock().unwrap();
+            match queue.current_track {
+                Some(current) => queue.playlist.next(current),
+                None => {
+                    debug!("FilePlayer: no track playing, cannot skip");
+                    return Ok(());
+                }
+            }
+        };
+        match next {
+            Some(track) => self.jump(PlaybackPosition {
+                track,
+                offset: Duration::from_secs(0),
+            }),
+            None => {
+                info!("FilePlayer: already playing the last track");
+                Ok(())
+            }
+        }
+    }
+
+    // Restarts the current track if it is the first one.
+    pub fn previous_track(&self) -> Result<()> {
+        let previous = {
+            let queue = self.queue.lock().unwrap();
+            match queue.current_track {
+                Some(current) => queue.playlist.previous(current).unwrap_or(current),
+                None => {
+                    debug!("FilePlayer: no track playing, cannot skip");
+                    return Ok(());
+                }
+            }
+        };
+        self.jump(PlaybackPosition {
+            track: previous,
+            offset: Duration::from_secs(0),
+        })
+    }
+
+    pub fn seek(&self, position: PlaybackPosition) -> Result<()> {
+        if self.queue.lock().unwrap().current_track.is_none() {
+            debug!("FilePlayer: no track playing, cannot seek");
+            return Ok(());
+        }
+        self.jump(position)
+    }
+
+    fn fade_in_duration(&self) -> Duration {
+        Duration::from_millis(self.config.get().fade_in_ms)
+    }
+
+    fn fade_out_duration(&self) -> Duration {
+        Duration::from_millis(self.config.get().fade_out_ms)
+    }
+
+    fn crossfade_duration(&self, tag_conf: &TagConf) -> Duration {
+        let crossfade_ms = tag_conf
+            .crossfade_ms
+            .unwrap_or_else(|| self.config.get().crossfade_ms);
+        Duration::from_millis(crossfade_ms)
+    }
+
+    // Fades out the sources currently in the sink, which end afterwards, and starts a new
+    // generation of sources. These remain silent until faded in.
+    fn replace_generation(&self, queue: &mut Queue) {
+        queue
+            .fade
+            .fade_to(0.0, self.fade_out_duration(), AfterFade::End);
+        queue.fade = FadeControl::default();
+        queue.handover = None;
+        queue.generation += 1;
+        queue.current_track = None;
+    }
+
+    // Fades out and pauses playback. The sink keeps playing silence, so that pausing
+    // does not block the interpreter while fading.
     pub fn stop(&self) -> Result<()> {
         debug!("FilePlayer: stop");
-        self.sink.pause();
+        let queue = self.queue.lock().unwrap();
+        queue
+            .fade
+            .fade_to(0.0, self.fade_out_duration(), AfterFade::Hold);
         Ok(())
     }
 
     pub fn cont(&self) -> Result<()> {
         debug!("FilePlayer: cont");
+        let queue = self.queue.lock().unwrap();
+        queue
+            .fade
+            .fade_to(FULL_VOLUME, self.fade_in_duration(), AfterFade::Continue);
         self.sink.play();
         Ok(())
     }
 
+    pub fn fade_out(&self, duration: Duration) -> Result<()> {
+        debug!("FilePlayer: fade out over {:?}", duration);
+        let queue = self.queue.lock().unwrap();
+        queue.fade.fade_to(0.0, duration, AfterFade::Continue);
+        Ok(())
+    }
+
+    pub fn fade_in(&self, duration: Duration) -> Result<()> {
+        debug!("FilePlayer: fade in over {:?}", duration);
+        let queue = self.queue.lock().unwrap();
+        queue
+            .fade
+            .fade_to(FULL_VOLUME, duration, AfterFade::Continue);
+        Ok(())
+    }
+
+    pub fn set_volume(&self, level: u32) -> Result<()> {
+        self.sink
+            .set_volume(level as f32 / volume::FULL_VOLUME as f32);
+        Ok(())
+    }
+
     fn display_device_info(device: &Device) -> Result<()> {
         let name = device.name().unwrap_or_else(|_| "Unknown".to_string());
         info!("- audio output device: {}", name);
@@ -80,7 +639,10 @@ impl FilePlayer {
         Ok(())
     }
 
-    pub fn new(config_loader: ConfigLoaderHandle) -> Result<Self> {
+    pub fn new(
+        config_loader: ConfigLoaderHandle,
+        playback_tx: Sender<PlaybackEvent>,
+    ) -> Result<Self> {
         info!("Creating new FilePlayer...");
         let config = config_loader.get();
         let base_dir = config.audio_base_directory;
@@ -94,22 +656,50 @@ impl FilePlayer {
                 let device = Self::lookup_device_by_name(&name)?;
                 debug!(
                     "Initiating playback via device: {:?}",
-                    device.name().unwrap_or("(unknown)".to_string())
+                    device.name().unwrap_or_else(|_| "(unknown)".to_string())
                 );
                 OutputStream::try_from_device(&device)?
             }
-            None => {
-                OutputStream::try_default().with_context(|| "retrieving default audio output device")?
-            }
+            None => OutputStream::try_default()
+                .with_context(|| "retrieving default audio output device")?,
         };
 
-        let sink = Sink::try_new(&stream_handle)?;
+        let sink = Arc::new(Sink::try_new(&stream_handle)?);
+        let queue = Arc::new(Mutex::new(Queue::default()));
+        let replay_gain = ReplayGain::new(&base_dir).context("Creating ReplayGain")?;
+        let shuffle_seeds = ShuffleSeeds::new(&base_dir);
+        let (events_tx, events_rx) = crossbeam_channel::unbounded();
+        {
+            let sink = sink.clone();
+            let queue = queue.clone();
+            let replay_gain = replay_gain.clone();
+            let events_tx = events_tx.clone();
+            let playback_tx = playback_tx.clone();
+            std::thread::Builder::new()
+                .name("queue-feeder".to_string())
+                .spawn(move || {
+                    Self::run_queue_feeder(
+                        sink,
+                        queue,
+                        replay_gain,
+                        events_rx,
+                        events_tx,
+                        playback_tx,
+                    )
+                })
+                .context("Spawning queue feeder")?;
+        }
         let player = FilePlayer {
             base_dir,
-            sink: Arc::new(sink),
-            file_path: None,
-            output_stream: stream,
-            output_stream_handle: stream_handle,
+            config: config_loader,
+            sink,
+            queue,
+            replay_gain,
+            shuffle_seeds,
+            events_tx,
+            playback_tx,
+            _output_stream: stream,
+            _output_stream_handle: stream_handle,
         };
 
         Ok(player)
@@ -126,7 +716,9 @@ impl FilePlayer {
 
     fn lookup_device_by_name(name: &str) -> Result<Device> {
         let host = cpal::default_host();
-        let devices = host.output_devices().with_context(|| "retrieving list of audio devices")?;
+        let devices = host
+            .output_devices()
+            .with_context(|| "retrieving list of audio devices")?;
         for device in devices {
             let device_name = device
                 .name()
@@ -138,28 +730,151 @@ impl FilePlayer {
         Err(anyhow!("audio device not found: {}", name))
     }
 
+    // Resolves the URIs of a TagConf into a list of tracks. URIs naming a directory,
+    // a playlist file or containing a glob pattern are expanded at this point. Files
+    // outside of the audio base directory are skipped.
+    fn resolve_tracks(&self, uris: &[String]) -> Result<Vec<Track>> {
+        if uris.is_empty() {
+            return Err(anyhow::Error::msg("TagConf is empty"));
+        }
+        let mut tracks = Vec::new();
+        for uri in uris {
+            if http_stream::is_stream_uri(uri) {
+                let url = Url::parse(uri).with_context(|| format!("parsing URL {}", uri))?;
+                tracks.push(Track::Stream(url));
+                continue;
+            }
+            let path = self
+                .complete_file_name(Path::new(uri.as_str()))
+                .with_context(|| format!("completing file name {}", uri))?;
+            if !path.exists() && track_list::is_glob(uri) {
+                let expanded = track_list::expand_glob(&self.base_dir, uri)
+                    .with_context(|| format!("expanding glob pattern {}", uri))?;
+                if expanded.is_empty() {
+                    warn!("No audio files matching {}", uri);
+                }
+                tracks.extend(expanded.into_iter().map(Track::File));
+                continue;
+            }
+            if path.is_dir() {
+                let expanded = track_list::expand_dir(&path)
+                    .with_context(|| format!("expanding directory {}", uri))?;
+                if expanded.is_empty() {
+                    warn!("No audio files found in directory {}", uri);
+                }
+                tracks.extend(expanded.into_iter().map(Track::File));
+            } else if track_list::is_playlist_file(&path) {
+                let expanded = track_list::load_playlist(&self.base_dir, &path)
+                    .with_context(|| format!("loading playlist {}", uri))?;
+                if expanded.is_empty() {
+                    warn!("No audio files found in playlist {}", uri);
+                }
+                tracks.extend(expanded);
+            } else {
+                tracks.push(Track::File(path));
+            }
+        }
+        tracks.retain(|track| match track {
+            Track::File(path) if !track_list::is_within(&self.base_dir, path) => {
+                warn!(
+                    "Skipping {}, not within audio base directory {}",
+                    path.display(),
+                    self.base_dir.display()
+                );
+                false
+            }
+            _ => true,
+        });
+        if tracks.is_empty() {
+            return Err(anyhow!("no audio files found for uris {:?}", uris));
+        }
+        Ok(tracks)
+    }
+
+    // Shuffled playlists are reshuffled when played from the beginning, resumed playback
+    // keeps the previous order.
+    fn playlist(&self, tag_conf: &TagConf, resume: bool) -> Result<Playlist> {
+        let tracks = self.resolve_tracks(&tag_conf.uris)?;
+        let shuffle_seed = match (tag_conf.shuffle, resume) {
+            (false, _) => None,
+            (true, false) => Some(self.shuffle_seeds.renew(&tag_conf.tag_id)),
+            (true, true) => Some(self.shuffle_seeds.current(&tag_conf.tag_id)),
+        };
+        Ok(Playlist::new(tracks, shuffle_seed, tag_conf.repeat))
+    }
+
+    // Accounts for a new playback request and resolves the playlist for it. If that
+    // fails, the previous playback is dropped and the request is reported as exhausted
+    // right away.
+    fn begin_request(&self, tag_conf: &TagConf, resume: bool) -> Result<Playlist> {
+        self.queue.lock().unwrap().request += 1;
+        let res = self.playlist(tag_conf, resume);
+        if res.is_err() {
+            let mut queue = self.queue.lock().unwrap();
+            self.replace_generation(&mut queue);
+            queue.playlist = Playlist::default();
+            queue.emit(&self.playback_tx, PlaybackEventKind::QueueExhausted);
+        }
+        res
+    }
+
     pub fn start_playback(
         &mut self,
-        uris: &[String],
-        pause_state: Option<std::time::Duration>,
+        tag_conf: &TagConf,
+        start: Option<PlaybackPosition>,
     ) -> Result<()> {
-        info!("FilePlayer: initiating playback for uris {:?}", uris);
+        info!(
+            "FilePlayer: initiating playback for uris {:?}",
+            tag_conf.uris
+        );
 
-        if let Some(pause_state) = pause_state {
-            warn!("Ignoring pause state: {:?}", pause_state);
-        }
+        let playlist = self.begin_request(tag_conf, false)?;
+        self.queue(tag_conf, playlist, start)
+            .context("queue method of player handle")?;
+        self.cont().context("cont method of player handle")?;
+        Ok(())
+    }
 
-        let file_name = match uris.first().cloned() {
-            Some(uri) => uri,
-            None => return Err(anyhow::Error::msg("TagConf is empty")),
+    // Continues playback at the given position. If the requested track is still loaded
+    // in the sink, it is simply unpaused. Otherwise the playlist is queued again and
+    // the decoder is sought to the requested offset.
+    pub fn resume_playback(
+        &mut self,
+        tag_conf: &TagConf,
+        position: PlaybackPosition,
+    ) -> Result<()> {
+        let playlist = self.begin_request(tag_conf, true)?;
+        let is_loaded = {
+            let queue = self.queue.lock().unwrap();
+            let is_loaded = queue.playlist.tracks() == playlist.tracks()
+                && queue.current_track == Some(position.track)
+                && !self.sink.empty();
+            if is_loaded {
+                info!(
+                    "FilePlayer: continuing playback of track {} loaded in sink",
+                    position.track
+                );
+                queue.emit(
+                    &self.playback_tx,
+                    PlaybackEventKind::TrackStarted {
+                        track: position.track,
+                        offset: position.offset,
+                        duration: queue.current_duration,
+                    },
+                );
+            }
+            is_loaded
         };
-        let file_path = self
-            .complete_file_name(Path::new(file_name.as_str()))
-            .with_context(|| format!("completing file name {}", file_name))?;
-
-        self.file_path = Some(file_path);
+        if is_loaded {
+            return self.cont();
+        }
 
-        self.queue().context("queue method of player handle")?;
+        info!(
+            "FilePlayer: resuming playback for uris {:?} at {:?}",
+            tag_conf.uris, position
+        );
+        self.queue(tag_conf, playlist, Some(position))
+            .context("queue method of player handle")?;
         self.cont().context("cont method of player handle")?;
         Ok(())
     }
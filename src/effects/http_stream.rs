@@ -0,0 +1,550 @@
+use anyhow::{anyhow, Context, Result};
+use crossbeam_channel::{Receiver, Sender};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, info, trace, warn};
+use url::Url;
+
+// Number of bytes at the beginning of a stream which are retained, so that the decoder
+// can rewind while probing the stream format.
+const HEAD_SIZE: usize = 256 * 1024;
+const CHUNK_SIZE: usize = 16 * 1024;
+// Number of chunks buffered ahead of the decoder.
+const PREFETCH_CHUNKS: usize = 64;
+const MAX_RECONNECT_ATTEMPTS: u32 = 10;
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StreamMetadata {
+    pub name: Option<String>,
+    pub title: Option<String>,
+}
+
+pub type MetadataCallback = Box<dyn Fn(StreamMetadata) + Send + Sync>;
+
+pub fn is_stream_uri(uri: &str) -> bool {
+    let uri = uri.to_ascii_lowercase();
+    uri.starts_with("http://") || uri.starts_with("https://")
+}
+
+// Strips ICY metadata blocks from a Shoutcast/Icecast stream. Every `metaint` bytes of
+// audio data are followed by a length byte and `16 * length` bytes of metadata.
+struct IcyParser {
+    metaint: usize,
+    audio_remaining: usize,
+    meta_remaining: Option<usize>,
+    meta: Vec<u8>,
+}
+
+impl IcyParser {
+    fn new(metaint: usize) -> Self {
+        IcyParser {
+            metaint,
+            audio_remaining: metaint,
+            meta_remaining: None,
+            meta: Vec::new(),
+        }
+    }
+
+    // Appends the audio bytes contained in `data` to `audio` and returns the contents of
+    // each metadata block completed within `data`.
+    fn process(&mut self, mut data: &[u8], audio: &mut Vec<u8>) -> Vec<String> {
+        let mut blocks = Vec::new();
+        while !data.is_empty() {
+            match self.meta_remaining {
+                None if self.audio_remaining > 0 => {
+                    let n = self.audio_remaining.min(data.len());
+                    audio.extend_from_slice(&data[..n]);
+                    self.audio_remaining -= n;
+                    data = &data[n..];
+                }
+                None => {
+                    self.meta_remaining = Some(data[0] as usize * 16);
+                    self.meta.clear();
+                    data = &data[1..];
+                }
+                Some(remaining) => {
+                    let n = remaining.min(data.len());
+                    self.meta.extend_from_slice(&data[..n]);
+                    data = &data[n..];
+                    if remaining == n {
+                        if !self.meta.is_empty() {
+                            blocks.push(
+                                String::from_utf8_lossy(&self.meta)
+                                    .trim_end_matches('\0')
+                                    .to_string(),
+                            );
+                        }
+                        self.meta_remaining = None;
+                        self.audio_remaining = self.metaint;
+                    } else {
+                        self.meta_remaining = Some(remaining - n);
+                    }
+                }
+            }
+        }
+        blocks
+    }
+}
+
+// Extracts the stream title from an ICY metadata block, e.g. "StreamTitle='Artist - Song';".
+pub fn parse_stream_title(block: &str) -> Option<String> {
+    let start = block.find("StreamTitle='")? + "StreamTitle='".len();
+    let rest = &block[start..];
+    let end = rest.find("';").unwrap_or(rest.len());
+    let title = rest[..end].trim();
+    if title.is_empty() {
+        None
+    } else {
+        Some(title.to_string())
+    }
+}
+
+struct Fetcher {
+    agent: ureq::Agent,
+    url: Url,
+    tx: Sender<Vec<u8>>,
+    on_metadata: MetadataCallback,
+    metadata: StreamMetadata,
+    received: u64,
+    content_length: Option<u64>,
+    accepts_ranges: bool,
+}
+
+impl Fetcher {
+    fn connect(&mut self) -> Result<ureq::Response> {
+        let mut request = self
+            .agent
+            .request_url("GET", &self.url)
+            .set("Icy-MetaData", "1");
+        if self.received > 0 && self.accepts_ranges {
+            request = request.set("Range", &format!("bytes={}-", self.received));
+        }
+        let response = request
+            .call()
+            .with_context(|| format!("Requesting {}", self.url))?;
+        if self.received == 0 {
+            self.content_length = response
+                .header("Content-Length")
+                .and_then(|len| len.parse().ok());
+            self.accepts_ranges = response
+                .header("Accept-Ranges")
+                .map(|ranges| ranges.contains("bytes"))
+                .unwrap_or(false);
+        }
+        if let Some(name) = response.header("icy-name") {
+            if self.metadata.name.as_deref() != Some(name) {
+                self.metadata.name = Some(name.to_string());
+                (self.on_metadata)(self.metadata.clone());
+            }
+        }
+        Ok(response)
+    }
+
+    // Transfers the response body to the decoder. Returns Ok(true) once the stream has
+    // been transferred completely and Ok(false) if the decoder went away.
+    fn transfer(&mut self, response: ureq::Response) -> Result<bool> {
+        // Skip data we have delivered already, if the server does not honor range requests.
+        let mut skip = if self.received > 0 && response.status() != 206 {
+            if self.content_length.is_some() {
+                self.received
+            } else {
+                // Live stream, continue with whatever the server sends now.
+                0
+            }
+        } else {
+            0
+        };
+        let mut icy = response
+            .header("icy-metaint")
+            .and_then(|metaint| metaint.parse().ok())
+            .filter(|metaint| *metaint > 0)
+            .map(IcyParser::new);
+        let mut reader = response.into_reader();
+        let mut buf = vec![0; CHUNK_SIZE];
+        loop {
+            let n = reader.read(&mut buf).context("Reading HTTP stream")?;
+            if n == 0 {
+                return match self.content_length {
+                    Some(len) if self.received >= len => Ok(true),
+                    Some(len) => Err(anyhow!(
+                        "stream ended prematurely after {} of {} bytes",
+                        self.received,
+                        len
+                    )),
+                    None => Err(anyhow!("live stream ended")),
+                };
+            }
+            let mut chunk = Vec::with_capacity(n);
+            match icy {
+                Some(ref mut icy) => {
+                    for block in icy.process(&buf[..n], &mut chunk) {
+                        trace!("Received ICY metadata: {}", block);
+                        let title = parse_stream_title(&block);
+                        if title.is_some() && title != self.metadata.title {
+                            self.metadata.title = title;
+                            info!("Stream title: {:?}", self.metadata.title);
+                            (self.on_metadata)(self.metadata.clone());
+                        }
+                    }
+                }
+                None => chunk.extend_from_slice(&buf[..n]),
+            }
+            if skip > 0 {
+                let n = (skip as usize).min(chunk.len());
+                chunk.drain(..n);
+                skip -= n as u64;
+            }
+            if chunk.is_empty() {
+                continue;
+            }
+            self.received += chunk.len() as u64;
+            if self.tx.send(chunk).is_err() {
+                return Ok(false);
+            }
+        }
+    }
+
+    fn run(mut self, first_response: ureq::Response) {
+        let mut response = Some(first_response);
+        let mut attempts = 0;
+        loop {
+            let res = match response.take() {
+                Some(response) => Ok(response),
+                None => self.connect(),
+            };
+            let received_before = self.received;
+            match res.and_then(|response| self.transfer(response)) {
+                Ok(true) => {
+                    debug!("Finished transferring {}", self.url);
+                    return;
+                }
+                Ok(false) => {
+                    debug!("Decoder for {} went away, closing stream", self.url);
+                    return;
+                }
+                Err(err) => {
+                    if self.received > received_before {
+                        attempts = 0;
+                    }
+                    attempts += 1;
+                    if attempts > MAX_RECONNECT_ATTEMPTS {
+                        warn!("Giving up on stream {}: {:#}", self.url, err);
+                        return;
+                    }
+                    warn!(
+                        "Stream {} dropped, reconnecting (attempt {}): {:#}",
+                        self.url, attempts, err
+                    );
+                    thread::sleep(RECONNECT_DELAY * attempts);
+                }
+            }
+        }
+    }
+}
+
+// A `Read + Seek` adapter for HTTP(S) resources, suitable for the rodio decoder. The
+// resource is fetched by a background thread, which reconnects if the connection drops.
+// Seeking is restricted to the retained head of the stream and to forward seeks.
+pub struct HttpStream {
+    chunks: Receiver<Vec<u8>>,
+    current: Vec<u8>,
+    current_pos: usize,
+    head: Vec<u8>,
+    // Logical read position.
+    pos: u64,
+    // Number of bytes received from the fetcher.
+    delivered: u64,
+}
+
+impl HttpStream {
+    pub fn open(url: &Url, on_metadata: MetadataCallback) -> Result<Self> {
+        info!("Opening HTTP stream {}", url);
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(Duration::from_secs(10))
+            .timeout_read(Duration::from_secs(30))
+            .build();
+        let (tx, rx) = crossbeam_channel::bounded(PREFETCH_CHUNKS);
+        let mut fetcher = Fetcher {
+            agent,
+            url: url.clone(),
+            tx,
+            on_metadata,
+            metadata: StreamMetadata::default(),
+            received: 0,
+            content_length: None,
+            accepts_ranges: false,
+        };
+        // Connect synchronously, so that unreachable streams are reported right away.
+        let response = fetcher.connect()?;
+        thread::Builder::new()
+            .name("http-stream".to_string())
+            .spawn(move || fetcher.run(response))
+            .context("Spawning HTTP stream fetcher")?;
+        Ok(HttpStream {
+            chunks: rx,
+            current: Vec::new(),
+            current_pos: 0,
+            head: Vec::new(),
+            pos: 0,
+            delivered: 0,
+        })
+    }
+
+    // Number of bytes handed out from the received chunks so far.
+    fn cursor(&self) -> u64 {
+        self.delivered - (self.current.len() - self.current_pos) as u64
+    }
+
+    // Makes sure that the current chunk contains unread data. Returns false at the end
+    // of the stream.
+    fn fill(&mut self) -> bool {
+        while self.current_pos >= self.current.len() {
+            match self.chunks.recv() {
+                Ok(chunk) => {
+                    if self.head.len() < HEAD_SIZE {
+                        let n = (HEAD_SIZE - self.head.len()).min(chunk.len());
+                        self.head.extend_from_slice(&chunk[..n]);
+                    }
+                    self.delivered += chunk.len() as u64;
+                    self.current = chunk;
+                    self.current_pos = 0;
+                }
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+}
+
+impl Read for HttpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let cursor = self.cursor();
+        if self.pos < cursor {
+            // Rewound into the retained head.
+            let start = self.pos as usize;
+            let end = (cursor as usize).min(self.head.len());
+            let n = (end - start).min(buf.len());
+            buf[..n].copy_from_slice(&self.head[start..start + n]);
+            self.pos += n as u64;
+            return Ok(n);
+        }
+        if !self.fill() {
+            return Ok(0);
+        }
+        let available = &self.current[self.current_pos..];
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.current_pos += n;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for HttpStream {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(target) => target,
+            SeekFrom::Current(offset) => (self.pos as i64 + offset).max(0) as u64,
+            SeekFrom::End(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "seeking relative to the end of an HTTP stream",
+                ))
+            }
+        };
+        let cursor = self.cursor();
+        if target < cursor {
+            if cursor <= self.head.len() as u64 {
+                // Everything read so far is retained in the head.
+                self.pos = target;
+                return Ok(target);
+            }
+            if target >= cursor - self.current_pos as u64 {
+                // Within the current chunk.
+                self.current_pos -= (cursor - target) as usize;
+                self.pos = target;
+                return Ok(target);
+            }
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "seeking backwards in an HTTP stream",
+            ));
+        }
+        // Seek forward by discarding data.
+        self.pos = cursor;
+        let mut remaining = target - cursor;
+        while remaining > 0 {
+            if !self.fill() {
+                break;
+            }
+            let n = ((self.current.len() - self.current_pos) as u64).min(remaining);
+            self.current_pos += n as usize;
+            self.pos += n;
+            remaining -= n;
+        }
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    // Serves the given raw responses to consecutive connections and reports the request
+    // heads received. The connection of the last response is kept open.
+    fn serve(responses: Vec<Vec<u8>>) -> (Url, Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/stream", listener.local_addr().unwrap())).unwrap();
+        let (requests_tx, requests_rx) = crossbeam_channel::unbounded();
+        thread::spawn(move || {
+            let count = responses.len();
+            for (i, response) in responses.into_iter().enumerate() {
+                let (mut conn, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(conn.try_clone().unwrap());
+                let mut head = String::new();
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap() == 0 || line == "\r\n" {
+                        break;
+                    }
+                    head.push_str(&line);
+                }
+                requests_tx.send(head).unwrap();
+                conn.write_all(&response).unwrap();
+                conn.flush().unwrap();
+                if i + 1 == count {
+                    // Block until the client goes away.
+                    let _ = reader.read_to_end(&mut Vec::new());
+                }
+            }
+        });
+        (url, requests_rx)
+    }
+
+    fn response(status: &str, headers: &[&str], body: &[u8]) -> Vec<u8> {
+        let mut response = format!("HTTP/1.1 {}\r\n", status);
+        for header in headers {
+            response.push_str(header);
+            response.push_str("\r\n");
+        }
+        response.push_str("\r\n");
+        let mut response = response.into_bytes();
+        response.extend_from_slice(body);
+        response
+    }
+
+    fn read_exact(stream: &mut HttpStream, len: usize) -> Vec<u8> {
+        let mut buf = vec![0; len];
+        stream.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn stream_title_is_parsed() {
+        assert_eq!(
+            parse_stream_title("StreamTitle='Artist - Song';StreamUrl='';"),
+            Some("Artist - Song".to_string())
+        );
+        assert_eq!(parse_stream_title("StreamTitle='';"), None);
+        assert_eq!(parse_stream_title("StreamUrl='x';"), None);
+    }
+
+    #[test]
+    fn icy_parser_strips_metadata_split_across_chunks() {
+        let mut data = b"abcd".to_vec();
+        let meta = b"StreamTitle='A';";
+        data.push(1);
+        data.extend_from_slice(meta);
+        data.extend_from_slice(b"efgh");
+        data.push(0);
+        data.extend_from_slice(b"ij");
+
+        let mut parser = IcyParser::new(4);
+        let mut audio = Vec::new();
+        let mut blocks = Vec::new();
+        for chunk in data.chunks(3) {
+            blocks.extend(parser.process(chunk, &mut audio));
+        }
+        assert_eq!(audio, b"abcdefghij");
+        assert_eq!(blocks, vec!["StreamTitle='A';".to_string()]);
+    }
+
+    #[test]
+    fn icy_metadata_is_reported() {
+        let mut body = b"0123".to_vec();
+        body.push(2);
+        let mut meta = b"StreamTitle='Song';".to_vec();
+        meta.resize(32, 0);
+        body.extend_from_slice(&meta);
+        body.extend_from_slice(b"4567");
+        let (url, requests) = serve(vec![response(
+            "200 OK",
+            &["icy-metaint: 4", "icy-name: Radio"],
+            &body,
+        )]);
+
+        let (metadata_tx, metadata_rx) = crossbeam_channel::unbounded();
+        let on_metadata = Box::new(move |metadata| metadata_tx.send(metadata).unwrap());
+        let mut stream = HttpStream::open(&url, on_metadata).unwrap();
+        assert_eq!(read_exact(&mut stream, 8), b"01234567");
+
+        assert!(requests.recv().unwrap().contains("Icy-MetaData: 1"));
+        let timeout = Duration::from_secs(5);
+        assert_eq!(
+            metadata_rx.recv_timeout(timeout).unwrap(),
+            StreamMetadata {
+                name: Some("Radio".to_string()),
+                title: None,
+            }
+        );
+        assert_eq!(
+            metadata_rx.recv_timeout(timeout).unwrap(),
+            StreamMetadata {
+                name: Some("Radio".to_string()),
+                title: Some("Song".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn reconnects_with_range_after_premature_eof() {
+        let first = response(
+            "200 OK",
+            &[
+                "Content-Length: 8",
+                "Accept-Ranges: bytes",
+                "Connection: close",
+            ],
+            b"0123",
+        );
+        let second = response("206 Partial Content", &["Content-Length: 4"], b"4567");
+        let (url, requests) = serve(vec![first, second]);
+
+        let mut stream = HttpStream::open(&url, Box::new(|_| {})).unwrap();
+        assert_eq!(read_exact(&mut stream, 8), b"01234567");
+        let mut rest = Vec::new();
+        stream.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+
+        assert!(!requests.recv().unwrap().contains("Range"));
+        assert!(requests.recv().unwrap().contains("Range: bytes=4-"));
+    }
+
+    #[test]
+    fn skips_delivered_data_if_range_is_ignored() {
+        let first = response(
+            "200 OK",
+            &["Content-Length: 8", "Connection: close"],
+            b"0123",
+        );
+        let second = response("200 OK", &["Content-Length: 8"], b"01234567");
+        let (url, _requests) = serve(vec![first, second]);
+
+        let mut stream = HttpStream::open(&url, Box::new(|_| {})).unwrap();
+        assert_eq!(read_exact(&mut stream, 8), b"01234567");
+    }
+}
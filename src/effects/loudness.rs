@@ -0,0 +1,210 @@
+// Integrated loudness according to EBU R128 / ITU-R BS.1770.
+
+use rodio::Source;
+use std::f64::consts::PI;
+
+// Gating blocks of 400ms are formed from four consecutive 100ms intervals.
+const INTERVALS_PER_SECOND: u32 = 10;
+const INTERVALS_PER_BLOCK: usize = 4;
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Loudness {
+    // Integrated loudness in LUFS, None for silence.
+    pub integrated: Option<f64>,
+    // Sample peak relative to full scale.
+    pub peak: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+}
+
+// K-weighting: a high shelf modelling the acoustic effect of the head, followed by a
+// high-pass filter. Coefficients are derived for the given sample rate.
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let fs = sample_rate as f64;
+
+    let f0 = 1681.974450955533;
+    let gain = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (PI * f0 / fs).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    };
+
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (PI * f0 / fs).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    };
+
+    [shelf, high_pass]
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct FilterState {
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl FilterState {
+    fn process(&mut self, filter: &Biquad, x: f64) -> f64 {
+        let y = filter.b[0] * x + filter.b[1] * self.x[0] + filter.b[2] * self.x[1]
+            - filter.a[1] * self.y[0]
+            - filter.a[2] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+fn loudness_of(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+fn mean(values: impl Iterator<Item = f64>) -> Option<f64> {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), value| (sum + value, count + 1));
+    if count == 0 {
+        None
+    } else {
+        Some(sum / count as f64)
+    }
+}
+
+// Measures the given source by decoding it completely.
+pub fn measure<S>(source: S) -> Loudness
+where
+    S: Source<Item = i16>,
+{
+    let channels = source.channels().max(1) as usize;
+    let sample_rate = source.sample_rate();
+    let filters = k_weighting(sample_rate);
+    let mut states = vec![[FilterState::default(); 2]; channels];
+    // Mono content is played back on both channels.
+    let weight = if channels == 1 { 2.0 } else { 1.0 };
+    let interval_len = (sample_rate / INTERVALS_PER_SECOND).max(1) as usize * channels;
+
+    let mut peak = 0.0f32;
+    let mut intervals = Vec::new();
+    let mut sum = 0.0;
+    let mut count = 0;
+    for (i, sample) in source.enumerate() {
+        let x = sample as f32 / i16::MAX as f32;
+        peak = peak.max(x.abs());
+        let state = &mut states[i % channels];
+        let shelved = state[0].process(&filters[0], x as f64);
+        let y = state[1].process(&filters[1], shelved);
+        sum += weight * y * y;
+        count += 1;
+        if count == interval_len {
+            intervals.push(sum / (interval_len / channels) as f64);
+            sum = 0.0;
+            count = 0;
+        }
+    }
+
+    let blocks: Vec<f64> = intervals
+        .windows(INTERVALS_PER_BLOCK)
+        .map(|window| window.iter().sum::<f64>() / INTERVALS_PER_BLOCK as f64)
+        .filter(|energy| *energy > 0.0 && loudness_of(*energy) > ABSOLUTE_GATE)
+        .collect();
+    let integrated = mean(blocks.iter().copied()).and_then(|energy| {
+        let threshold = loudness_of(energy) + RELATIVE_GATE;
+        mean(
+            blocks
+                .iter()
+                .copied()
+                .filter(|energy| loudness_of(*energy) > threshold),
+        )
+        .map(loudness_of)
+    });
+    Loudness { integrated, peak }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    // A 1 kHz sine of the given amplitude relative to full scale, on every channel.
+    fn sine(channels: u16, amplitude: f64, seconds: u32) -> SamplesBuffer<i16> {
+        let samples = (0..SAMPLE_RATE * seconds)
+            .flat_map(|i| {
+                let t = i as f64 / SAMPLE_RATE as f64;
+                let x = amplitude * (2.0 * PI * 1000.0 * t).sin();
+                let sample = (x * i16::MAX as f64).round() as i16;
+                std::iter::repeat_n(sample, channels as usize)
+            })
+            .collect::<Vec<_>>();
+        SamplesBuffer::new(channels, SAMPLE_RATE, samples)
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 0.1,
+            "expected {} LUFS, measured {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn stereo_sine_has_the_loudness_of_its_amplitude() {
+        // K-weighting is neutral at 1 kHz, the -0.691 dB offset compensates for the gain
+        // of the shelf.
+        for amplitude_db in [-6.0, -18.0, -30.0] {
+            let amplitude = 10f64.powf(amplitude_db / 20.0);
+            let loudness = measure(sine(2, amplitude, 3));
+            assert_close(loudness.integrated.unwrap(), amplitude_db);
+            assert!((loudness.peak as f64 - amplitude).abs() < 0.001);
+        }
+    }
+
+    #[test]
+    fn mono_is_measured_as_played_on_both_channels() {
+        let mono = measure(sine(1, 0.25, 3)).integrated.unwrap();
+        let stereo = measure(sine(2, 0.25, 3)).integrated.unwrap();
+        assert_close(mono, stereo);
+    }
+
+    #[test]
+    fn silence_has_no_loudness() {
+        let loudness = measure(sine(2, 0.0, 1));
+        assert_eq!(loudness.integrated, None);
+        assert_eq!(loudness.peak, 0.0);
+    }
+
+    #[test]
+    fn quiet_passages_are_gated() {
+        // Without gating, two seconds of silence following two seconds of the sine
+        // would lower the loudness by 3 dB. Only the blocks overlapping both count.
+        let samples = sine(2, 0.5, 2)
+            .chain(std::iter::repeat_n(0, 2 * 2 * SAMPLE_RATE as usize))
+            .collect::<Vec<_>>();
+        let loudness = measure(SamplesBuffer::new(2, SAMPLE_RATE, samples));
+        let sine_loudness = 20.0 * 0.5f64.log10();
+        let integrated = loudness.integrated.unwrap();
+        assert!(
+            integrated < sine_loudness && integrated > sine_loudness - 0.5,
+            "measured {} LUFS",
+            integrated
+        );
+    }
+}
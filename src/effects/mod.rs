@@ -1,11 +1,22 @@
+pub mod fade;
 pub mod file_player;
+pub mod http_stream;
 pub mod led;
+pub mod loudness;
+pub mod playlist;
+pub mod recording;
+pub mod replay_gain;
+pub mod track_list;
+pub mod track_source;
 
-use std::sync::{Arc, RwLock};
+use std::sync::Arc;
+use std::time::Duration;
 
 use crate::components::config::ConfigLoaderHandle;
 use anyhow::Result;
+use crossbeam_channel::Sender;
 use file_player::FilePlayer;
+use http_stream::StreamMetadata;
 use led::{Led, LedController};
 use std::process::Command;
 use tracing::{debug, info, warn};
@@ -15,31 +26,66 @@ use crate::components::tag_mapper::TagConf;
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Effect {
     Play(TagConf),
-    PlayContinue(std::time::Duration),
+    PlayContinue(TagConf, PlaybackPosition),
     Stop,
     LedOn,
     LedOff,
     GenericCommand(String),
+    FadeOut(Duration),
+    FadeIn(Duration),
+    // Volume level in percent.
+    SetVolume(u32),
+    NextTrack,
+    PreviousTrack,
+    // Continues the current playlist at the given position.
+    Seek(PlaybackPosition),
+    // Plays the given URI once, replacing any playback. Not a playback of a tag, the
+    // player state is not affected.
+    PlayNotice(String),
 }
 
-#[derive(Debug, Clone, Copy)]
-pub struct InterpreterState {
-    pub currently_playing: bool,
+/// Position within a playlist: the index of a track and the offset into that track.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PlaybackPosition {
+    pub track: usize,
+    pub offset: Duration,
 }
 
-impl InterpreterState {
-    pub fn new() -> Self {
-        InterpreterState {
-            currently_playing: false,
-        }
-    }
+/// Events reported by the audio backend while working through a playlist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlaybackEventKind {
+    // The duration is the total duration of the track, if known.
+    TrackStarted {
+        track: usize,
+        offset: Duration,
+        duration: Option<Duration>,
+    },
+    TrackFinished {
+        track: usize,
+    },
+    // The last track of the playlist has finished, or no track could be played at all.
+    QueueExhausted,
+    DecodeError {
+        track: usize,
+        error: String,
+    },
+    StreamMetadata(StreamMetadata),
+}
+
+/// A playback event, tagged with the number of playback requests (`Effect::Play`,
+/// `Effect::PlayContinue` and `Effect::PlayNotice`) the interpreter had received when the
+/// event was emitted.
+/// This allows the consumer to discard events belonging to a playback it has replaced
+/// in the meantime.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlaybackEvent {
+    pub request: u64,
+    pub kind: PlaybackEventKind,
 }
 
 pub struct ProdInterpreter {
     file_player: FilePlayer,
     led_controller: Arc<Box<dyn LedController + 'static + Send + Sync>>,
-    pause_state: std::time::Duration,
-    pub interpreter_state: Arc<RwLock<InterpreterState>>,
 }
 
 pub trait Interpreter {
@@ -59,31 +105,30 @@ impl Interpreter for ProdInterpreter {
             Effect::LedOff => self.led_off(),
             Effect::Play(tag_conf) => self.play(tag_conf),
             Effect::Stop => self.stop(),
-            Effect::PlayContinue(_) => self.play_continue(),
+            Effect::PlayContinue(tag_conf, position) => self.play_continue(tag_conf, position),
+            Effect::FadeOut(duration) => self.file_player.fade_out(duration),
+            Effect::FadeIn(duration) => self.file_player.fade_in(duration),
+            Effect::SetVolume(level) => self.set_volume(level),
+            Effect::NextTrack => self.file_player.next_track(),
+            Effect::PreviousTrack => self.file_player.previous_track(),
+            Effect::Seek(position) => self.file_player.seek(position),
+            Effect::PlayNotice(uri) => self.play_notice(uri),
         }
     }
 }
 
 impl ProdInterpreter {
-    pub fn new(config_loader: ConfigLoaderHandle, interpreter_state: Arc<RwLock<InterpreterState>>) -> Result<Self> {
+    pub fn new(
+        config_loader: ConfigLoaderHandle,
+        playback_tx: Sender<PlaybackEvent>,
+    ) -> Result<Self> {
         info!("Creating production interpreter");
         let led_controller = Arc::new(Box::new(led::gpio_cdev::GpioCdev::new()?)
             as Box<dyn LedController + 'static + Send + Sync>);
-        let file_player = FilePlayer::new(config_loader)?;
-        let interpreter_state_copy = interpreter_state.clone();
-        let sink = file_player.sink.clone();
-        tokio::task::spawn_blocking(move || loop {
-            {
-                let mut state = interpreter_state_copy.write().unwrap();
-                state.currently_playing = !sink.empty();
-            }
-            std::thread::sleep(std::time::Duration::from_secs(2));
-        });
+        let file_player = FilePlayer::new(config_loader, playback_tx)?;
         Ok(ProdInterpreter {
             file_player,
             led_controller,
-            pause_state: std::time::Duration::from_secs(0),
-            interpreter_state,
         })
     }
 
@@ -91,15 +136,23 @@ impl ProdInterpreter {
     // Effect implementations.
     //
 
-    fn play_continue(&mut self) -> Result<()> {
-        debug!("Interpreter: play/continue");
-        self.file_player.cont()
+    fn play_continue(&mut self, tag_conf: TagConf, position: PlaybackPosition) -> Result<()> {
+        debug!("Interpreter: play/continue at {:?}", position);
+        self.file_player.resume_playback(&tag_conf, position)
     }
 
     fn play(&mut self, tag_conf: TagConf) -> Result<()> {
         debug!("Interpreter: play");
-        self.file_player
-            .start_playback(&tag_conf.uris, Some(self.pause_state))
+        self.file_player.start_playback(&tag_conf, None)
+    }
+
+    fn play_notice(&mut self, uri: String) -> Result<()> {
+        debug!("Interpreter: play notice {}", uri);
+        let tag_conf = TagConf {
+            uris: vec![uri],
+            ..TagConf::default()
+        };
+        self.file_player.start_playback(&tag_conf, None)
     }
 
     fn stop(&self) -> Result<()> {
@@ -107,6 +160,11 @@ impl ProdInterpreter {
         self.file_player.stop()
     }
 
+    fn set_volume(&self, level: u32) -> Result<()> {
+        debug!("Interpreter: set volume to {}%", level);
+        self.file_player.set_volume(level)
+    }
+
     fn led_on(&self) -> Result<()> {
         debug!("Interpreter: LED on");
         self.led_controller.switch_on(Led::Playback)
@@ -119,7 +177,7 @@ impl ProdInterpreter {
 
     pub fn generic_command(&self, cmd: &str) -> Result<()> {
         debug!("Interpreter: Executing command '{}'", &cmd);
-        let res = Command::new("/bin/sh").arg("-c").arg(&cmd).status();
+        let res = Command::new("/bin/sh").arg("-c").arg(cmd).status();
         match res {
             Ok(exit_status) => {
                 if exit_status.success() {
@@ -0,0 +1,331 @@
+use anyhow::Result;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+use crate::components::json_file;
+use crate::components::tag_mapper::Repeat;
+use crate::effects::track_list::Track;
+
+// Stored in the audio base directory.
+const SHUFFLE_SEEDS_FILE_NAME: &str = ".shuffle-seeds.json";
+
+// A playlist consists of the tracks in the order given in the tag mapper configuration
+// together with the order in which they are to be played. Track indices always refer
+// to the configured order, so that bookmarks stay valid for shuffled playlists.
+#[derive(Debug, Clone, Default)]
+pub struct Playlist {
+    tracks: Vec<Track>,
+    order: Vec<usize>,
+    repeat: Repeat,
+}
+
+impl Playlist {
+    // Shuffles the tracks if a seed is given. The same seed yields the same order, so that
+    // resumed playback continues in the order it was started with.
+    pub fn new(tracks: Vec<Track>, shuffle_seed: Option<u64>, repeat: Repeat) -> Self {
+        let mut order: Vec<usize> = (0..tracks.len()).collect();
+        if let Some(seed) = shuffle_seed {
+            order.shuffle(&mut StdRng::seed_from_u64(seed));
+        }
+        Playlist {
+            tracks,
+            order,
+            repeat,
+        }
+    }
+
+    pub fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    pub fn len(&self) -> usize {
+        self.tracks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tracks.is_empty()
+    }
+
+    pub fn track(&self, index: usize) -> Option<&Track> {
+        self.tracks.get(index)
+    }
+
+    // Returns the index of the track to start playback with.
+    pub fn first(&self) -> Option<usize> {
+        self.order.first().copied()
+    }
+
+    // Returns the index of the track to be played after the given track, honoring
+    // the repeat mode.
+    pub fn successor(&self, index: usize) -> Option<usize> {
+        if self.repeat == Repeat::One {
+            return Some(index);
+        }
+        let pos = self.order.iter().position(|i| *i == index)?;
+        match self.order.get(pos + 1) {
+            Some(next) => Some(*next),
+            None if self.repeat == Repeat::All => self.first(),
+            None => None,
+        }
+    }
+
+    // Returns the index of the track following the given track when skipping forward.
+    // Unlike `successor`, this leaves a repeated track.
+    pub fn next(&self, index: usize) -> Option<usize> {
+        let pos = self.order.iter().position(|i| *i == index)?;
+        match self.order.get(pos + 1) {
+            Some(next) => Some(*next),
+            None if self.repeat != Repeat::None => self.first(),
+            None => None,
+        }
+    }
+
+    // Returns the index of the track to try after the given track failed to play. A
+    // failed track is not repeated.
+    pub fn after_failure(&self, index: usize) -> Option<usize> {
+        self.next(index).filter(|next| *next != index)
+    }
+
+    // Returns the index of the track preceding the given track when skipping backward.
+    pub fn previous(&self, index: usize) -> Option<usize> {
+        let pos = self.order.iter().position(|i| *i == index)?;
+        match pos.checked_sub(1) {
+            Some(prev) => Some(self.order[prev]),
+            None if self.repeat != Repeat::None => self.order.last().copied(),
+            None => None,
+        }
+    }
+}
+
+// The seeds of the shuffled playlists are stored as JSON object keyed by tag UID:
+//
+// {"0a1b2c3d": 8127349812734}
+//
+
+// Remembers the seed each tag was last shuffled with, across restarts.
+#[derive(Debug, Clone)]
+pub struct ShuffleSeeds {
+    file: PathBuf,
+    seeds: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl ShuffleSeeds {
+    pub fn new(base_dir: &Path) -> Self {
+        let file = base_dir.join(SHUFFLE_SEEDS_FILE_NAME);
+        let seeds = match json_file::load(&file, "shuffle seeds") {
+            Ok(seeds) => seeds.unwrap_or_default(),
+            Err(err) => {
+                warn!("Discarding shuffle seeds: {:#}", err);
+                HashMap::new()
+            }
+        };
+        ShuffleSeeds {
+            file,
+            seeds: Arc::new(Mutex::new(seeds)),
+        }
+    }
+
+    fn persist(&self, seeds: &HashMap<String, u64>) -> Result<()> {
+        json_file::store(&self.file, seeds, "shuffle seeds")
+    }
+
+    // Draws a new seed for the tag, for playback from the beginning.
+    pub fn renew(&self, tag_id: &str) -> u64 {
+        let seed = rand::random();
+        let mut seeds = self.seeds.lock().unwrap();
+        seeds.insert(tag_id.to_string(), seed);
+        if let Err(err) = self.persist(&seeds) {
+            warn!("Failed to persist shuffle seeds: {:#}", err);
+        }
+        seed
+    }
+
+    // Returns the seed the tag was last shuffled with, for resuming playback.
+    pub fn current(&self, tag_id: &str) -> u64 {
+        let seed = self.seeds.lock().unwrap().get(tag_id).copied();
+        match seed {
+            Some(seed) => seed,
+            None => self.renew(tag_id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playlist(len: usize, repeat: Repeat) -> Playlist {
+        let tracks = (0..len)
+            .map(|i| Track::File(PathBuf::from(format!("{}.mp3", i))))
+            .collect();
+        Playlist::new(tracks, None, repeat)
+    }
+
+    struct Case {
+        repeat: Repeat,
+        index: usize,
+        successor: Option<usize>,
+        next: Option<usize>,
+        previous: Option<usize>,
+        after_failure: Option<usize>,
+    }
+
+    #[test]
+    fn repeat_modes_at_both_ends() {
+        let cases = vec![
+            Case {
+                repeat: Repeat::None,
+                index: 0,
+                successor: Some(1),
+                next: Some(1),
+                previous: None,
+                after_failure: Some(1),
+            },
+            Case {
+                repeat: Repeat::None,
+                index: 2,
+                successor: None,
+                next: None,
+                previous: Some(1),
+                after_failure: None,
+            },
+            Case {
+                repeat: Repeat::One,
+                index: 0,
+                successor: Some(0),
+                next: Some(1),
+                previous: Some(2),
+                after_failure: Some(1),
+            },
+            Case {
+                repeat: Repeat::One,
+                index: 2,
+                successor: Some(2),
+                next: Some(0),
+                previous: Some(1),
+                after_failure: Some(0),
+            },
+            Case {
+                repeat: Repeat::All,
+                index: 0,
+                successor: Some(1),
+                next: Some(1),
+                previous: Some(2),
+                after_failure: Some(1),
+            },
+            Case {
+                repeat: Repeat::All,
+                index: 2,
+                successor: Some(0),
+                next: Some(0),
+                previous: Some(1),
+                after_failure: Some(0),
+            },
+        ];
+        for case in cases {
+            let playlist = playlist(3, case.repeat);
+            let name = format!("{:?} at {}", case.repeat, case.index);
+            assert_eq!(playlist.first(), Some(0), "{}", name);
+            assert_eq!(playlist.successor(case.index), case.successor, "{}", name);
+            assert_eq!(playlist.next(case.index), case.next, "{}", name);
+            assert_eq!(playlist.previous(case.index), case.previous, "{}", name);
+            assert_eq!(
+                playlist.after_failure(case.index),
+                case.after_failure,
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn failed_single_track_is_not_retried() {
+        for repeat in [Repeat::None, Repeat::One, Repeat::All].iter() {
+            let playlist = playlist(1, *repeat);
+            assert_eq!(playlist.after_failure(0), None, "{:?}", repeat);
+        }
+        assert_eq!(playlist(1, Repeat::One).successor(0), Some(0));
+        assert_eq!(playlist(1, Repeat::All).successor(0), Some(0));
+    }
+
+    #[test]
+    fn unknown_tracks_have_no_neighbours() {
+        let playlist = playlist(3, Repeat::All);
+        assert_eq!(playlist.successor(3), None);
+        assert_eq!(playlist.next(3), None);
+        assert_eq!(playlist.previous(3), None);
+        assert_eq!(Playlist::default().first(), None);
+    }
+
+    // Follows the successors from the first track, the order the tracks are played in.
+    fn play_order(playlist: &Playlist) -> Vec<usize> {
+        let mut order = vec![];
+        let mut next = playlist.first();
+        while let Some(index) = next {
+            order.push(index);
+            next = playlist.successor(index);
+        }
+        order
+    }
+
+    #[test]
+    fn shuffled_order_is_determined_by_the_seed() {
+        let tracks: Vec<Track> = (0..20)
+            .map(|i| Track::File(PathBuf::from(format!("{}.mp3", i))))
+            .collect();
+        let shuffled = Playlist::new(tracks.clone(), Some(42), Repeat::None);
+        let order = play_order(&shuffled);
+
+        let mut sorted = order.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, (0..20).collect::<Vec<_>>());
+        assert_ne!(order, sorted);
+        assert_eq!(
+            play_order(&Playlist::new(tracks.clone(), Some(42), Repeat::None)),
+            order
+        );
+        assert_ne!(
+            play_order(&Playlist::new(tracks.clone(), Some(43), Repeat::None)),
+            order
+        );
+        // Indices refer to the configured order.
+        assert_eq!(shuffled.track(3), tracks.get(3));
+        assert_eq!(shuffled.previous(order[1]), Some(order[0]));
+    }
+
+    #[test]
+    fn shuffle_seeds_survive_a_restart() {
+        let base_dir =
+            std::env::temp_dir().join(format!("rustberry-shuffle-seeds-{}", std::process::id()));
+        std::fs::create_dir_all(&base_dir).unwrap();
+        let tracks: Vec<Track> = (0..20)
+            .map(|i| Track::File(PathBuf::from(format!("{}.mp3", i))))
+            .collect();
+
+        let seeds = ShuffleSeeds::new(&base_dir);
+        let seed = seeds.renew("a");
+        assert_eq!(seeds.current("a"), seed);
+        let order = play_order(&Playlist::new(tracks.clone(), Some(seed), Repeat::None));
+
+        let restarted = ShuffleSeeds::new(&base_dir);
+        assert_eq!(restarted.current("a"), seed);
+        assert_eq!(
+            play_order(&Playlist::new(
+                tracks,
+                Some(restarted.current("a")),
+                Repeat::None
+            )),
+            order
+        );
+        // Unknown tags get a seed drawn and remembered.
+        let other = restarted.current("b");
+        assert_eq!(ShuffleSeeds::new(&base_dir).current("b"), other);
+
+        std::fs::remove_dir_all(&base_dir).unwrap();
+    }
+}
@@ -0,0 +1,173 @@
+use anyhow::{anyhow, Result};
+use crossbeam_channel::Sender;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, error};
+
+use crate::components::clock::Clock;
+use crate::effects::{Effect, Interpreter, PlaybackEvent, PlaybackEventKind, PlaybackPosition};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedEffect {
+    pub at: Instant,
+    pub effect: Effect,
+    pub succeeded: bool,
+}
+
+#[derive(Debug, Default)]
+struct Recording {
+    effects: Vec<RecordedEffect>,
+    // Error messages for the next effects to be interpreted.
+    failures: VecDeque<String>,
+    // Number of playback requests received, see `PlaybackEvent`.
+    request: u64,
+}
+
+// An interpreter which does not touch any hardware. It records the effects it is asked
+// to interpret and emits playback events the way the production interpreter does:
+// playback requests are reported as started right away, completion and decoding
+// failures are triggered via the handle.
+pub struct RecordingInterpreter {
+    clock: Arc<dyn Clock>,
+    playback_tx: Sender<PlaybackEvent>,
+    recording: Arc<Mutex<Recording>>,
+}
+
+#[derive(Clone)]
+pub struct RecordingInterpreterHandle {
+    playback_tx: Sender<PlaybackEvent>,
+    recording: Arc<Mutex<Recording>>,
+}
+
+impl Recording {
+    fn emit(&self, playback_tx: &Sender<PlaybackEvent>, kind: PlaybackEventKind) {
+        let event = PlaybackEvent {
+            request: self.request,
+            kind,
+        };
+        if let Err(err) = playback_tx.send(event) {
+            error!("Failed to send playback event: {}", err);
+        }
+    }
+}
+
+impl Interpreter for RecordingInterpreter {
+    fn wait_until_ready(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn interprete(&mut self, eff: Effect) -> Result<()> {
+        debug!("RecordingInterpreter: {:?}", eff);
+        let mut recording = self.recording.lock().unwrap();
+        let failure = recording.failures.pop_front();
+        recording.effects.push(RecordedEffect {
+            at: self.clock.now(),
+            effect: eff.clone(),
+            succeeded: failure.is_none(),
+        });
+
+        let (start, new_request) = match eff {
+            Effect::Play(_) | Effect::PlayNotice(_) => (Some(PlaybackPosition::default()), true),
+            Effect::PlayContinue(_, position) => (Some(position), true),
+            // Seeking continues the current playback request.
+            Effect::Seek(position) => (Some(position), false),
+            _ => (None, false),
+        };
+        if let Some(position) = start {
+            if new_request {
+                recording.request += 1;
+            }
+            let kind = match failure {
+                Some(_) => PlaybackEventKind::QueueExhausted,
+                None => PlaybackEventKind::TrackStarted {
+                    track: position.track,
+                    offset: position.offset,
+                    duration: None,
+                },
+            };
+            recording.emit(&self.playback_tx, kind);
+        }
+
+        match failure {
+            Some(msg) => Err(anyhow!(msg)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl RecordingInterpreter {
+    pub fn new(clock: Arc<dyn Clock>, playback_tx: Sender<PlaybackEvent>) -> Self {
+        RecordingInterpreter {
+            clock,
+            playback_tx,
+            recording: Arc::new(Mutex::new(Recording::default())),
+        }
+    }
+
+    pub fn handle(&self) -> RecordingInterpreterHandle {
+        RecordingInterpreterHandle {
+            playback_tx: self.playback_tx.clone(),
+            recording: self.recording.clone(),
+        }
+    }
+}
+
+impl RecordingInterpreterHandle {
+    /// Returns the effects interpreted so far.
+    pub fn effects(&self) -> Vec<RecordedEffect> {
+        self.recording.lock().unwrap().effects.clone()
+    }
+
+    /// Returns the effects interpreted so far and forgets about them.
+    pub fn take_effects(&self) -> Vec<RecordedEffect> {
+        std::mem::take(&mut self.recording.lock().unwrap().effects)
+    }
+
+    /// Lets the next effect to be interpreted fail with the given message. Failing
+    /// playback requests are reported as exhausted.
+    pub fn fail_next(&self, msg: &str) {
+        self.recording
+            .lock()
+            .unwrap()
+            .failures
+            .push_back(msg.to_string());
+    }
+
+    pub fn start_track(&self, track: usize, offset: Duration, duration: Option<Duration>) {
+        let recording = self.recording.lock().unwrap();
+        recording.emit(
+            &self.playback_tx,
+            PlaybackEventKind::TrackStarted {
+                track,
+                offset,
+                duration,
+            },
+        );
+    }
+
+    pub fn finish_track(&self, track: usize) {
+        let recording = self.recording.lock().unwrap();
+        recording.emit(
+            &self.playback_tx,
+            PlaybackEventKind::TrackFinished { track },
+        );
+    }
+
+    /// Reports the current playback as completed.
+    pub fn complete_playback(&self) {
+        let recording = self.recording.lock().unwrap();
+        recording.emit(&self.playback_tx, PlaybackEventKind::QueueExhausted);
+    }
+
+    pub fn decode_error(&self, track: usize, error: &str) {
+        let recording = self.recording.lock().unwrap();
+        recording.emit(
+            &self.playback_tx,
+            PlaybackEventKind::DecodeError {
+                track,
+                error: error.to_string(),
+            },
+        );
+    }
+}
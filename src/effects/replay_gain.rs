@@ -0,0 +1,443 @@
+use anyhow::{Context, Result};
+use crossbeam_channel::{Receiver, Sender};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, StandardTagKey, Tag};
+use symphonia::core::probe::Hint;
+use tracing::{debug, info, warn};
+
+use crate::components::json_file;
+use crate::effects::loudness;
+use crate::model::config::ReplayGainMode;
+
+// Loudness tracks are normalized to, as defined by ReplayGain 2.0.
+const REFERENCE_LOUDNESS: f64 = -18.0;
+// Stored in the audio base directory.
+const CACHE_FILE_NAME: &str = ".loudness-cache.json";
+
+// A gain in dB.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Decibels(pub f32);
+
+impl Decibels {
+    pub fn factor(self) -> f32 {
+        10f32.powf(self.0 / 20.0)
+    }
+}
+
+impl PartialEq for Decibels {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.to_bits() == other.0.to_bits()
+    }
+}
+
+impl Eq for Decibels {}
+
+// How the tracks of a playlist are normalized.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Normalization {
+    pub mode: ReplayGainMode,
+    // Overrides the gain determined for each track.
+    pub gain: Option<Decibels>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TrackGain {
+    gain: Decibels,
+    peak: Option<f32>,
+}
+
+impl TrackGain {
+    // Lowers the gain if necessary to prevent clipping.
+    fn factor(self) -> f32 {
+        let factor = self.gain.factor();
+        match self.peak {
+            Some(peak) if peak > 0.0 => factor.min(1.0 / peak),
+            _ => factor,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct TagGains {
+    track: Option<TrackGain>,
+    album: Option<TrackGain>,
+}
+
+// Parses tag values like "-6.48 dB".
+fn parse_tag_value(value: &str) -> Option<f32> {
+    let value = value.trim();
+    let value = value
+        .strip_suffix("dB")
+        .or_else(|| value.strip_suffix("db"))
+        .unwrap_or(value);
+    value.trim().parse().ok()
+}
+
+impl TagGains {
+    fn from_tags<'a>(tags: impl Iterator<Item = (&'a str, &'a str)>) -> Self {
+        let mut track_gain = None;
+        let mut track_peak = None;
+        let mut album_gain = None;
+        let mut album_peak = None;
+        for (key, value) in tags {
+            let slot = match key.to_ascii_uppercase().as_str() {
+                "REPLAYGAIN_TRACK_GAIN" => &mut track_gain,
+                "REPLAYGAIN_TRACK_PEAK" => &mut track_peak,
+                "REPLAYGAIN_ALBUM_GAIN" => &mut album_gain,
+                "REPLAYGAIN_ALBUM_PEAK" => &mut album_peak,
+                _ => continue,
+            };
+            *slot = parse_tag_value(value);
+        }
+        TagGains {
+            track: track_gain.map(|gain| TrackGain {
+                gain: Decibels(gain),
+                peak: track_peak,
+            }),
+            album: album_gain.map(|gain| TrackGain {
+                gain: Decibels(gain),
+                peak: album_peak,
+            }),
+        }
+    }
+
+    fn read_flac(path: &Path) -> Result<Self> {
+        let reader = claxon::FlacReader::open(path).context("reading FLAC metadata")?;
+        Ok(Self::from_tags(reader.tags()))
+    }
+
+    fn read_vorbis(path: &Path) -> Result<Self> {
+        let file = File::open(path)?;
+        let reader = lewton::inside_ogg::OggStreamReader::new(BufReader::new(file))
+            .context("reading Vorbis headers")?;
+        let tags = reader.comment_hdr.comment_list.iter();
+        Ok(Self::from_tags(
+            tags.map(|(key, value)| (key.as_str(), value.as_str())),
+        ))
+    }
+
+    fn read_mp3(path: &Path) -> Result<Self> {
+        let file = File::open(path)?;
+        let stream = MediaSourceStream::new(Box::new(file), Default::default());
+        let mut hint = Hint::new();
+        hint.with_extension("mp3");
+        let mut probed = symphonia::default::get_probe()
+            .format(
+                &hint,
+                stream,
+                &FormatOptions::default(),
+                &MetadataOptions::default(),
+            )
+            .context("probing MP3 file")?;
+        // ID3v2 tags preceding the audio data are read while probing, others are part of
+        // the container.
+        let mut tags: Vec<Tag> = Vec::new();
+        if let Some(revision) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+            tags.extend(revision.tags().iter().cloned());
+        }
+        if let Some(revision) = probed.format.metadata().current() {
+            tags.extend(revision.tags().iter().cloned());
+        }
+        let tags: Vec<(&str, String)> = tags
+            .iter()
+            .filter_map(|tag| {
+                let key = match tag.std_key? {
+                    StandardTagKey::ReplayGainTrackGain => "REPLAYGAIN_TRACK_GAIN",
+                    StandardTagKey::ReplayGainTrackPeak => "REPLAYGAIN_TRACK_PEAK",
+                    StandardTagKey::ReplayGainAlbumGain => "REPLAYGAIN_ALBUM_GAIN",
+                    StandardTagKey::ReplayGainAlbumPeak => "REPLAYGAIN_ALBUM_PEAK",
+                    _ => return None,
+                };
+                Some((key, tag.value.to_string()))
+            })
+            .collect();
+        Ok(Self::from_tags(
+            tags.iter().map(|(key, value)| (*key, value.as_str())),
+        ))
+    }
+
+    fn read(path: &Path) -> Result<Self> {
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("flac") => Self::read_flac(path),
+            Some("ogg") | Some("oga") => Self::read_vorbis(path),
+            Some("mp3") => Self::read_mp3(path),
+            _ => Ok(TagGains::default()),
+        }
+    }
+}
+
+// Result of a loudness analysis, along with the size and modification time of the
+// analyzed file for detecting changes.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct CacheEntry {
+    size: u64,
+    modified: u64,
+    loudness: Option<f64>,
+    peak: f32,
+}
+
+impl CacheEntry {
+    fn file_stamp(path: &Path) -> Result<(u64, u64)> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Ok((metadata.len(), modified))
+    }
+
+    fn is_fresh(&self, path: &Path) -> bool {
+        match Self::file_stamp(path) {
+            Ok(stamp) => stamp == (self.size, self.modified),
+            Err(_) => false,
+        }
+    }
+
+    fn track_gain(&self) -> TrackGain {
+        TrackGain {
+            gain: Decibels(
+                self.loudness
+                    .map(|loudness| REFERENCE_LOUDNESS - loudness)
+                    .unwrap_or(0.0) as f32,
+            ),
+            peak: Some(self.peak),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Cache {
+    entries: HashMap<String, CacheEntry>,
+    // Files queued for analysis.
+    pending: HashSet<String>,
+}
+
+// Determines the gain for files from their ReplayGain tags. Files without such tags are
+// analyzed in the background, the results are cached in the audio base directory.
+#[derive(Debug, Clone)]
+pub struct ReplayGain {
+    base_dir: PathBuf,
+    cache: Arc<Mutex<Cache>>,
+    analysis_tx: Sender<PathBuf>,
+}
+
+impl ReplayGain {
+    fn cache_file(base_dir: &Path) -> PathBuf {
+        base_dir.join(CACHE_FILE_NAME)
+    }
+
+    fn load(file: &Path) -> Result<HashMap<String, CacheEntry>> {
+        Ok(json_file::load(file, "loudness cache")?.unwrap_or_default())
+    }
+
+    fn persist(file: &Path, entries: &HashMap<String, CacheEntry>) -> Result<()> {
+        json_file::store(file, entries, "loudness cache")
+    }
+
+    pub fn new(base_dir: &Path) -> Result<Self> {
+        let entries = match Self::load(&Self::cache_file(base_dir)) {
+            Ok(entries) => entries,
+            Err(err) => {
+                warn!("Discarding loudness cache: {:#}", err);
+                HashMap::new()
+            }
+        };
+        let cache = Arc::new(Mutex::new(Cache {
+            entries,
+            pending: HashSet::new(),
+        }));
+        let (analysis_tx, analysis_rx) = crossbeam_channel::unbounded();
+        {
+            let base_dir = base_dir.to_path_buf();
+            let cache = cache.clone();
+            std::thread::Builder::new()
+                .name("loudness-analyzer".to_string())
+                .spawn(move || Self::run_analyzer(base_dir, cache, analysis_rx))
+                .context("Spawning loudness analyzer")?;
+        }
+        Ok(ReplayGain {
+            base_dir: base_dir.to_path_buf(),
+            cache,
+            analysis_tx,
+        })
+    }
+
+    fn cache_key(base_dir: &Path, path: &Path) -> String {
+        path.strip_prefix(base_dir)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn analyze(path: &Path) -> Result<CacheEntry> {
+        let (size, modified) = CacheEntry::file_stamp(path)?;
+        let file = File::open(path)?;
+        let source = rodio::Decoder::new(BufReader::new(file)).context("decoding audio file")?;
+        let loudness = loudness::measure(source);
+        Ok(CacheEntry {
+            size,
+            modified,
+            loudness: loudness.integrated,
+            peak: loudness.peak,
+        })
+    }
+
+    fn run_analyzer(base_dir: PathBuf, cache: Arc<Mutex<Cache>>, analysis_rx: Receiver<PathBuf>) {
+        let cache_file = Self::cache_file(&base_dir);
+        for path in analysis_rx {
+            let key = Self::cache_key(&base_dir, &path);
+            debug!("Analyzing loudness of {}", path.display());
+            let res = Self::analyze(&path);
+            let mut cache = cache.lock().unwrap();
+            cache.pending.remove(&key);
+            match res {
+                Ok(entry) => {
+                    info!(
+                        "Analyzed loudness of {}: {:?} LUFS, peak {}",
+                        path.display(),
+                        entry.loudness,
+                        entry.peak
+                    );
+                    cache.entries.insert(key, entry);
+                    if let Err(err) = Self::persist(&cache_file, &cache.entries) {
+                        warn!("Failed to persist loudness cache: {:#}", err);
+                    }
+                }
+                Err(err) => warn!(
+                    "Failed to analyze loudness of {}: {:#}",
+                    path.display(),
+                    err
+                ),
+            }
+        }
+    }
+
+    // Returns the cached analysis result for the given file. If there is none, the file
+    // is queued for analysis.
+    fn analyzed_gain(&self, path: &Path) -> Option<TrackGain> {
+        let key = Self::cache_key(&self.base_dir, path);
+        let mut cache = self.cache.lock().unwrap();
+        if let Some(entry) = cache.entries.get(&key) {
+            if entry.is_fresh(path) {
+                return Some(entry.track_gain());
+            }
+        }
+        if cache.pending.insert(key) {
+            debug!("Queueing {} for loudness analysis", path.display());
+            if let Err(err) = self.analysis_tx.send(path.to_path_buf()) {
+                warn!("Failed to queue loudness analysis: {}", err);
+            }
+        }
+        None
+    }
+
+    // Returns the factor for amplifying the given file with.
+    pub fn factor(&self, path: &Path, normalization: Normalization) -> f32 {
+        if let Some(gain) = normalization.gain {
+            return gain.factor();
+        }
+        let tags = match normalization.mode {
+            ReplayGainMode::Off => return 1.0,
+            ReplayGainMode::Track | ReplayGainMode::Album => {
+                TagGains::read(path).unwrap_or_else(|err| {
+                    warn!(
+                        "Failed to read ReplayGain tags of {}: {:#}",
+                        path.display(),
+                        err
+                    );
+                    TagGains::default()
+                })
+            }
+        };
+        let tagged = match normalization.mode {
+            ReplayGainMode::Album => tags.album.or(tags.track),
+            _ => tags.track.or(tags.album),
+        };
+        match tagged.or_else(|| self.analyzed_gain(path)) {
+            Some(gain) => {
+                debug!("Applying gain {:?} to {}", gain, path.display());
+                gain.factor()
+            }
+            None => 1.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_tag_values() {
+        assert_eq!(parse_tag_value("-6.48 dB"), Some(-6.48));
+        assert_eq!(parse_tag_value("+2.5dB"), Some(2.5));
+        assert_eq!(parse_tag_value(" 1.25 db "), Some(1.25));
+        assert_eq!(parse_tag_value("0.988553"), Some(0.988553));
+        assert_eq!(parse_tag_value("loud"), None);
+        assert_eq!(parse_tag_value(""), None);
+    }
+
+    #[test]
+    fn reads_gains_from_tags() {
+        let tags = [
+            ("replaygain_track_gain", "-6.00 dB"),
+            ("REPLAYGAIN_TRACK_PEAK", "0.5"),
+            ("ReplayGain_Album_Gain", "-3.00 dB"),
+            ("ARTIST", "-1.00 dB"),
+        ];
+        let gains = TagGains::from_tags(tags.iter().copied());
+        assert_eq!(
+            gains.track,
+            Some(TrackGain {
+                gain: Decibels(-6.0),
+                peak: Some(0.5),
+            })
+        );
+        assert_eq!(
+            gains.album,
+            Some(TrackGain {
+                gain: Decibels(-3.0),
+                peak: None,
+            })
+        );
+    }
+
+    #[test]
+    fn peaks_without_gains_are_ignored() {
+        let tags = [
+            ("REPLAYGAIN_TRACK_PEAK", "0.5"),
+            ("REPLAYGAIN_ALBUM_GAIN", "n/a"),
+        ];
+        let gains = TagGains::from_tags(tags.iter().copied());
+        assert_eq!(gains.track, None);
+        assert_eq!(gains.album, None);
+    }
+
+    #[test]
+    fn gain_is_limited_by_the_peak() {
+        let gain = TrackGain {
+            gain: Decibels(6.0),
+            peak: Some(0.8),
+        };
+        assert_eq!(gain.factor(), 1.25);
+        let gain = TrackGain {
+            gain: Decibels(-6.0),
+            peak: Some(0.8),
+        };
+        assert!((gain.factor() - 0.501).abs() < 0.001);
+    }
+}
@@ -0,0 +1,455 @@
+use anyhow::{Context, Result};
+use std::cmp::Ordering;
+use std::fmt;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use tracing::{debug, warn};
+use url::Url;
+
+use crate::effects::http_stream;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Track {
+    File(PathBuf),
+    Stream(Url),
+}
+
+impl fmt::Display for Track {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Track::File(path) => write!(f, "{}", path.display()),
+            Track::Stream(url) => write!(f, "{}", url),
+        }
+    }
+}
+
+// File extensions of the audio formats supported by the decoder.
+const SUPPORTED_EXTENSIONS: &[&str] = &["mp3", "ogg", "oga", "flac", "wav"];
+
+pub fn is_supported_audio_file(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| {
+                SUPPORTED_EXTENSIONS
+                    .iter()
+                    .any(|supported| supported.eq_ignore_ascii_case(ext))
+            })
+            .unwrap_or(false)
+}
+
+// Only meaningful for URIs which do not name an existing file, since file names may
+// contain these characters as well.
+pub fn is_glob(uri: &str) -> bool {
+    uri.contains(['*', '?', '['])
+}
+
+// Resolves `.` and `..` components without touching the file system, so that symlinks
+// within the base directory keep working. Leading `..` components of relative paths
+// are kept.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                // `..` of the root directory is the root directory itself.
+                Some(Component::RootDir) | Some(Component::Prefix(_)) => {}
+                _ => normalized.push(component),
+            },
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+// Returns true if `path` lies within `base_dir`.
+pub fn is_within(base_dir: &Path, path: &Path) -> bool {
+    normalize(path).starts_with(normalize(base_dir))
+}
+
+// Compares strings such that embedded numbers are ordered by their numeric value,
+// e.g. "track2.mp3" < "track10.mp3".
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let mut x_digits = String::new();
+                while let Some(c) = a.next_if(|c| c.is_ascii_digit()) {
+                    x_digits.push(c);
+                }
+                let mut y_digits = String::new();
+                while let Some(c) = b.next_if(|c| c.is_ascii_digit()) {
+                    y_digits.push(c);
+                }
+                let x_trimmed = x_digits.trim_start_matches('0');
+                let y_trimmed = y_digits.trim_start_matches('0');
+                let ord = x_trimmed
+                    .len()
+                    .cmp(&y_trimmed.len())
+                    .then_with(|| x_trimmed.cmp(y_trimmed))
+                    .then_with(|| x_digits.len().cmp(&y_digits.len()));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ord = x
+                    .to_lowercase()
+                    .cmp(y.to_lowercase())
+                    .then_with(|| x.cmp(&y));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+fn natural_sort(paths: &mut [PathBuf]) {
+    paths.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+}
+
+// Expands a directory into the naturally sorted list of supported audio files it
+// contains. Subdirectories are not descended into.
+pub fn expand_dir(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut tracks = Vec::new();
+    let entries =
+        fs::read_dir(dir).with_context(|| format!("Reading directory {}", dir.display()))?;
+    for entry in entries {
+        let path = entry
+            .with_context(|| format!("Reading directory {}", dir.display()))?
+            .path();
+        if is_supported_audio_file(&path) {
+            tracks.push(path);
+        }
+    }
+    natural_sort(&mut tracks);
+    debug!("Expanded directory {} to {:?}", dir.display(), tracks);
+    Ok(tracks)
+}
+
+// Expands a glob pattern, relative to `base_dir`, into the naturally sorted list of
+// supported audio files matching it.
+pub fn expand_glob(base_dir: &Path, pattern: &str) -> Result<Vec<PathBuf>> {
+    let pattern = pattern.trim_start_matches('/');
+    let full_pattern = format!(
+        "{}/{}",
+        glob::Pattern::escape(&base_dir.to_string_lossy()),
+        pattern
+    );
+    let mut tracks = Vec::new();
+    for entry in
+        glob::glob(&full_pattern).with_context(|| format!("Parsing glob pattern {}", pattern))?
+    {
+        match entry {
+            Ok(path) if is_supported_audio_file(&path) => tracks.push(path),
+            Ok(path) => debug!("Skipping unsupported file {}", path.display()),
+            Err(err) => warn!("Failed to expand glob pattern {}: {}", pattern, err),
+        }
+    }
+    natural_sort(&mut tracks);
+    debug!("Expanded glob pattern {} to {:?}", pattern, tracks);
+    Ok(tracks)
+}
+
+// File extensions of the supported playlist formats.
+const PLAYLIST_EXTENSIONS: &[&str] = &["m3u", "m3u8", "pls"];
+
+pub fn is_playlist_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| {
+            PLAYLIST_EXTENSIONS
+                .iter()
+                .any(|supported| supported.eq_ignore_ascii_case(ext))
+        })
+        .unwrap_or(false)
+}
+
+// Extracts the entries of an M3U or M3U8 playlist. Extended M3U directives and
+// comments are skipped.
+pub fn parse_m3u(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .map(|line| line.trim().trim_start_matches('\u{feff}'))
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_string())
+        .collect()
+}
+
+// Extracts the entries of a PLS playlist, ordered by their index.
+pub fn parse_pls(content: &str) -> Vec<String> {
+    let mut entries: Vec<(u32, String)> = content
+        .lines()
+        .map(|line| line.trim().trim_start_matches('\u{feff}'))
+        .filter_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            let index = key.trim().strip_prefix("File")?.parse().ok()?;
+            Some((index, value.trim().to_string()))
+        })
+        .collect();
+    entries.sort_by_key(|(index, _)| *index);
+    entries.into_iter().map(|(_, entry)| entry).collect()
+}
+
+// Converts a playlist entry into a track. Relative entries are resolved against the
+// directory containing the playlist.
+fn playlist_entry(playlist_dir: &Path, entry: &str) -> Option<Track> {
+    if http_stream::is_stream_uri(entry) {
+        return Url::parse(entry).ok().map(Track::Stream);
+    }
+    if entry.starts_with("file://") {
+        return Url::parse(entry).ok()?.to_file_path().ok().map(Track::File);
+    }
+    if entry.contains("://") {
+        return None;
+    }
+    // Playlists exported on Windows use backslashes as path separators.
+    let entry = entry.replace('\\', "/");
+    let path = Path::new(&entry);
+    if path.is_absolute() {
+        Some(Track::File(path.to_path_buf()))
+    } else {
+        Some(Track::File(playlist_dir.join(path)))
+    }
+}
+
+// Loads an M3U, M3U8 or PLS playlist file and returns the list of tracks it references.
+// Files outside of `base_dir` are skipped.
+pub fn load_playlist(base_dir: &Path, path: &Path) -> Result<Vec<Track>> {
+    let content = fs::read(path).with_context(|| format!("Reading playlist {}", path.display()))?;
+    // M3U files are not necessarily UTF-8 encoded.
+    let content = String::from_utf8_lossy(&content);
+    let is_pls = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.eq_ignore_ascii_case("pls"))
+        .unwrap_or(false);
+    let entries = if is_pls {
+        parse_pls(&content)
+    } else {
+        parse_m3u(&content)
+    };
+    let playlist_dir = path.parent().unwrap_or_else(|| Path::new("/"));
+
+    let mut tracks = Vec::new();
+    for entry in entries {
+        match playlist_entry(playlist_dir, &entry) {
+            Some(Track::File(file)) if !is_within(base_dir, &file) => warn!(
+                "Skipping {} referenced in playlist {}, not within audio base directory {}",
+                file.display(),
+                path.display(),
+                base_dir.display()
+            ),
+            Some(Track::File(file)) if !file.is_file() => warn!(
+                "Skipping missing file {} referenced in playlist {}",
+                file.display(),
+                path.display()
+            ),
+            Some(track) => tracks.push(track),
+            None => warn!(
+                "Skipping unsupported entry {} in playlist {}",
+                entry,
+                path.display()
+            ),
+        }
+    }
+    debug!("Loaded playlist {}: {:?}", path.display(), tracks);
+    Ok(tracks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn natural_order() {
+        let cases = [
+            ("track2.mp3", "track10.mp3", Ordering::Less),
+            ("track10.mp3", "track9.mp3", Ordering::Greater),
+            ("track02.mp3", "track2.mp3", Ordering::Greater),
+            ("track02.mp3", "track3.mp3", Ordering::Less),
+            ("track002.mp3", "track10.mp3", Ordering::Less),
+            ("Track1.mp3", "track2.mp3", Ordering::Less),
+            ("track1.mp3", "Track1.mp3", Ordering::Greater),
+            ("b.mp3", "A.mp3", Ordering::Greater),
+            ("1/10.mp3", "1/9.mp3", Ordering::Greater),
+            ("cd1/track10.mp3", "cd2/track1.mp3", Ordering::Less),
+            ("track.mp3", "track1.mp3", Ordering::Less),
+            ("track1.mp3", "track1.mp3", Ordering::Equal),
+            ("", "", Ordering::Equal),
+        ];
+        for (a, b, expected) in cases.iter() {
+            assert_eq!(natural_cmp(a, b), *expected, "{} <=> {}", a, b);
+            assert_eq!(natural_cmp(b, a), expected.reverse(), "{} <=> {}", b, a);
+        }
+    }
+
+    #[test]
+    fn natural_sort_orders_paths() {
+        let mut paths: Vec<PathBuf> = ["t10.mp3", "T1.mp3", "t2.mp3", "t01.mp3"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        natural_sort(&mut paths);
+        assert_eq!(
+            paths,
+            ["T1.mp3", "t01.mp3", "t2.mp3", "t10.mp3"]
+                .iter()
+                .map(PathBuf::from)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn m3u_entries() {
+        let content = "\u{feff}#EXTM3U\r\n\
+                       #EXTINF:123,Artist - First\r\n\
+                       first.mp3\r\n\
+                       \r\n\
+                       # A comment\r\n\
+                       #EXTINF:-1,Radio\r\n\
+                       https://radio.example.org/stream.mp3\r\n\
+                       \t sub dir/second.ogg \r\n\
+                       /music/third.flac";
+        assert_eq!(
+            parse_m3u(content),
+            vec![
+                "first.mp3",
+                "https://radio.example.org/stream.mp3",
+                "sub dir/second.ogg",
+                "/music/third.flac",
+            ]
+        );
+        assert_eq!(
+            parse_m3u("\u{feff}first.mp3\nsecond.mp3\n"),
+            vec!["first.mp3", "second.mp3"]
+        );
+        assert!(parse_m3u("#EXTM3U\n").is_empty());
+    }
+
+    #[test]
+    fn pls_entries() {
+        let content = "\u{feff}[playlist]\r\n\
+                       File10=tenth.mp3\r\n\
+                       Title10=Tenth\r\n\
+                       File2 = second.mp3\r\n\
+                       File1=first.mp3\r\n\
+                       Length1=123\r\n\
+                       FileX=invalid.mp3\r\n\
+                       NumberOfEntries=3\r\n\
+                       Version=2\r\n";
+        assert_eq!(
+            parse_pls(content),
+            vec!["first.mp3", "second.mp3", "tenth.mp3"]
+        );
+    }
+
+    #[test]
+    fn playlist_entries_are_resolved() {
+        let dir = Path::new("/music/playlists");
+        let cases = [
+            ("a.mp3", Some("/music/playlists/a.mp3")),
+            ("../albums/a.mp3", Some("/music/playlists/../albums/a.mp3")),
+            ("albums\\a.mp3", Some("/music/playlists/albums/a.mp3")),
+            ("/music/a.mp3", Some("/music/a.mp3")),
+            ("/etc/passwd", Some("/etc/passwd")),
+            ("file:///music/a%20b.mp3", Some("/music/a b.mp3")),
+            ("ftp://example.org/a.mp3", None),
+        ];
+        for (entry, expected) in cases.iter() {
+            assert_eq!(
+                playlist_entry(dir, entry),
+                expected.map(|file| Track::File(PathBuf::from(file))),
+                "{}",
+                entry
+            );
+        }
+        assert_eq!(
+            playlist_entry(dir, "http://radio.example.org/stream"),
+            Some(Track::Stream(
+                Url::parse("http://radio.example.org/stream").unwrap()
+            ))
+        );
+    }
+
+    #[test]
+    fn playlists_are_confined_to_base_dir() {
+        let base_dir =
+            std::env::temp_dir().join(format!("rustberry-track-list-{}", std::process::id()));
+        let music_dir = base_dir.join("music");
+        fs::create_dir_all(music_dir.join("albums")).unwrap();
+        fs::write(music_dir.join("albums/a.mp3"), b"").unwrap();
+        fs::write(music_dir.join("b.mp3"), b"").unwrap();
+        fs::write(base_dir.join("outside.mp3"), b"").unwrap();
+        let playlist = music_dir.join("albums/list.m3u8");
+        let content = format!(
+            "a.mp3\n../b.mp3\nmissing.mp3\n../../outside.mp3\n{}\n{}\n",
+            music_dir.join("b.mp3").display(),
+            base_dir.join("outside.mp3").display(),
+        );
+        fs::write(&playlist, content).unwrap();
+
+        let tracks = load_playlist(&music_dir, &playlist).unwrap();
+        let files: Vec<PathBuf> = tracks
+            .into_iter()
+            .map(|track| match track {
+                Track::File(file) => normalize(&file),
+                Track::Stream(url) => panic!("unexpected stream {}", url),
+            })
+            .collect();
+        assert_eq!(
+            files,
+            vec![
+                music_dir.join("albums/a.mp3"),
+                music_dir.join("b.mp3"),
+                music_dir.join("b.mp3"),
+            ]
+        );
+
+        fs::remove_dir_all(&base_dir).unwrap();
+    }
+
+    #[test]
+    fn paths_within_base_dir() {
+        let cases = [
+            ("/music", "/music/a.mp3", true),
+            ("/music/", "/music/a.mp3", true),
+            ("/music", "/music/", true),
+            ("/music", "/music/album/./a.mp3", true),
+            ("/music", "/music/album/../a.mp3", true),
+            ("/music/", "/music/../music/a.mp3", true),
+            ("/music", "/music/../a.mp3", false),
+            ("/music", "/music/album/../../etc/passwd", false),
+            ("/music", "/music/../../../music/a.mp3", true),
+            ("/music", "/musicbox/a.mp3", false),
+            ("/music", "/etc/passwd", false),
+            ("/music", "/", false),
+            ("/music/./", "/music/a.mp3", true),
+            ("music", "music/a.mp3", true),
+            ("music", "music/../../music/a.mp3", false),
+            ("music", "../music/a.mp3", false),
+        ];
+        for (base_dir, path, expected) in cases.iter() {
+            assert_eq!(
+                is_within(Path::new(base_dir), Path::new(path)),
+                *expected,
+                "{} within {}",
+                path,
+                base_dir
+            );
+        }
+    }
+}
@@ -0,0 +1,420 @@
+use rodio::source::SeekError;
+use rodio::Source;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::debug;
+
+pub type BoxedSource = Box<dyn Source<Item = i16> + Send>;
+
+// A decoder together with the samples it has decoded ahead of playback.
+pub struct Decoded {
+    source: BoxedSource,
+    buffer: VecDeque<i16>,
+    exhausted: bool,
+    // Samples already played as part of a crossfade with the preceding track.
+    played: usize,
+}
+
+impl Decoded {
+    // Decodes the beginning of the source right away, so that the audio thread does not
+    // have to wait for the decoder once playback reaches the track.
+    pub fn prefetch(source: BoxedSource, duration: Duration) -> Self {
+        let mut decoded = Decoded {
+            source,
+            buffer: VecDeque::new(),
+            exhausted: false,
+            played: 0,
+        };
+        let samples = decoded.samples(duration);
+        decoded.fill(samples, samples);
+        decoded
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    // Number of samples making up the given duration, rounded down to whole frames.
+    fn samples(&self, duration: Duration) -> usize {
+        let channels = self.channels().max(1) as usize;
+        let frames = duration.as_secs_f64() * self.sample_rate() as f64;
+        frames as usize * channels
+    }
+
+    fn duration(&self, samples: usize) -> Duration {
+        let per_sec = self.sample_rate() as f64 * self.channels() as f64;
+        if per_sec <= 0.0 {
+            return Duration::from_secs(0);
+        }
+        Duration::from_secs_f64(samples as f64 / per_sec)
+    }
+
+    // Reads from the source until `len` samples are buffered, reading at most `max_reads`
+    // samples.
+    fn fill(&mut self, len: usize, max_reads: usize) {
+        for _ in 0..max_reads {
+            if self.exhausted || self.buffer.len() >= len {
+                break;
+            }
+            match self.source.next() {
+                Some(sample) => self.buffer.push_back(sample),
+                None => self.exhausted = true,
+            }
+        }
+    }
+
+    fn next_sample(&mut self) -> Option<i16> {
+        self.fill(1, 1);
+        self.buffer.pop_front()
+    }
+}
+
+impl fmt::Debug for Decoded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Decoded")
+            .field("channels", &self.channels())
+            .field("sample_rate", &self.sample_rate())
+            .field("buffered", &self.buffer.len())
+            .field("exhausted", &self.exhausted)
+            .finish()
+    }
+}
+
+// Passes the decoder of a track on from the preceding track, which may play its
+// beginning as part of a crossfade.
+pub type Handover = Arc<Mutex<Option<Decoded>>>;
+
+struct Crossfade {
+    len: usize,
+    successor: Decoded,
+}
+
+type OnStart = Box<dyn FnOnce(Duration) + Send>;
+type OnFinish = Box<dyn FnOnce() + Send>;
+
+// A single track in the sink. The end of the track is mixed with the beginning of the
+// following one if a crossfade is configured. Start and end are announced via callbacks,
+// since with crossfading they no longer coincide with the boundaries between sources in
+// the sink.
+pub struct TrackSource {
+    decoded: Option<Decoded>,
+    // Where the decoder is taken from, if it is handed over by the preceding track.
+    incoming: Option<Handover>,
+    // Receives the decoder of the following track, once that has been queued.
+    successor: Handover,
+    // Number of samples at the end of this track to be mixed with the beginning of the
+    // following track.
+    crossfade_len: usize,
+    crossfade: Option<Crossfade>,
+    crossfade_checked: bool,
+    channels: u16,
+    sample_rate: u32,
+    // Called with the duration of the track played during the preceding crossfade.
+    on_start: Option<OnStart>,
+    on_finish: Option<OnFinish>,
+}
+
+impl TrackSource {
+    fn with_format(channels: u16, sample_rate: u32, crossfade: Duration) -> Self {
+        let frames = crossfade.as_secs_f64() * sample_rate as f64;
+        TrackSource {
+            decoded: None,
+            incoming: None,
+            successor: Arc::new(Mutex::new(None)),
+            crossfade_len: frames as usize * channels as usize,
+            crossfade: None,
+            crossfade_checked: false,
+            channels,
+            sample_rate,
+            on_start: None,
+            on_finish: None,
+        }
+    }
+
+    pub fn new(decoded: Decoded, crossfade: Duration) -> Self {
+        let mut track = Self::with_format(decoded.channels(), decoded.sample_rate(), crossfade);
+        track.decoded = Some(decoded);
+        track
+    }
+
+    // Creates a track following the one owning `handover`. The decoder is offered to the
+    // preceding track for crossfading first.
+    pub fn after(handover: &Handover, decoded: Decoded, crossfade: Duration) -> Self {
+        let mut track = Self::with_format(decoded.channels(), decoded.sample_rate(), crossfade);
+        *handover.lock().unwrap() = Some(decoded);
+        track.incoming = Some(handover.clone());
+        track
+    }
+
+    pub fn handover(&self) -> Handover {
+        self.successor.clone()
+    }
+
+    pub fn on_start(mut self, on_start: OnStart) -> Self {
+        self.on_start = Some(on_start);
+        self
+    }
+
+    pub fn on_finish(mut self, on_finish: OnFinish) -> Self {
+        self.on_finish = Some(on_finish);
+        self
+    }
+
+    // Starts the crossfade if the following track has been queued in time and can be
+    // mixed with this one.
+    fn begin_crossfade(&mut self, len: usize) {
+        self.crossfade_checked = true;
+        let mut successor = self.successor.lock().unwrap();
+        let compatible = match successor.as_ref() {
+            Some(decoded) => {
+                decoded.channels() == self.channels && decoded.sample_rate() == self.sample_rate
+            }
+            None => {
+                debug!("TrackSource: following track not queued in time, not crossfading");
+                return;
+            }
+        };
+        if !compatible {
+            debug!("TrackSource: following track differs in format, not crossfading");
+            return;
+        }
+        if let Some(successor) = successor.take() {
+            self.crossfade = Some(Crossfade { len, successor });
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(crossfade) = self.crossfade.take() {
+            *self.successor.lock().unwrap() = Some(crossfade.successor);
+        }
+        if let Some(on_finish) = self.on_finish.take() {
+            on_finish();
+        }
+    }
+}
+
+impl Iterator for TrackSource {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        if let Some(incoming) = self.incoming.take() {
+            self.decoded = incoming.lock().unwrap().take();
+        }
+        // Without a decoder, the preceding track has been cut off during the crossfade.
+        let decoded = self.decoded.as_mut()?;
+        if let Some(on_start) = self.on_start.take() {
+            on_start(decoded.duration(decoded.played));
+        }
+
+        // Keep the samples of the crossfade buffered, so that the end of the track is
+        // known before it is reached. Reading two samples at a time catches up with
+        // playback gradually.
+        decoded.fill(self.crossfade_len + 1, 2);
+        let sample = match decoded.buffer.pop_front() {
+            Some(sample) => sample,
+            None => {
+                self.finish();
+                return None;
+            }
+        };
+        let remaining = decoded.buffer.len() + 1;
+        let channels = self.channels.max(1) as usize;
+        if !self.crossfade_checked
+            && decoded.exhausted
+            && remaining <= self.crossfade_len
+            && remaining % channels == 0
+        {
+            self.begin_crossfade(remaining);
+        }
+
+        match self.crossfade.as_mut() {
+            Some(crossfade) => {
+                let incoming = match crossfade.successor.next_sample() {
+                    Some(incoming) => {
+                        crossfade.successor.played += 1;
+                        incoming
+                    }
+                    None => 0,
+                };
+                let fade_out = remaining as f32 / crossfade.len as f32;
+                let mixed = sample as f32 * fade_out + incoming as f32 * (1.0 - fade_out);
+                Some(mixed.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16)
+            }
+            None => Some(sample),
+        }
+    }
+}
+
+impl Source for TrackSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        // Buffered samples belong to the frame the decoder is currently in, or have
+        // been decoded before it.
+        let decoded = self.decoded.as_ref()?;
+        if decoded.exhausted {
+            return Some(decoded.buffer.len());
+        }
+        decoded
+            .source
+            .current_frame_len()
+            .map(|len| len + decoded.buffer.len())
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.decoded
+            .as_ref()
+            .and_then(|decoded| decoded.source.total_duration())
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        let decoded = self.decoded.as_mut().ok_or(SeekError::NotSupported {
+            underlying_source: "TrackSource",
+        })?;
+        decoded.source.try_seek(pos)?;
+        decoded.buffer.clear();
+        decoded.exhausted = false;
+        if let Some(crossfade) = self.crossfade.take() {
+            *self.successor.lock().unwrap() = Some(crossfade.successor);
+        }
+        self.crossfade_checked = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    const RATE: u32 = 1000;
+
+    fn decoded(channels: u16, samples: Vec<i16>) -> Decoded {
+        let source: BoxedSource = Box::new(SamplesBuffer::new(channels, RATE, samples));
+        Decoded::prefetch(source, Duration::from_millis(50))
+    }
+
+    fn ramp(len: usize, offset: i16) -> Vec<i16> {
+        (0..len).map(|i| offset + i as i16).collect()
+    }
+
+    // Plays the tracks one after the other, the way the sink does. Returns the samples
+    // played and the offsets reported when the tracks started.
+    fn play(
+        tracks: Vec<Vec<i16>>,
+        channels: u16,
+        crossfade: Duration,
+    ) -> (Vec<i16>, Vec<Duration>) {
+        let started = Arc::new(Mutex::new(vec![]));
+        let mut sources: Vec<TrackSource> = vec![];
+        for samples in tracks {
+            let decoded = decoded(channels, samples);
+            let source = match sources.last() {
+                Some(previous) => TrackSource::after(&previous.handover(), decoded, crossfade),
+                None => TrackSource::new(decoded, crossfade),
+            };
+            let started = started.clone();
+            sources.push(
+                source.on_start(Box::new(move |offset| started.lock().unwrap().push(offset))),
+            );
+        }
+        let samples = sources.into_iter().flatten().collect();
+        let started = started.lock().unwrap().clone();
+        (samples, started)
+    }
+
+    #[test]
+    fn prefetch_buffers_whole_frames() {
+        let decoded = decoded(2, ramp(1000, 0));
+        assert_eq!(decoded.buffer.len(), 100);
+        assert!(!decoded.exhausted);
+
+        let short = self::decoded(2, ramp(60, 0));
+        assert_eq!(short.buffer.len(), 60);
+        assert!(short.exhausted);
+    }
+
+    #[test]
+    fn gapless_playback_keeps_every_sample() {
+        for channels in [1, 2].iter().copied() {
+            let tracks = vec![ramp(1000, 0), ramp(500, 1000), ramp(30, 2000)];
+            let expected: Vec<i16> = tracks.concat();
+            let (samples, started) = play(tracks, channels, Duration::ZERO);
+            assert_eq!(samples, expected);
+            assert_eq!(started, vec![Duration::ZERO; 3]);
+        }
+    }
+
+    #[test]
+    fn crossfade_overlaps_tracks() {
+        for channels in [1, 2].iter().copied() {
+            let overlap = 100 * channels as usize;
+            let first = vec![1000; 1000];
+            let second = vec![3000; 1000];
+            let (samples, started) =
+                play(vec![first, second], channels, Duration::from_millis(100));
+            assert_eq!(samples.len(), 2000 - overlap);
+            assert_eq!(started, vec![Duration::ZERO, Duration::from_millis(100)]);
+
+            let (before, rest) = samples.split_at(1000 - overlap);
+            let (mixed, after) = rest.split_at(overlap);
+            assert!(before.iter().all(|sample| *sample == 1000));
+            assert!(after.iter().all(|sample| *sample == 3000));
+            assert!(mixed.windows(2).all(|pair| pair[0] <= pair[1]));
+            assert!(mixed[0] < 1100, "{}", mixed[0]);
+            assert!(mixed[overlap - 1] > 2900, "{}", mixed[overlap - 1]);
+        }
+    }
+
+    #[test]
+    fn short_successor_is_played_within_the_crossfade() {
+        let (samples, started) = play(
+            vec![vec![1000; 1000], vec![3000; 40], vec![5000; 100]],
+            1,
+            Duration::from_millis(100),
+        );
+        // The second track is played completely during the crossfade, which fades into
+        // silence for the rest of it. The third track follows without a crossfade.
+        assert_eq!(samples.len(), 1000 + 100);
+        assert_eq!(
+            started,
+            vec![Duration::ZERO, Duration::from_millis(40), Duration::ZERO]
+        );
+        assert_eq!(samples[999], 10);
+        assert!(samples[1000..].iter().all(|sample| *sample == 5000));
+    }
+
+    #[test]
+    fn differing_formats_are_not_crossfaded() {
+        let first = decoded(1, vec![1000; 1000]);
+        let second: BoxedSource = Box::new(SamplesBuffer::new(1, RATE * 2, vec![3000; 1000]));
+        let crossfade = Duration::from_millis(100);
+        let first = TrackSource::new(first, crossfade);
+        let second = TrackSource::after(
+            &first.handover(),
+            Decoded::prefetch(second, Duration::ZERO),
+            crossfade,
+        );
+        let samples: Vec<i16> = first.chain(second).collect();
+        assert_eq!(samples, [vec![1000; 1000], vec![3000; 1000]].concat());
+    }
+
+    #[test]
+    fn crossfade_requires_a_queued_successor() {
+        let track = TrackSource::new(decoded(1, vec![1000; 1000]), Duration::from_millis(100));
+        let samples: Vec<i16> = track.collect();
+        assert_eq!(samples, vec![1000; 1000]);
+    }
+}
@@ -1,20 +1,221 @@
-use std::time::Instant;
+use std::convert::TryFrom;
+use std::time::{Duration, Instant};
 
-use anyhow::{Context, Result};
-use crossbeam_channel::{self, Receiver, Sender};
+use anyhow::{anyhow, Context, Result};
+use crossbeam_channel::{self, Receiver, RecvTimeoutError, Sender};
+use serde::de::IntoDeserializer;
+use serde::Deserialize;
+use tracing::{debug, error};
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+use crate::input_controller::gesture::{Edge, Gesture, GestureDetector, GestureTiming};
+use crate::model;
+
+const DEFAULT_CHIP: &str = "/dev/gpiochip0";
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Command {
     VolumeUp,
     VolumeDown,
     PauseContinue,
+    SleepTimer,
+    NextTrack,
+    PreviousTrack,
+    Stop,
+    SeekForward,
+    SeekBackward,
+    Shutdown,
+}
+
+impl TryFrom<&str> for Command {
+    type Error = anyhow::Error;
+
+    // Parses command names like "sleep_timer".
+    fn try_from(name: &str) -> Result<Self> {
+        let de: serde::de::value::StrDeserializer<serde::de::value::Error> =
+            name.into_deserializer();
+        Command::deserialize(de).map_err(|err| anyhow!("invalid command {:?}: {}", name, err))
+    }
+}
+
+// Bias applied to the line of a button. By default, the line is left as configured.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Bias {
+    #[default]
+    AsIs,
+    PullUp,
+    PullDown,
+    Disabled,
+}
+
+// Buttons are configured in the main configuration as e.g.:
+//
+// buttons:
+//   - line: 14
+//     short_press: pause_continue
+//     long_press: sleep_timer
+//     double_press: shutdown
+//   - chip: /dev/gpiochip0
+//     line: 15
+//     action: volume_up
+//     active_low: false
+//     bias: pull_down
+//     debounce_ms: 50
+// long_press_ms: 1000
+// double_press_ms: 300
+//
+// `action` is short for `short_press`. Buttons are active low by default, i.e. they
+// pull their line low while pressed. Without a buttons section, buttons are configured
+// via environment variables.
+
+// Commands bound to the gestures of a single button, along with the line it is
+// connected to.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Button {
+    #[serde(default = "default_chip")]
+    pub chip: String,
+    pub line: u32,
+    #[serde(default, alias = "action")]
+    pub short_press: Option<Command>,
+    #[serde(default)]
+    pub long_press: Option<Command>,
+    #[serde(default)]
+    pub double_press: Option<Command>,
+    #[serde(default = "default_active_low")]
+    pub active_low: bool,
+    #[serde(default)]
+    pub bias: Bias,
+    // Overrides the default debounce time.
+    #[serde(default)]
+    pub debounce_ms: Option<u64>,
+}
+
+fn default_chip() -> String {
+    DEFAULT_CHIP.to_string()
 }
 
-#[derive(Debug, Clone)]
+fn default_active_low() -> bool {
+    true
+}
+
+impl Button {
+    pub fn new(line: u32) -> Self {
+        Button {
+            chip: default_chip(),
+            line,
+            short_press: None,
+            long_press: None,
+            double_press: None,
+            active_low: default_active_low(),
+            bias: Bias::default(),
+            debounce_ms: None,
+        }
+    }
+
+    pub fn command(&self, gesture: Gesture) -> Option<&Command> {
+        match gesture {
+            Gesture::ShortPress => self.short_press.as_ref(),
+            Gesture::LongPress => self.long_press.as_ref(),
+            Gesture::DoublePress => self.double_press.as_ref(),
+        }
+    }
+
+    fn bind(&mut self, gesture: Gesture, command: Command) {
+        let slot = match gesture {
+            Gesture::ShortPress => &mut self.short_press,
+            Gesture::LongPress => &mut self.long_press,
+            Gesture::DoublePress => &mut self.double_press,
+        };
+        *slot = Some(command);
+    }
+
+    // Translates the level of the line into the state of the button.
+    pub fn edge(&self, high: bool) -> Edge {
+        if high != self.active_low {
+            Edge::Pressed
+        } else {
+            Edge::Released
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct Config {
-    pub volume_up_pin: Option<u32>,
-    pub volume_down_pin: Option<u32>,
-    pub pause_pin: Option<u32>,
+    pub buttons: Vec<Button>,
+    pub timing: GestureTiming,
+}
+
+impl Config {
+    fn bind(&mut self, line: u32, gesture: Gesture, command: Command) {
+        let index = match self.buttons.iter().position(|button| button.line == line) {
+            Some(index) => index,
+            None => {
+                self.buttons.push(Button::new(line));
+                self.buttons.len() - 1
+            }
+        };
+        self.buttons[index].bind(gesture, command);
+    }
+
+    // Returns the button configuration of the main configuration, if it has any.
+    pub fn from_config(config: &model::config::Config) -> Option<Self> {
+        let buttons = config.buttons.clone()?;
+        Some(Config {
+            buttons,
+            timing: GestureTiming {
+                long_press: Duration::from_millis(config.long_press_ms),
+                double_press: Duration::from_millis(config.double_press_ms),
+                ..GestureTiming::default()
+            },
+        })
+    }
+}
+
+// Translates the edges of a button into commands, until the edge channel is closed.
+pub fn run_gesture_detector<T>(
+    button: Button,
+    timing: GestureTiming,
+    edges: Receiver<(Edge, Instant)>,
+    tx: Sender<T>,
+) where
+    T: From<Command>,
+{
+    let timing = match button.debounce_ms {
+        Some(debounce_ms) => GestureTiming {
+            debounce: Duration::from_millis(debounce_ms),
+            ..timing
+        },
+        None => timing,
+    };
+    let mut detector = GestureDetector::new(
+        timing,
+        button.long_press.is_some(),
+        button.double_press.is_some(),
+    );
+    loop {
+        let gesture = match detector.deadline() {
+            Some(deadline) => match edges.recv_deadline(deadline) {
+                Ok((edge, at)) => detector.edge(edge, at),
+                Err(RecvTimeoutError::Timeout) => detector.timeout(Instant::now()),
+                Err(RecvTimeoutError::Disconnected) => return,
+            },
+            None => match edges.recv() {
+                Ok((edge, at)) => detector.edge(edge, at),
+                Err(_) => return,
+            },
+        };
+        let gesture = match gesture {
+            Some(gesture) => gesture,
+            None => continue,
+        };
+        debug!("Recognized {:?} on line {}", gesture, button.line);
+        if let Some(cmd) = button.command(gesture) {
+            if let Err(err) = tx.send(cmd.clone().into()) {
+                error!("Failed to transmit button command: {}", err);
+            }
+        }
+    }
 }
 
 pub struct Handle<T> {
@@ -30,19 +231,40 @@ impl<T> Handle<T> {
 pub mod cdev_gpio {
     use std::collections::HashMap;
     use std::convert::From;
-    use std::sync::{Arc, RwLock};
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread::JoinHandle;
 
-    use gpio_cdev::{Chip, EventRequestFlags, Line, LineRequestFlags};
-    use serde::Deserialize;
-    use tracing::{error, info, trace};
+    use gpio_cdev::{Chip, EventRequestFlags, LineEventHandle, LineRequestFlags};
+    use tracing::{info, trace, warn};
 
     use super::*;
+    use crate::components::config::ConfigLoaderHandle;
+
+    // Interval at which listeners check whether they are to stop.
+    const POLL_INTERVAL_MS: libc::c_int = 200;
 
-    #[derive(Debug, Clone)]
-    pub struct CdevGpio<T: Clone> {
-        map: HashMap<u32, Command>,
-        chip: Arc<RwLock<Chip>>,
+    // Bias flags of line requests. Supported by the kernel since Linux 5.5, but not
+    // defined by gpio-cdev.
+    const GPIOHANDLE_REQUEST_BIAS_PULL_UP: u32 = 1 << 5;
+    const GPIOHANDLE_REQUEST_BIAS_PULL_DOWN: u32 = 1 << 6;
+    const GPIOHANDLE_REQUEST_BIAS_DISABLE: u32 = 1 << 7;
+
+    #[derive(Debug)]
+    struct LineListener {
+        stop: Arc<AtomicBool>,
+        thread: JoinHandle<()>,
+    }
+
+    // Requests the lines of the configured buttons and requests them anew whenever the
+    // button configuration changes.
+    #[derive(Debug)]
+    pub struct CdevGpio<T> {
+        config: Config,
         tx: Sender<T>,
+        listeners: Vec<LineListener>,
     }
 
     #[derive(Deserialize, Debug, Clone)]
@@ -50,15 +272,73 @@ pub mod cdev_gpio {
         volume_up_pin: Option<u32>,
         volume_down_pin: Option<u32>,
         pause_pin: Option<u32>,
+        sleep_timer_pin: Option<u32>,
+        next_track_pin: Option<u32>,
+        previous_track_pin: Option<u32>,
+        stop_pin: Option<u32>,
+        seek_forward_pin: Option<u32>,
+        seek_backward_pin: Option<u32>,
+        shutdown_pin: Option<u32>,
+        // Comma separated bindings of commands to gestures, e.g. "14:sleep_timer".
+        long_press: Option<Vec<String>>,
+        double_press: Option<Vec<String>>,
+        long_press_ms: Option<u64>,
+        double_press_ms: Option<u64>,
     }
 
-    impl From<EnvConfig> for Config {
-        fn from(env_config: EnvConfig) -> Self {
-            Config {
-                volume_up_pin: env_config.volume_up_pin,
-                volume_down_pin: env_config.volume_down_pin,
-                pause_pin: env_config.pause_pin,
+    // Parses a binding like "14:sleep_timer".
+    fn parse_binding(binding: &str) -> Result<(u32, Command)> {
+        let (pin, command) = binding
+            .split_once(':')
+            .ok_or_else(|| anyhow!("expected binding as PIN:COMMAND, got {:?}", binding))?;
+        let pin = pin
+            .trim()
+            .parse()
+            .with_context(|| format!("parsing pin of binding {:?}", binding))?;
+        Ok((pin, Command::try_from(command.trim())?))
+    }
+
+    impl TryFrom<EnvConfig> for Config {
+        type Error = anyhow::Error;
+
+        // The plain pin settings bind short presses.
+        fn try_from(env_config: EnvConfig) -> Result<Self> {
+            let mut config = Config::default();
+            let short_presses = [
+                (env_config.volume_up_pin, Command::VolumeUp),
+                (env_config.volume_down_pin, Command::VolumeDown),
+                (env_config.pause_pin, Command::PauseContinue),
+                (env_config.sleep_timer_pin, Command::SleepTimer),
+                (env_config.next_track_pin, Command::NextTrack),
+                (env_config.previous_track_pin, Command::PreviousTrack),
+                (env_config.stop_pin, Command::Stop),
+                (env_config.seek_forward_pin, Command::SeekForward),
+                (env_config.seek_backward_pin, Command::SeekBackward),
+                (env_config.shutdown_pin, Command::Shutdown),
+            ];
+            for (pin, command) in short_presses.iter().cloned() {
+                if let Some(pin) = pin {
+                    config.bind(pin, Gesture::ShortPress, command);
+                }
+            }
+            let gestures = [
+                (env_config.long_press, Gesture::LongPress),
+                (env_config.double_press, Gesture::DoublePress),
+            ];
+            for (bindings, gesture) in gestures.iter().cloned() {
+                for binding in bindings.unwrap_or_default() {
+                    let (pin, command) = parse_binding(&binding)
+                        .with_context(|| format!("parsing {:?} bindings", gesture))?;
+                    config.bind(pin, gesture, command);
+                }
             }
+            if let Some(long_press_ms) = env_config.long_press_ms {
+                config.timing.long_press = Duration::from_millis(long_press_ms);
+            }
+            if let Some(double_press_ms) = env_config.double_press_ms {
+                config.timing.double_press = Duration::from_millis(double_press_ms);
+            }
+            Ok(config)
         }
     }
 
@@ -68,93 +348,222 @@ pub mod cdev_gpio {
         }
     }
 
-    impl<T: Clone + Send + 'static> CdevGpio<T>
+    // gpio-cdev passes the bits of the flags on to the kernel in the line request as
+    // they are. Kernels without bias support reject such a request with EINVAL.
+    fn bias_flags(bias: Bias) -> LineRequestFlags {
+        let bits = match bias {
+            Bias::AsIs => 0,
+            Bias::PullUp => GPIOHANDLE_REQUEST_BIAS_PULL_UP,
+            Bias::PullDown => GPIOHANDLE_REQUEST_BIAS_PULL_DOWN,
+            Bias::Disabled => GPIOHANDLE_REQUEST_BIAS_DISABLE,
+        };
+        LineRequestFlags::from_bits_retain(bits)
+    }
+
+    // Flags for requesting an input line with the given bias.
+    pub(crate) fn request_flags(bias: Bias) -> LineRequestFlags {
+        LineRequestFlags::INPUT | bias_flags(bias)
+    }
+
+    // The button configuration of the main configuration takes precedence over the
+    // environment.
+    fn button_config(config: &model::config::Config) -> Result<Config> {
+        match Config::from_config(config) {
+            Some(config) => Ok(config),
+            None => {
+                let env_config = EnvConfig::new_from_env()
+                    .context("Reading button configuration from environment")?;
+                Config::try_from(env_config)
+            }
+        }
+    }
+
+    impl<T: Send + 'static> CdevGpio<T>
     where
         T: From<Command>,
     {
-        pub fn new_from_env(input_tx: Sender<T>) -> Result<()> {
+        #[allow(clippy::new_ret_no_self)]
+        pub fn new(config: ConfigLoaderHandle, input_tx: Sender<T>) -> Result<()> {
             info!("Using CdevGpio based Button Controller");
-            let env_config =
-                EnvConfig::new_from_env().context("Creating CdevGpio based button controller")?;
-            let config: Config = env_config.into();
-            let mut map = HashMap::new();
-            if let Some(pin) = config.volume_up_pin {
-                map.insert(pin, Command::VolumeUp);
-            }
-            if let Some(pin) = config.volume_down_pin {
-                map.insert(pin, Command::VolumeDown);
-            }
-            if let Some(pin) = config.pause_pin {
-                map.insert(pin, Command::PauseContinue);
-            }
-            let chip = Chip::new("/dev/gpiochip0")
-                .map_err(|err| Error::IO(format!("Failed to open Chip: {:?}", err)))?;
-            let mut gpio_cdev = Self {
-                map,
-                chip: Arc::new(RwLock::new(chip)),
+            let updates = config.subscribe();
+            let button_config = button_config(&config.get())
+                .context("Creating CdevGpio based button controller")?;
+            let mut gpio_cdev = CdevGpio {
+                config: button_config,
                 tx: input_tx,
+                listeners: vec![],
             };
+            gpio_cdev
+                .request_lines()
+                .context("Running GPIO event listener")?;
 
-            gpio_cdev.run().context("Running GPIO event listener")?;
+            std::thread::Builder::new()
+                .name("button-config-watcher".to_string())
+                .spawn(move || {
+                    for config in updates {
+                        gpio_cdev.reconfigure(&config);
+                    }
+                })
+                .context("Spawning button configuration watcher")?;
             Ok(())
         }
 
+        fn reconfigure(&mut self, config: &model::config::Config) {
+            let button_config = match button_config(config) {
+                Ok(button_config) => button_config,
+                Err(err) => {
+                    error!("Failed to determine button configuration: {:#}", err);
+                    return;
+                }
+            };
+            if button_config == self.config {
+                return;
+            }
+            info!("Button configuration changed, requesting GPIO lines anew");
+            self.release_lines();
+            self.config = button_config;
+            if let Err(err) = self.request_lines() {
+                error!("Failed to request GPIO lines: {:#}", err);
+            }
+        }
+
+        // Forwards the level of the given line to the gesture detector whenever an edge
+        // occurs, until asked to stop. The level is read from the line rather than taken
+        // from the edge, and read again while the line is idle, so that lost edges do not
+        // leave the detector out of sync.
         fn run_single_event_listener(
-            self,
-            (line, line_id, cmd): (Line, u32, Command),
+            mut events: LineEventHandle,
+            button: &Button,
+            edges_tx: Sender<(Edge, Instant)>,
+            stop: &AtomicBool,
         ) -> Result<()> {
-            let mut ts = Instant::now();
-
-            info!("Listening for GPIO events on line {}", line_id);
-            for event in line
-                .events(
-                    LineRequestFlags::INPUT,
-                    EventRequestFlags::FALLING_EDGE,
-                    "read-input",
-                )
-                .map_err(|err| {
+            info!("Listening for GPIO events on line {}", button.line);
+            let mut poll_fd = libc::pollfd {
+                fd: events.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let read_edge = |events: &LineEventHandle| -> Result<Edge> {
+                let value = events.get_value().map_err(|err| {
                     Error::IO(format!(
-                        "Failed to request events from GPIO line {}: {}",
-                        line_id, err
+                        "Failed to read value of GPIO line {}: {}",
+                        button.line, err
                     ))
-                })?
-            {
-                if ts.elapsed() < std::time::Duration::from_millis(500) {
-                    trace!("Ignoring GPIO event {:?} on line {} since the last event on this line arrived just {}ms ago",
-                          event, line_id, ts.elapsed().as_millis());
+                })?;
+                Ok(button.edge(value != 0))
+            };
+            let mut level = read_edge(&events)?;
+            while !stop.load(Ordering::Relaxed) {
+                let res = unsafe { libc::poll(&mut poll_fd, 1, POLL_INTERVAL_MS) };
+                if res < 0 {
+                    let err = io::Error::last_os_error();
+                    if err.kind() == io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    return Err(err).with_context(|| format!("Polling GPIO line {}", button.line));
+                }
+                if res > 0 {
+                    let event = events.get_event().map_err(|err| {
+                        Error::IO(format!(
+                            "Failed to read event from GPIO line {}: {}",
+                            button.line, err
+                        ))
+                    })?;
+                    trace!("Received GPIO event {:?} on line {}", event, button.line);
+                }
+                let edge = read_edge(&events)?;
+                if res == 0 && edge == level {
                     continue;
                 }
-
-                trace!("Received GPIO event {:?} on line {}", event, line_id);
-                if let Err(err) = self.tx.send(cmd.clone().into()) {
-                    error!("Failed to transmit GPIO event: {}", err);
+                level = edge;
+                if edges_tx.send((edge, Instant::now())).is_err() {
+                    return Ok(());
                 }
-                ts = Instant::now();
             }
             Ok(())
         }
 
-        fn run(&mut self) -> Result<()> {
-            let chip = &mut *(self.chip.write().unwrap());
-            // Spawn threads for requested GPIO lines.
-            for (line_id, cmd) in self.map.iter() {
-                info!("Listening for {:?} on GPIO line {}", cmd, line_id);
-                let line_id = *line_id as u32;
+        // Requests the lines of all configured buttons. If one of them cannot be requested,
+        // the listeners started for the others are stopped again, so that no button is
+        // left half configured and a later reload finds the lines free.
+        fn request_lines(&mut self) -> Result<()> {
+            let res = self.request_all_lines();
+            if res.is_err() {
+                self.release_lines();
+            }
+            res
+        }
+
+        fn request_all_lines(&mut self) -> Result<()> {
+            let mut chips: HashMap<String, Chip> = HashMap::new();
+            for button in self.config.buttons.iter() {
+                info!("Listening for {:?} on GPIO line {}", button, button.line);
+                let chip = match chips.get_mut(&button.chip) {
+                    Some(chip) => chip,
+                    None => {
+                        let chip = Chip::new(&button.chip).map_err(|err| {
+                            Error::IO(format!("Failed to open chip {}: {:?}", button.chip, err))
+                        })?;
+                        chips.entry(button.chip.clone()).or_insert(chip)
+                    }
+                };
                 let line = chip
-                    .get_line(line_id)
+                    .get_line(button.line)
                     .map_err(|err| Error::IO(format!("Failed to get GPIO line: {:?}", err)))?;
-                let cmd = (*cmd).clone();
-                let clone = self.clone();
-                let _handle = std::thread::Builder::new()
-                    .name(format!("button-controller-{}", line_id))
-                    .spawn(move || {
-                        let res = clone.run_single_event_listener((line, line_id, cmd));
-                        error!("GPIO Listener loop terminated unexpectedly: {:?}", res);
-                    })
-                    .unwrap();
+                let events = line
+                    .events(
+                        request_flags(button.bias),
+                        EventRequestFlags::BOTH_EDGES,
+                        "read-input",
+                    )
+                    .map_err(|err| {
+                        Error::IO(format!(
+                            "Failed to request events from GPIO line {}: {}",
+                            button.line, err
+                        ))
+                    })?;
+
+                let (edges_tx, edges_rx) = crossbeam_channel::unbounded();
+                let stop = Arc::new(AtomicBool::new(false));
+                let thread = {
+                    let button = button.clone();
+                    let stop = stop.clone();
+                    std::thread::Builder::new()
+                        .name(format!("button-controller-{}", button.line))
+                        .spawn(move || {
+                            let res =
+                                Self::run_single_event_listener(events, &button, edges_tx, &stop);
+                            if let Err(err) = res {
+                                error!("GPIO Listener loop terminated unexpectedly: {:?}", err);
+                            }
+                        })
+                        .context("Spawning GPIO event listener")?
+                };
+                self.listeners.push(LineListener { stop, thread });
+
+                let button = button.clone();
+                let timing = self.config.timing;
+                let tx = self.tx.clone();
+                std::thread::Builder::new()
+                    .name(format!("button-gestures-{}", button.line))
+                    .spawn(move || run_gesture_detector(button, timing, edges_rx, tx))
+                    .context("Spawning gesture detector")?;
             }
             Ok(())
         }
+
+        // Stops the listeners, which releases their lines. Gesture detectors terminate
+        // along with them.
+        fn release_lines(&mut self) {
+            for listener in self.listeners.iter() {
+                listener.stop.store(true, Ordering::Relaxed);
+            }
+            for listener in self.listeners.drain(..) {
+                if listener.thread.join().is_err() {
+                    warn!("GPIO listener panicked");
+                }
+            }
+        }
     }
 }
 
@@ -172,3 +581,163 @@ impl std::fmt::Display for Error {
 }
 
 impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::cdev_gpio::{request_flags, EnvConfig};
+    use super::*;
+
+    fn env_config(vars: &[(&str, &str)]) -> Result<Config> {
+        let vars = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()));
+        Config::try_from(envy::from_iter::<_, EnvConfig>(vars)?)
+    }
+
+    #[test]
+    fn buttons_are_deserialized() {
+        let buttons: Vec<Button> = serde_yaml::from_str(
+            r#"
+- line: 14
+  short_press: pause_continue
+  long_press: sleep_timer
+  double_press: shutdown
+- chip: /dev/gpiochip1
+  line: 15
+  action: volume_up
+  active_low: false
+  bias: pull_down
+  debounce_ms: 50
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            buttons,
+            vec![
+                Button {
+                    short_press: Some(Command::PauseContinue),
+                    long_press: Some(Command::SleepTimer),
+                    double_press: Some(Command::Shutdown),
+                    ..Button::new(14)
+                },
+                Button {
+                    chip: "/dev/gpiochip1".to_string(),
+                    short_press: Some(Command::VolumeUp),
+                    active_low: false,
+                    bias: Bias::PullDown,
+                    debounce_ms: Some(50),
+                    ..Button::new(15)
+                },
+            ]
+        );
+        assert_eq!(
+            buttons[1].command(Gesture::ShortPress),
+            Some(&Command::VolumeUp)
+        );
+        assert_eq!(buttons[1].command(Gesture::LongPress), None);
+    }
+
+    #[test]
+    fn button_defaults() {
+        let button: Button = serde_yaml::from_str("line: 14").unwrap();
+        assert_eq!(button, Button::new(14));
+        assert_eq!(button.chip, DEFAULT_CHIP);
+        assert!(button.active_low);
+        assert_eq!(button.bias, Bias::AsIs);
+        assert_eq!(button.debounce_ms, None);
+        assert_eq!(button.edge(false), Edge::Pressed);
+        assert_eq!(button.edge(true), Edge::Released);
+
+        let active_high = Button {
+            active_low: false,
+            ..button
+        };
+        assert_eq!(active_high.edge(true), Edge::Pressed);
+        assert_eq!(active_high.edge(false), Edge::Released);
+    }
+
+    #[test]
+    fn invalid_buttons_are_rejected() {
+        for yaml in &[
+            "short_press: stop",
+            "line: 14\naction: dance",
+            "line: 14\nbias: pull_sideways",
+            "line: -1",
+        ] {
+            assert!(serde_yaml::from_str::<Button>(yaml).is_err(), "{}", yaml);
+        }
+    }
+
+    #[test]
+    fn buttons_section_takes_precedence() {
+        let config = model::config::Config {
+            buttons: Some(vec![Button::new(14)]),
+            long_press_ms: 800,
+            double_press_ms: 250,
+            ..model::config::Config::default()
+        };
+        let button_config = Config::from_config(&config).unwrap();
+        assert_eq!(button_config.buttons, vec![Button::new(14)]);
+        assert_eq!(button_config.timing.long_press, Duration::from_millis(800));
+        assert_eq!(
+            button_config.timing.double_press,
+            Duration::from_millis(250)
+        );
+        assert_eq!(
+            button_config.timing.debounce,
+            GestureTiming::default().debounce
+        );
+        assert_eq!(Config::from_config(&model::config::Config::default()), None);
+    }
+
+    #[test]
+    fn buttons_from_environment() {
+        let config = env_config(&[
+            ("PAUSE_PIN", "14"),
+            ("VOLUME_UP_PIN", "15"),
+            ("LONG_PRESS", "14:sleep_timer,16:shutdown"),
+            ("DOUBLE_PRESS", "15: stop"),
+            ("LONG_PRESS_MS", "800"),
+        ])
+        .unwrap();
+        assert_eq!(
+            config.buttons,
+            vec![
+                Button {
+                    short_press: Some(Command::VolumeUp),
+                    double_press: Some(Command::Stop),
+                    ..Button::new(15)
+                },
+                Button {
+                    short_press: Some(Command::PauseContinue),
+                    long_press: Some(Command::SleepTimer),
+                    ..Button::new(14)
+                },
+                Button {
+                    long_press: Some(Command::Shutdown),
+                    ..Button::new(16)
+                },
+            ]
+        );
+        assert_eq!(config.timing.long_press, Duration::from_millis(800));
+        assert_eq!(
+            config.timing.double_press,
+            GestureTiming::default().double_press
+        );
+
+        assert_eq!(env_config(&[]).unwrap(), Config::default());
+        assert!(env_config(&[("LONG_PRESS", "14")]).is_err());
+        assert!(env_config(&[("LONG_PRESS", "x:stop")]).is_err());
+        assert!(env_config(&[("DOUBLE_PRESS", "14:dance")]).is_err());
+        assert!(env_config(&[("PAUSE_PIN", "x")]).is_err());
+    }
+
+    #[test]
+    fn bias_is_requested() {
+        let bits = |bias| request_flags(bias).bits();
+        assert_eq!(bits(Bias::AsIs), 1);
+        assert_eq!(bits(Bias::PullUp), 1 | 1 << 5);
+        assert_eq!(bits(Bias::PullDown), 1 | 1 << 6);
+        assert_eq!(bits(Bias::Disabled), 1 | 1 << 7);
+    }
+}
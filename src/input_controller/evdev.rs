@@ -0,0 +1,661 @@
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::io::{self, Read};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+
+use crate::input_controller::button::Command;
+use crate::input_controller::keypad::{KeypadInput, NumberEntry};
+
+// Input devices like USB numpads, IR receivers or keyboards are configured in the main
+// configuration as e.g.:
+//
+// evdev:
+//   devices:
+//     - /dev/input/by-id/usb-0c45_USB_Keyboard-event-kbd
+//     - /dev/input/by-path/platform-ir-receiver@12-event
+//   keymap:
+//     KEY_F1: sleep_timer
+//     KEY_ESC: stop
+//     KEY_POWER: shutdown
+//
+// The keymap maps key names or numeric key codes onto button commands, "enter" or a
+// digit. It extends the default keymap, which covers media keys and digits. Digits enter
+// a number, which is confirmed with enter or once no further digit has been pressed for
+// `entry_timeout_ms`, as with the matrix keypad. Devices are grabbed, so that key
+// presses do not reach the console. Devices which are missing are opened once they
+// appear.
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum KeyAction {
+    Command(Command),
+    Digit(char),
+    Enter,
+}
+
+impl TryFrom<String> for KeyAction {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        match s.as_str() {
+            "enter" => Ok(KeyAction::Enter),
+            digit if digit.len() == 1 && digit.chars().all(|c| c.is_ascii_digit()) => {
+                Ok(KeyAction::Digit(digit.chars().next().unwrap()))
+            }
+            command => Ok(KeyAction::Command(Command::try_from(command)?)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Evdev {
+    pub devices: Vec<String>,
+    #[serde(default)]
+    pub keymap: BTreeMap<String, KeyAction>,
+    #[serde(default = "default_entry_timeout_ms")]
+    pub entry_timeout_ms: u64,
+    #[serde(default = "default_grab")]
+    pub grab: bool,
+}
+
+fn default_entry_timeout_ms() -> u64 {
+    3000
+}
+
+fn default_grab() -> bool {
+    true
+}
+
+// Codes of the keys which are likely to be mapped, see linux/input-event-codes.h.
+const KEY_CODES: &[(&str, u16)] = &[
+    ("KEY_ESC", 1),
+    ("KEY_1", 2),
+    ("KEY_2", 3),
+    ("KEY_3", 4),
+    ("KEY_4", 5),
+    ("KEY_5", 6),
+    ("KEY_6", 7),
+    ("KEY_7", 8),
+    ("KEY_8", 9),
+    ("KEY_9", 10),
+    ("KEY_0", 11),
+    ("KEY_BACKSPACE", 14),
+    ("KEY_ENTER", 28),
+    ("KEY_SPACE", 57),
+    ("KEY_F1", 59),
+    ("KEY_F2", 60),
+    ("KEY_F3", 61),
+    ("KEY_F4", 62),
+    ("KEY_F5", 63),
+    ("KEY_F6", 64),
+    ("KEY_F7", 65),
+    ("KEY_F8", 66),
+    ("KEY_F9", 67),
+    ("KEY_F10", 68),
+    ("KEY_KP7", 71),
+    ("KEY_KP8", 72),
+    ("KEY_KP9", 73),
+    ("KEY_KPMINUS", 74),
+    ("KEY_KP4", 75),
+    ("KEY_KP5", 76),
+    ("KEY_KP6", 77),
+    ("KEY_KPPLUS", 78),
+    ("KEY_KP1", 79),
+    ("KEY_KP2", 80),
+    ("KEY_KP3", 81),
+    ("KEY_KP0", 82),
+    ("KEY_KPENTER", 96),
+    ("KEY_UP", 103),
+    ("KEY_LEFT", 105),
+    ("KEY_RIGHT", 106),
+    ("KEY_DOWN", 108),
+    ("KEY_MUTE", 113),
+    ("KEY_VOLUMEDOWN", 114),
+    ("KEY_VOLUMEUP", 115),
+    ("KEY_POWER", 116),
+    ("KEY_PAUSE", 119),
+    ("KEY_STOP", 128),
+    ("KEY_SLEEP", 142),
+    ("KEY_NEXTSONG", 163),
+    ("KEY_PLAYPAUSE", 164),
+    ("KEY_PREVIOUSSONG", 165),
+    ("KEY_STOPCD", 166),
+    ("KEY_REWIND", 168),
+    ("KEY_PLAYCD", 200),
+    ("KEY_PAUSECD", 201),
+    ("KEY_PLAY", 207),
+    ("KEY_FASTFORWARD", 208),
+    ("KEY_OK", 352),
+    ("KEY_CHANNELUP", 402),
+    ("KEY_CHANNELDOWN", 403),
+    ("KEY_NUMERIC_0", 512),
+    ("KEY_NUMERIC_1", 513),
+    ("KEY_NUMERIC_2", 514),
+    ("KEY_NUMERIC_3", 515),
+    ("KEY_NUMERIC_4", 516),
+    ("KEY_NUMERIC_5", 517),
+    ("KEY_NUMERIC_6", 518),
+    ("KEY_NUMERIC_7", 519),
+    ("KEY_NUMERIC_8", 520),
+    ("KEY_NUMERIC_9", 521),
+];
+
+const DEFAULT_KEYMAP: &[(&str, &str)] = &[
+    ("KEY_ENTER", "enter"),
+    ("KEY_KPENTER", "enter"),
+    ("KEY_OK", "enter"),
+    ("KEY_PLAYPAUSE", "pause_continue"),
+    ("KEY_PLAY", "pause_continue"),
+    ("KEY_PAUSE", "pause_continue"),
+    ("KEY_PLAYCD", "pause_continue"),
+    ("KEY_PAUSECD", "pause_continue"),
+    ("KEY_SPACE", "pause_continue"),
+    ("KEY_VOLUMEUP", "volume_up"),
+    ("KEY_KPPLUS", "volume_up"),
+    ("KEY_VOLUMEDOWN", "volume_down"),
+    ("KEY_KPMINUS", "volume_down"),
+    ("KEY_NEXTSONG", "next_track"),
+    ("KEY_PREVIOUSSONG", "previous_track"),
+    ("KEY_STOPCD", "stop"),
+    ("KEY_STOP", "stop"),
+    ("KEY_FASTFORWARD", "seek_forward"),
+    ("KEY_REWIND", "seek_backward"),
+    ("KEY_SLEEP", "sleep_timer"),
+];
+
+// Accepts key names like "KEY_PLAYPAUSE" as well as numeric codes.
+pub fn key_code(name: &str) -> Result<u16> {
+    if let Ok(code) = name.parse() {
+        return Ok(code);
+    }
+    KEY_CODES
+        .iter()
+        .find(|(key, _)| *key == name)
+        .map(|(_, code)| *code)
+        .ok_or_else(|| anyhow!("unknown key {:?}", name))
+}
+
+#[derive(Debug, Clone)]
+pub struct Keymap(HashMap<u16, KeyAction>);
+
+impl Keymap {
+    pub fn new(evdev: &Evdev) -> Result<Self> {
+        let mut keymap = HashMap::new();
+        let digits = ["KEY_", "KEY_KP", "KEY_NUMERIC_"];
+        for prefix in digits.iter() {
+            for digit in '0'..='9' {
+                keymap.insert(
+                    key_code(&format!("{}{}", prefix, digit))?,
+                    KeyAction::Digit(digit),
+                );
+            }
+        }
+        for (name, action) in DEFAULT_KEYMAP.iter() {
+            keymap.insert(key_code(name)?, KeyAction::try_from(action.to_string())?);
+        }
+        for (name, action) in evdev.keymap.iter() {
+            let code = key_code(name).context("parsing keymap")?;
+            keymap.insert(code, action.clone());
+        }
+        Ok(Keymap(keymap))
+    }
+
+    pub fn get(&self, code: u16) -> Option<&KeyAction> {
+        self.0.get(&code)
+    }
+}
+
+const EV_KEY: u16 = 0x01;
+// Values of key events, auto repeats are ignored.
+const KEY_PRESSED: i32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputEvent {
+    pub kind: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+// Reads `struct input_event` records, as read from /dev/input/event* or recorded from
+// there. The records start with a timestamp, which is not used.
+pub struct EventReader<R> {
+    reader: R,
+}
+
+const TIMESTAMP_SIZE: usize = std::mem::size_of::<libc::timeval>();
+const EVENT_SIZE: usize = TIMESTAMP_SIZE + 8;
+
+impl<R: Read> EventReader<R> {
+    pub fn new(reader: R) -> Self {
+        EventReader { reader }
+    }
+
+    // Returns None once the stream has ended.
+    pub fn next_event(&mut self) -> io::Result<Option<InputEvent>> {
+        let mut buf = [0; EVENT_SIZE];
+        match self.reader.read_exact(&mut buf) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        }
+        let data = &buf[TIMESTAMP_SIZE..];
+        Ok(Some(InputEvent {
+            kind: u16::from_ne_bytes([data[0], data[1]]),
+            code: u16::from_ne_bytes([data[2], data[3]]),
+            value: i32::from_ne_bytes([data[4], data[5], data[6], data[7]]),
+        }))
+    }
+}
+
+// Turns key events into commands and entered numbers.
+#[derive(Debug, Clone)]
+pub struct EventInterpreter {
+    keymap: Keymap,
+    entry_timeout: Duration,
+    entry: NumberEntry,
+}
+
+impl EventInterpreter {
+    pub fn new(evdev: &Evdev) -> Result<Self> {
+        Ok(EventInterpreter {
+            keymap: Keymap::new(evdev)?,
+            entry_timeout: Duration::from_millis(evdev.entry_timeout_ms),
+            entry: NumberEntry::new(),
+        })
+    }
+
+    pub fn event(&mut self, event: &InputEvent, now: Instant) -> Option<KeypadInput> {
+        if event.kind != EV_KEY || event.value != KEY_PRESSED {
+            return None;
+        }
+        match self.keymap.get(event.code)? {
+            KeyAction::Command(cmd) => Some(KeypadInput::Command(cmd.clone())),
+            KeyAction::Digit(digit) => {
+                self.entry.digit(*digit, now);
+                None
+            }
+            KeyAction::Enter => self.entry.confirm().map(KeypadInput::Number),
+        }
+    }
+
+    pub fn timeout(&mut self, now: Instant) -> Option<KeypadInput> {
+        self.entry
+            .timeout(self.entry_timeout, now)
+            .map(KeypadInput::Number)
+    }
+}
+
+pub mod device {
+    use std::fs::File;
+    use std::os::unix::io::AsRawFd;
+
+    use crossbeam_channel::Sender;
+    use tracing::{debug, error, info, warn};
+
+    use super::*;
+    use crate::components::config::ConfigLoaderHandle;
+    use crate::input_controller::keypad::Number;
+
+    const POLL_INTERVAL_MS: libc::c_int = 100;
+    // Interval at which missing devices are looked for.
+    const REOPEN_INTERVAL: Duration = Duration::from_secs(5);
+    // _IOW('E', 0x90, int)
+    const EVIOCGRAB: libc::c_ulong = 0x4004_4590;
+
+    struct Device {
+        path: String,
+        events: Option<EventReader<File>>,
+    }
+
+    struct Devices {
+        evdev: Evdev,
+        devices: Vec<Device>,
+        interpreter: EventInterpreter,
+        last_open: Option<Instant>,
+    }
+
+    fn open_device(path: &str, grab: bool) -> Result<EventReader<File>> {
+        let file = File::open(path).with_context(|| format!("Opening input device {}", path))?;
+        if grab {
+            // Safety: the ioctl takes an int argument.
+            let res = unsafe { libc::ioctl(file.as_raw_fd(), EVIOCGRAB as _, 1 as libc::c_int) };
+            if res < 0 {
+                return Err(io::Error::last_os_error())
+                    .with_context(|| format!("Grabbing input device {}", path));
+            }
+        }
+        info!("Reading key events from {}", path);
+        Ok(EventReader::new(file))
+    }
+
+    impl Devices {
+        fn new(evdev: Option<Evdev>) -> Option<Self> {
+            let evdev = evdev?;
+            let interpreter = match EventInterpreter::new(&evdev) {
+                Ok(interpreter) => interpreter,
+                Err(err) => {
+                    error!("Invalid evdev configuration: {:#}", err);
+                    return None;
+                }
+            };
+            let devices = evdev
+                .devices
+                .iter()
+                .map(|path| Device {
+                    path: path.clone(),
+                    events: None,
+                })
+                .collect();
+            Some(Devices {
+                evdev,
+                devices,
+                interpreter,
+                last_open: None,
+            })
+        }
+
+        fn open_missing(&mut self, now: Instant) {
+            if matches!(self.last_open, Some(last_open) if now < last_open + REOPEN_INTERVAL) {
+                return;
+            }
+            self.last_open = Some(now);
+            for device in self.devices.iter_mut().filter(|dev| dev.events.is_none()) {
+                match open_device(&device.path, self.evdev.grab) {
+                    Ok(events) => device.events = Some(events),
+                    Err(err) => debug!("Input device not available: {:#}", err),
+                }
+            }
+        }
+
+        // Waits for events on the open devices and interprets them.
+        fn poll(&mut self) -> Vec<KeypadInput> {
+            let now = Instant::now();
+            self.open_missing(now);
+            let open: Vec<&mut Device> = self
+                .devices
+                .iter_mut()
+                .filter(|dev| dev.events.is_some())
+                .collect();
+            let mut poll_fds: Vec<libc::pollfd> = open
+                .iter()
+                .filter_map(|dev| dev.events.as_ref())
+                .map(|events| libc::pollfd {
+                    fd: events.reader.as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                })
+                .collect();
+            let res = unsafe {
+                libc::poll(
+                    poll_fds.as_mut_ptr(),
+                    poll_fds.len() as libc::nfds_t,
+                    POLL_INTERVAL_MS,
+                )
+            };
+            if res < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    error!("Failed to poll input devices: {}", err);
+                    std::thread::sleep(Duration::from_millis(POLL_INTERVAL_MS as u64));
+                }
+                return vec![];
+            }
+            let now = Instant::now();
+            let mut inputs = vec![];
+            for (device, poll_fd) in open.into_iter().zip(poll_fds.iter()) {
+                if poll_fd.revents == 0 {
+                    continue;
+                }
+                let events = device.events.as_mut().unwrap();
+                match events.next_event() {
+                    Ok(Some(event)) => inputs.extend(self.interpreter.event(&event, now)),
+                    Ok(None) => device.events = None,
+                    Err(err) => {
+                        // E.g. the device has been unplugged.
+                        warn!("Failed to read from input device {}: {}", device.path, err);
+                        device.events = None;
+                    }
+                }
+            }
+            inputs.extend(self.interpreter.timeout(now));
+            inputs
+        }
+    }
+
+    // Reads the configured input devices and transmits the resulting inputs. The devices
+    // are opened anew whenever the evdev configuration changes.
+    fn run<T>(config: ConfigLoaderHandle, tx: Sender<T>)
+    where
+        T: From<Command> + From<Number>,
+    {
+        let updates = config.subscribe();
+        let mut devices = Devices::new(config.get().evdev);
+        loop {
+            if let Some(update) = updates.try_iter().last() {
+                if update.evdev != devices.as_ref().map(|devices| devices.evdev.clone()) {
+                    info!("Evdev configuration changed");
+                    // Release grabbed devices before opening them anew.
+                    drop(devices.take());
+                    devices = Devices::new(update.evdev);
+                }
+            }
+            let inputs = match devices.as_mut() {
+                Some(devices) => devices.poll(),
+                None => {
+                    // Nothing to read until the configuration changes.
+                    match updates.recv() {
+                        Ok(update) => {
+                            devices = Devices::new(update.evdev);
+                            continue;
+                        }
+                        Err(_) => return,
+                    }
+                }
+            };
+            for input in inputs {
+                let res = match input {
+                    KeypadInput::Command(cmd) => tx.send(cmd.into()),
+                    KeypadInput::Number(number) => tx.send(number.into()),
+                };
+                if let Err(err) = res {
+                    error!("Failed to transmit key input: {}", err);
+                }
+            }
+        }
+    }
+
+    pub fn spawn<T>(config: ConfigLoaderHandle, tx: Sender<T>) -> Result<()>
+    where
+        T: From<Command> + From<Number> + Send + 'static,
+    {
+        std::thread::Builder::new()
+            .name("evdev".to_string())
+            .spawn(move || run(config, tx))
+            .context("Spawning evdev reader")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input_controller::keypad::Number;
+
+    const EV_SYN: u16 = 0x00;
+    const EV_MSC: u16 = 0x04;
+    const MSC_SCAN: u16 = 0x04;
+    const KEY_RELEASED: i32 = 0;
+    const KEY_REPEATED: i32 = 2;
+
+    fn event(kind: u16, code: u16, value: i32) -> InputEvent {
+        InputEvent { kind, code, value }
+    }
+
+    // The events of a key press as reported by a keyboard: scan code, key and
+    // synchronization.
+    fn key(name: &str, value: i32) -> Vec<InputEvent> {
+        vec![
+            event(EV_MSC, MSC_SCAN, 0x70028),
+            event(EV_KEY, key_code(name).unwrap(), value),
+            event(EV_SYN, 0, 0),
+        ]
+    }
+
+    fn tap(name: &str) -> Vec<InputEvent> {
+        let mut events = key(name, KEY_PRESSED);
+        events.extend(key(name, KEY_RELEASED));
+        events
+    }
+
+    // Encodes events as `struct input_event` records, as recorded from a device.
+    fn record(events: &[InputEvent]) -> Vec<u8> {
+        let mut bytes = vec![];
+        for (i, event) in events.iter().enumerate() {
+            let mut timestamp = vec![0; TIMESTAMP_SIZE];
+            timestamp[0] = i as u8;
+            bytes.extend(timestamp);
+            bytes.extend(event.kind.to_ne_bytes());
+            bytes.extend(event.code.to_ne_bytes());
+            bytes.extend(event.value.to_ne_bytes());
+        }
+        bytes
+    }
+
+    // Hands out the recorded bytes in chunks of the given size.
+    struct ChunkedReader {
+        bytes: Vec<u8>,
+        position: usize,
+        chunk: usize,
+    }
+
+    impl Read for ChunkedReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let end = (self.position + self.chunk.min(buf.len())).min(self.bytes.len());
+            let n = end - self.position;
+            buf[..n].copy_from_slice(&self.bytes[self.position..end]);
+            self.position = end;
+            Ok(n)
+        }
+    }
+
+    fn read_all<R: Read>(reader: R) -> Vec<InputEvent> {
+        let mut reader = EventReader::new(reader);
+        let mut events = vec![];
+        while let Some(event) = reader.next_event().unwrap() {
+            events.push(event);
+        }
+        events
+    }
+
+    fn interprete(evdev: &Evdev, events: &[InputEvent]) -> Vec<KeypadInput> {
+        let mut interpreter = EventInterpreter::new(evdev).unwrap();
+        let now = Instant::now();
+        events
+            .iter()
+            .filter_map(|event| interpreter.event(event, now))
+            .collect()
+    }
+
+    fn evdev(keymap: &str) -> Evdev {
+        serde_yaml::from_str(&format!("{{devices: [], keymap: {}}}", keymap)).unwrap()
+    }
+
+    #[test]
+    fn reads_recorded_events() {
+        let mut events = tap("KEY_PLAYPAUSE");
+        events.extend(key("KEY_VOLUMEUP", KEY_PRESSED));
+        events.extend(key("KEY_VOLUMEUP", KEY_REPEATED));
+        events.extend(key("KEY_VOLUMEUP", KEY_RELEASED));
+        assert_eq!(read_all(&record(&events)[..]), events);
+    }
+
+    #[test]
+    fn short_reads_are_completed() {
+        let events = tap("KEY_KP1");
+        for chunk in [1, 5, EVENT_SIZE - 1, EVENT_SIZE + 3] {
+            let reader = ChunkedReader {
+                bytes: record(&events),
+                position: 0,
+                chunk,
+            };
+            assert_eq!(read_all(reader), events, "chunks of {} bytes", chunk);
+        }
+    }
+
+    #[test]
+    fn truncated_record_ends_the_stream() {
+        let events = tap("KEY_KP1");
+        let mut bytes = record(&events);
+        bytes.extend(&record(&key("KEY_KP2", KEY_PRESSED))[..EVENT_SIZE / 2]);
+        assert_eq!(read_all(&bytes[..]), events);
+    }
+
+    #[test]
+    fn only_key_presses_are_interpreted() {
+        let mut events = key("KEY_VOLUMEUP", KEY_PRESSED);
+        for _ in 0..3 {
+            events.extend(key("KEY_VOLUMEUP", KEY_REPEATED));
+        }
+        events.extend(key("KEY_VOLUMEUP", KEY_RELEASED));
+        events.extend(tap("KEY_NEXTSONG"));
+        assert_eq!(
+            interprete(&evdev("{}"), &events),
+            vec![
+                KeypadInput::Command(Command::VolumeUp),
+                KeypadInput::Command(Command::NextTrack),
+            ]
+        );
+    }
+
+    #[test]
+    fn digits_enter_numbers() {
+        let events: Vec<InputEvent> = ["KEY_KP0", "KEY_1", "KEY_NUMERIC_2", "KEY_KPENTER"]
+            .iter()
+            .flat_map(|name| tap(name))
+            .collect();
+        assert_eq!(
+            interprete(&evdev("{}"), &events),
+            vec![KeypadInput::Number(Number("012".to_string()))]
+        );
+
+        let mut interpreter = EventInterpreter::new(&evdev("{}")).unwrap();
+        let start = Instant::now();
+        for event in tap("KEY_7") {
+            assert_eq!(interpreter.event(&event, start), None);
+        }
+        assert_eq!(interpreter.timeout(start + Duration::from_secs(2)), None);
+        assert_eq!(
+            interpreter.timeout(start + Duration::from_secs(3)),
+            Some(KeypadInput::Number(Number("7".to_string())))
+        );
+    }
+
+    #[test]
+    fn keymap_extends_the_default() {
+        let evdev = evdev(r#"{KEY_F1: sleep_timer, KEY_SPACE: stop, "2": "enter", KEY_ESC: "9"}"#);
+        let mut events = tap("KEY_F1");
+        events.extend(tap("KEY_SPACE"));
+        events.extend(tap("KEY_PLAYPAUSE"));
+        events.extend(tap("KEY_ESC"));
+        // Code 2 is KEY_1.
+        events.extend(tap("KEY_1"));
+        assert_eq!(
+            interprete(&evdev, &events),
+            vec![
+                KeypadInput::Command(Command::SleepTimer),
+                KeypadInput::Command(Command::Stop),
+                KeypadInput::Command(Command::PauseContinue),
+                KeypadInput::Number(Number("9".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_keymaps_are_rejected() {
+        assert!(EventInterpreter::new(&evdev("{KEY_UNKNOWN: stop}")).is_err());
+        assert!(serde_yaml::from_str::<Evdev>("{devices: [], keymap: {KEY_F1: dance}}").is_err());
+    }
+}
@@ -0,0 +1,391 @@
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Gesture {
+    ShortPress,
+    LongPress,
+    DoublePress,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    Pressed,
+    Released,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GestureTiming {
+    // A button held for this long is long-pressed, even before it is released.
+    pub long_press: Duration,
+    // A second press within this duration after a release makes a double press.
+    pub double_press: Duration,
+    // A level is only accepted once the button has stayed at it for this long, shorter
+    // pulses are considered contact bounce.
+    pub debounce: Duration,
+}
+
+impl Default for GestureTiming {
+    fn default() -> Self {
+        GestureTiming {
+            long_press: Duration::from_millis(1000),
+            double_press: Duration::from_millis(300),
+            debounce: Duration::from_millis(20),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Idle,
+    Pressed { since: Instant },
+    // Released after a short press, a second press may still follow.
+    Released { at: Instant },
+    // The gesture has been recognized already, the button is still held.
+    Recognized,
+}
+
+// Recognizes gestures from the levels of a single button. Levels are debounced: a
+// level is accepted once the button has stayed at it for the debounce duration, and
+// reporting the current level again re-synchronizes the detector after lost edges.
+// Gestures which are not enabled are not waited for: without long and double presses,
+// a short press is recognized as soon as the press is accepted. Otherwise it is
+// recognized on release, or once the time for a second press has passed.
+#[derive(Debug, Clone)]
+pub struct GestureDetector {
+    timing: GestureTiming,
+    long_press: bool,
+    double_press: bool,
+    state: State,
+    // The accepted level.
+    level: Edge,
+    // A level differing from the accepted one and since when it has been reported.
+    pending: Option<(Edge, Instant)>,
+}
+
+impl GestureDetector {
+    pub fn new(timing: GestureTiming, long_press: bool, double_press: bool) -> Self {
+        GestureDetector {
+            timing,
+            long_press,
+            double_press,
+            state: State::Idle,
+            level: Edge::Released,
+            pending: None,
+        }
+    }
+
+    // Reports the level of the button at the given point in time.
+    pub fn edge(&mut self, edge: Edge, at: Instant) -> Option<Gesture> {
+        // The pending level has lasted until now.
+        let gesture = self.settle(at);
+        self.pending = match self.pending {
+            Some((level, since)) if level == edge => Some((level, since)),
+            _ if edge == self.level => None,
+            _ => Some((edge, at)),
+        };
+        // Without debouncing, the level is accepted right away.
+        gesture.or_else(|| self.settle(at))
+    }
+
+    // Accepts the pending level if it has been stable for the debounce duration.
+    fn settle(&mut self, now: Instant) -> Option<Gesture> {
+        match self.pending {
+            Some((level, since)) if now >= since + self.timing.debounce => {
+                self.pending = None;
+                self.level = level;
+                self.accept(level, since)
+            }
+            _ => None,
+        }
+    }
+
+    fn accept(&mut self, edge: Edge, at: Instant) -> Option<Gesture> {
+        match (edge, self.state) {
+            (Edge::Pressed, State::Idle) => {
+                if !self.long_press && !self.double_press {
+                    self.state = State::Recognized;
+                    return Some(Gesture::ShortPress);
+                }
+                self.state = State::Pressed { since: at };
+                None
+            }
+            (Edge::Pressed, State::Released { .. }) => {
+                self.state = State::Recognized;
+                Some(Gesture::DoublePress)
+            }
+            (Edge::Released, State::Pressed { since }) => {
+                // In case the deadline has not been handled in time.
+                if self.long_press && at.saturating_duration_since(since) >= self.timing.long_press
+                {
+                    self.state = State::Idle;
+                    return Some(Gesture::LongPress);
+                }
+                if self.double_press {
+                    self.state = State::Released { at };
+                    return None;
+                }
+                self.state = State::Idle;
+                Some(Gesture::ShortPress)
+            }
+            (Edge::Released, State::Recognized) => {
+                self.state = State::Idle;
+                None
+            }
+            // Accepted levels alternate, the state already matches the level.
+            (Edge::Pressed, _) | (Edge::Released, _) => None,
+        }
+    }
+
+    // Point in time at which the current gesture is decided unless the level changes.
+    // A pending level which may still be accepted before then has to be waited for.
+    fn gesture_deadline(&self) -> Option<Instant> {
+        let deadline = match self.state {
+            State::Pressed { since } if self.long_press => since + self.timing.long_press,
+            State::Released { at } => at + self.timing.double_press,
+            _ => return None,
+        };
+        match self.pending {
+            Some((_, since)) if since < deadline => None,
+            _ => Some(deadline),
+        }
+    }
+
+    // Point in time at which `timeout` has to be called, if any.
+    pub fn deadline(&self) -> Option<Instant> {
+        let settle = self.pending.map(|(_, since)| since + self.timing.debounce);
+        match (self.gesture_deadline(), settle) {
+            (Some(gesture), Some(settle)) => Some(gesture.min(settle)),
+            (gesture, settle) => gesture.or(settle),
+        }
+    }
+
+    pub fn timeout(&mut self, now: Instant) -> Option<Gesture> {
+        if let Some(gesture) = self.settle(now) {
+            return Some(gesture);
+        }
+        match self.gesture_deadline() {
+            Some(deadline) if now >= deadline => {}
+            _ => return None,
+        }
+        match self.state {
+            State::Pressed { .. } => {
+                self.state = State::Recognized;
+                Some(Gesture::LongPress)
+            }
+            State::Released { .. } => {
+                self.state = State::Idle;
+                Some(Gesture::ShortPress)
+            }
+            State::Idle | State::Recognized => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Edge::{Pressed, Released};
+    use Gesture::{DoublePress, LongPress, ShortPress};
+
+    // Feeds the levels reported at the given milliseconds into the detector, calling
+    // `timeout` at the deadlines in between like `run_gesture_detector` does. Returns the
+    // gestures along with the millisecond they have been recognized at.
+    fn simulate(
+        detector: &mut GestureDetector,
+        levels: &[(Edge, u64)],
+        until: u64,
+    ) -> Vec<(Gesture, u64)> {
+        let start = Instant::now();
+        let mut gestures = vec![];
+        for (level, at) in levels {
+            let at = start + Duration::from_millis(*at);
+            handle_deadlines(detector, at, &mut gestures);
+            if let Some(gesture) = detector.edge(*level, at) {
+                gestures.push((gesture, at));
+            }
+        }
+        handle_deadlines(
+            detector,
+            start + Duration::from_millis(until),
+            &mut gestures,
+        );
+        gestures
+            .into_iter()
+            .map(|(gesture, at)| (gesture, (at - start).as_millis() as u64))
+            .collect()
+    }
+
+    fn handle_deadlines(
+        detector: &mut GestureDetector,
+        now: Instant,
+        gestures: &mut Vec<(Gesture, Instant)>,
+    ) {
+        while let Some(deadline) = detector.deadline().filter(|deadline| *deadline <= now) {
+            if let Some(gesture) = detector.timeout(deadline) {
+                gestures.push((gesture, deadline));
+            }
+        }
+    }
+
+    fn new_detector(long_press: bool, double_press: bool) -> GestureDetector {
+        GestureDetector::new(GestureTiming::default(), long_press, double_press)
+    }
+
+    struct Case {
+        name: &'static str,
+        long_press: bool,
+        double_press: bool,
+        levels: Vec<(Edge, u64)>,
+        expected: Vec<(Gesture, u64)>,
+    }
+
+    #[test]
+    fn recognizes_gestures() {
+        let cases = vec![
+            Case {
+                name: "short press only",
+                long_press: false,
+                double_press: false,
+                levels: vec![(Pressed, 0), (Released, 100)],
+                expected: vec![(ShortPress, 20)],
+            },
+            Case {
+                name: "short press with long press enabled",
+                long_press: true,
+                double_press: false,
+                levels: vec![(Pressed, 0), (Released, 100)],
+                expected: vec![(ShortPress, 120)],
+            },
+            Case {
+                name: "short press with double press enabled",
+                long_press: false,
+                double_press: true,
+                levels: vec![(Pressed, 0), (Released, 100)],
+                expected: vec![(ShortPress, 400)],
+            },
+            Case {
+                name: "long press",
+                long_press: true,
+                double_press: true,
+                levels: vec![(Pressed, 0), (Released, 1500)],
+                expected: vec![(LongPress, 1000)],
+            },
+            Case {
+                name: "long press disabled",
+                long_press: false,
+                double_press: true,
+                levels: vec![(Pressed, 0), (Released, 1500)],
+                expected: vec![(ShortPress, 1800)],
+            },
+            Case {
+                name: "double press",
+                long_press: true,
+                double_press: true,
+                levels: vec![
+                    (Pressed, 0),
+                    (Released, 100),
+                    (Pressed, 250),
+                    (Released, 350),
+                ],
+                expected: vec![(DoublePress, 270)],
+            },
+            Case {
+                name: "presses too far apart",
+                long_press: false,
+                double_press: true,
+                levels: vec![
+                    (Pressed, 0),
+                    (Released, 100),
+                    (Pressed, 450),
+                    (Released, 550),
+                ],
+                expected: vec![(ShortPress, 400), (ShortPress, 850)],
+            },
+            Case {
+                name: "release pending at the long press deadline",
+                long_press: true,
+                double_press: false,
+                levels: vec![(Pressed, 0), (Released, 990)],
+                expected: vec![(ShortPress, 1010)],
+            },
+        ];
+        for case in cases {
+            let mut detector = new_detector(case.long_press, case.double_press);
+            assert_eq!(
+                simulate(&mut detector, &case.levels, 5000),
+                case.expected,
+                "gestures of: {}",
+                case.name
+            );
+        }
+    }
+
+    #[test]
+    fn bounce_is_ignored() {
+        // Pulses shorter than the debounce duration, the bounce lasting longer than that
+        // as a whole.
+        let levels = vec![
+            (Pressed, 0),
+            (Released, 10),
+            (Pressed, 20),
+            (Released, 30),
+            (Pressed, 40),
+            (Released, 500),
+            (Pressed, 505),
+            (Released, 515),
+            (Pressed, 525),
+            (Released, 535),
+        ];
+        let mut detector = new_detector(true, true);
+        assert_eq!(
+            simulate(&mut detector, &levels, 5000),
+            vec![(ShortPress, 835)]
+        );
+    }
+
+    #[test]
+    fn repeated_levels_do_not_restart_the_gesture() {
+        let levels = vec![
+            (Pressed, 0),
+            (Pressed, 500),
+            (Pressed, 900),
+            (Released, 1200),
+        ];
+        let mut detector = new_detector(true, false);
+        assert_eq!(
+            simulate(&mut detector, &levels, 5000),
+            vec![(LongPress, 1000)]
+        );
+    }
+
+    #[test]
+    fn resynchronizes_after_lost_edges() {
+        // The release edge got lost, the level read afterwards tells the button is
+        // released, before a long press would be recognized.
+        let levels = vec![(Pressed, 0), (Pressed, 400), (Released, 400)];
+        let mut detector = new_detector(true, false);
+        assert_eq!(
+            simulate(&mut detector, &levels, 5000),
+            vec![(ShortPress, 420)]
+        );
+
+        // The press edge got lost, the release is not taken for a gesture.
+        let mut detector = new_detector(true, true);
+        assert_eq!(simulate(&mut detector, &[(Released, 100)], 5000), vec![]);
+        assert_eq!(detector.deadline(), None);
+    }
+
+    #[test]
+    fn zero_debounce_accepts_levels_right_away() {
+        let timing = GestureTiming {
+            debounce: Duration::ZERO,
+            ..GestureTiming::default()
+        };
+        let mut detector = GestureDetector::new(timing, false, false);
+        let start = Instant::now();
+        assert_eq!(detector.edge(Pressed, start), Some(ShortPress));
+        assert_eq!(detector.deadline(), None);
+    }
+}
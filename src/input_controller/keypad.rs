@@ -0,0 +1,553 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::input_controller::button::Command;
+
+// A matrix keypad is configured in the main configuration as e.g.:
+//
+// keypad:
+//   rows: [5, 6, 13, 19]
+//   columns: [12, 16, 20]
+//   layout: ["123", "456", "789", "*0#"]
+//   commands:
+//     "*": pause_continue
+//
+// Digits enter a number, which is confirmed with the enter key ("#" by default) or once
+// no further digit has been pressed for `entry_timeout_ms`. The tag mapper entry keyed
+// by the number is then played, e.g. "12" for album 12. Other keys trigger the button
+// commands bound to them.
+//
+// The rows are driven low one after another, the columns are read with pull-ups. The
+// lines are requested from /dev/gpiochip0 unless `chip` is given.
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Keypad {
+    #[serde(default = "default_chip")]
+    pub chip: String,
+    pub rows: Vec<u32>,
+    pub columns: Vec<u32>,
+    // One string per row, one character per column.
+    #[serde(default = "default_layout")]
+    pub layout: Vec<String>,
+    #[serde(default)]
+    pub commands: BTreeMap<char, Command>,
+    #[serde(default = "default_enter_key")]
+    pub enter_key: char,
+    #[serde(default = "default_entry_timeout_ms")]
+    pub entry_timeout_ms: u64,
+}
+
+fn default_chip() -> String {
+    "/dev/gpiochip0".to_string()
+}
+
+fn default_layout() -> Vec<String> {
+    vec!["123", "456", "789", "*0#"]
+        .into_iter()
+        .map(String::from)
+        .collect()
+}
+
+fn default_enter_key() -> char {
+    '#'
+}
+
+fn default_entry_timeout_ms() -> u64 {
+    3000
+}
+
+impl Keypad {
+    pub fn key(&self, row: usize, column: usize) -> Option<char> {
+        self.layout.get(row)?.chars().nth(column)
+    }
+}
+
+// Number entered on the keypad, as the digits entered. Leading zeros are kept, "012"
+// and "12" are different numbers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Number(pub String);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeypadInput {
+    Command(Command),
+    Number(Number),
+}
+
+// Numbers are limited to this many digits, further digits are ignored.
+const MAX_DIGITS: usize = 6;
+
+// Collects digits until the number is confirmed, or until no further digit has been
+// entered for a while.
+#[derive(Debug, Clone, Default)]
+pub struct NumberEntry {
+    digits: String,
+    last_digit: Option<Instant>,
+}
+
+impl NumberEntry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn digit(&mut self, digit: char, now: Instant) {
+        if !digit.is_ascii_digit() {
+            return;
+        }
+        if self.digits.len() < MAX_DIGITS {
+            self.digits.push(digit);
+        }
+        self.last_digit = Some(now);
+    }
+
+    pub fn confirm(&mut self) -> Option<Number> {
+        self.last_digit = None;
+        if self.digits.is_empty() {
+            return None;
+        }
+        Some(Number(std::mem::take(&mut self.digits)))
+    }
+
+    // Confirms the number entered so far, once the timeout has passed.
+    pub fn timeout(&mut self, timeout: Duration, now: Instant) -> Option<Number> {
+        let last_digit = self.last_digit?;
+        if now.saturating_duration_since(last_digit) < timeout {
+            return None;
+        }
+        self.confirm()
+    }
+}
+
+// Turns key presses into commands and entered numbers.
+#[derive(Debug, Clone, Default)]
+pub struct KeyInterpreter {
+    entry: NumberEntry,
+}
+
+impl KeyInterpreter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn key(&mut self, keypad: &Keypad, key: char, now: Instant) -> Option<KeypadInput> {
+        if key == keypad.enter_key {
+            return self.entry.confirm().map(KeypadInput::Number);
+        }
+        if let Some(cmd) = keypad.commands.get(&key) {
+            return Some(KeypadInput::Command(cmd.clone()));
+        }
+        self.entry.digit(key, now);
+        None
+    }
+
+    pub fn timeout(&mut self, keypad: &Keypad, now: Instant) -> Option<KeypadInput> {
+        let timeout = Duration::from_millis(keypad.entry_timeout_ms);
+        self.entry.timeout(timeout, now).map(KeypadInput::Number)
+    }
+}
+
+// Access to the lines of a keypad. Rows are selected one at a time, columns read true
+// while a key of the selected row is pressed.
+pub trait KeypadLines {
+    fn select_row(&mut self, row: usize) -> Result<()>;
+    fn read_columns(&mut self) -> Result<Vec<bool>>;
+}
+
+// Scans the keypad matrix. A key counts as pressed once it has been seen down in two
+// consecutive scans, which filters out contact bounce.
+#[derive(Debug)]
+pub struct KeypadScanner<L> {
+    lines: L,
+    rows: usize,
+    columns: usize,
+    // Keys down in the last scan, and keys considered pressed.
+    down: Vec<bool>,
+    pressed: Vec<bool>,
+}
+
+impl<L: KeypadLines> KeypadScanner<L> {
+    pub fn new(lines: L, rows: usize, columns: usize) -> Self {
+        KeypadScanner {
+            lines,
+            rows,
+            columns,
+            down: vec![false; rows * columns],
+            pressed: vec![false; rows * columns],
+        }
+    }
+
+    // Returns the keys pressed since the last scan as (row, column).
+    pub fn scan(&mut self) -> Result<Vec<(usize, usize)>> {
+        let mut newly_pressed = vec![];
+        for row in 0..self.rows {
+            self.lines.select_row(row)?;
+            let columns = self.lines.read_columns()?;
+            for column in 0..self.columns {
+                let i = row * self.columns + column;
+                let down = columns.get(column).copied().unwrap_or(false);
+                if down == self.down[i] && down != self.pressed[i] {
+                    self.pressed[i] = down;
+                    if down {
+                        newly_pressed.push((row, column));
+                    }
+                }
+                self.down[i] = down;
+            }
+        }
+        Ok(newly_pressed)
+    }
+}
+
+pub mod cdev_gpio {
+    use std::time::Duration;
+
+    use anyhow::Context;
+    use crossbeam_channel::Sender;
+    use gpio_cdev::{Chip, LineRequestFlags, MultiLineHandle};
+    use tracing::{debug, error, info};
+
+    use super::*;
+    use crate::components::config::ConfigLoaderHandle;
+    use crate::input_controller::button::{cdev_gpio::request_flags, Bias, Error};
+
+    const SCAN_INTERVAL: Duration = Duration::from_millis(10);
+
+    pub struct CdevKeypadLines {
+        rows: MultiLineHandle,
+        columns: MultiLineHandle,
+    }
+
+    impl CdevKeypadLines {
+        pub fn new(keypad: &Keypad) -> Result<Self> {
+            let mut chip = Chip::new(&keypad.chip).map_err(|err| {
+                Error::IO(format!("Failed to open chip {}: {:?}", keypad.chip, err))
+            })?;
+            // Rows are open drain, so that keys pressed in several rows at once do not
+            // short them.
+            let rows = chip
+                .get_lines(&keypad.rows)
+                .and_then(|lines| {
+                    lines.request(
+                        LineRequestFlags::OUTPUT
+                            | LineRequestFlags::ACTIVE_LOW
+                            | LineRequestFlags::OPEN_DRAIN,
+                        &vec![0; keypad.rows.len()],
+                        "keypad-rows",
+                    )
+                })
+                .map_err(|err| Error::IO(format!("Failed to request keypad row lines: {}", err)))?;
+            let columns = chip
+                .get_lines(&keypad.columns)
+                .and_then(|lines| {
+                    lines.request(
+                        request_flags(Bias::PullUp) | LineRequestFlags::ACTIVE_LOW,
+                        &vec![0; keypad.columns.len()],
+                        "keypad-columns",
+                    )
+                })
+                .map_err(|err| {
+                    Error::IO(format!("Failed to request keypad column lines: {}", err))
+                })?;
+            Ok(CdevKeypadLines { rows, columns })
+        }
+    }
+
+    impl KeypadLines for CdevKeypadLines {
+        fn select_row(&mut self, row: usize) -> Result<()> {
+            let values: Vec<u8> = (0..self.rows.num_lines())
+                .map(|i| (i == row) as u8)
+                .collect();
+            self.rows
+                .set_values(&values)
+                .map_err(|err| Error::IO(format!("Failed to select keypad row: {}", err)))?;
+            Ok(())
+        }
+
+        fn read_columns(&mut self) -> Result<Vec<bool>> {
+            let values = self
+                .columns
+                .get_values()
+                .map_err(|err| Error::IO(format!("Failed to read keypad columns: {}", err)))?;
+            Ok(values.into_iter().map(|value| value != 0).collect())
+        }
+    }
+
+    struct ActiveKeypad {
+        keypad: Keypad,
+        scanner: KeypadScanner<CdevKeypadLines>,
+        interpreter: KeyInterpreter,
+    }
+
+    impl ActiveKeypad {
+        fn open(keypad: Option<Keypad>) -> Option<Self> {
+            let keypad = keypad?;
+            info!(
+                "Scanning keypad with rows {:?} and columns {:?}",
+                keypad.rows, keypad.columns
+            );
+            match CdevKeypadLines::new(&keypad) {
+                Ok(lines) => Some(ActiveKeypad {
+                    scanner: KeypadScanner::new(lines, keypad.rows.len(), keypad.columns.len()),
+                    keypad,
+                    interpreter: KeyInterpreter::new(),
+                }),
+                Err(err) => {
+                    error!("Failed to open keypad: {:#}", err);
+                    None
+                }
+            }
+        }
+    }
+
+    // Scans the keypad and transmits the resulting inputs. The lines are requested anew
+    // whenever the keypad configuration changes.
+    fn run<T>(config: ConfigLoaderHandle, tx: Sender<T>)
+    where
+        T: From<Command> + From<Number>,
+    {
+        let updates = config.subscribe();
+        let mut active = ActiveKeypad::open(config.get().keypad);
+        loop {
+            if let Some(update) = updates.try_iter().last() {
+                if update.keypad != active.as_ref().map(|active| active.keypad.clone()) {
+                    info!("Keypad configuration changed");
+                    // Release the lines before requesting them anew.
+                    drop(active.take());
+                    active = ActiveKeypad::open(update.keypad);
+                }
+            }
+            let keypad = match active.as_mut() {
+                Some(keypad) => keypad,
+                None => {
+                    // Nothing to scan until the configuration changes.
+                    match updates.recv() {
+                        Ok(update) => {
+                            active = ActiveKeypad::open(update.keypad);
+                            continue;
+                        }
+                        Err(_) => return,
+                    }
+                }
+            };
+            let now = Instant::now();
+            let mut inputs = vec![];
+            match keypad.scanner.scan() {
+                Ok(keys) => {
+                    for (row, column) in keys {
+                        let key = match keypad.keypad.key(row, column) {
+                            Some(key) => key,
+                            None => continue,
+                        };
+                        debug!("Key {:?} pressed", key);
+                        inputs.extend(keypad.interpreter.key(&keypad.keypad, key, now));
+                    }
+                }
+                Err(err) => error!("Failed to scan keypad: {:#}", err),
+            }
+            inputs.extend(keypad.interpreter.timeout(&keypad.keypad, now));
+            for input in inputs {
+                let res = match input {
+                    KeypadInput::Command(cmd) => tx.send(cmd.into()),
+                    KeypadInput::Number(number) => tx.send(number.into()),
+                };
+                if let Err(err) = res {
+                    error!("Failed to transmit keypad input: {}", err);
+                }
+            }
+            std::thread::sleep(SCAN_INTERVAL);
+        }
+    }
+
+    pub fn spawn<T>(config: ConfigLoaderHandle, tx: Sender<T>) -> Result<()>
+    where
+        T: From<Command> + From<Number> + Send + 'static,
+    {
+        std::thread::Builder::new()
+            .name("keypad".to_string())
+            .spawn(move || run(config, tx))
+            .context("Spawning keypad scanner")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    // Keypad lines without hardware, the pressed keys are set by the caller.
+    #[derive(Debug, Clone)]
+    struct SimulatedKeypadLines {
+        columns: usize,
+        selected_row: Option<usize>,
+        pressed: Vec<(usize, usize)>,
+    }
+
+    impl SimulatedKeypadLines {
+        fn new(columns: usize) -> Self {
+            SimulatedKeypadLines {
+                columns,
+                selected_row: None,
+                pressed: vec![],
+            }
+        }
+    }
+
+    impl KeypadLines for SimulatedKeypadLines {
+        fn select_row(&mut self, row: usize) -> Result<()> {
+            self.selected_row = Some(row);
+            Ok(())
+        }
+
+        fn read_columns(&mut self) -> Result<Vec<bool>> {
+            let row = self
+                .selected_row
+                .ok_or_else(|| anyhow!("no keypad row selected"))?;
+            Ok((0..self.columns)
+                .map(|column| self.pressed.contains(&(row, column)))
+                .collect())
+        }
+    }
+
+    fn keypad() -> Keypad {
+        serde_yaml::from_str(
+            r#"
+            rows: [5, 6, 13, 19]
+            columns: [12, 16, 20]
+            commands:
+              "*": pause_continue
+            "#,
+        )
+        .unwrap()
+    }
+
+    fn scanner() -> KeypadScanner<SimulatedKeypadLines> {
+        KeypadScanner::new(SimulatedKeypadLines::new(3), 4, 3)
+    }
+
+    #[test]
+    fn keys_are_reported_once_down_in_two_scans() {
+        let mut scanner = scanner();
+        scanner.lines.pressed = vec![(1, 2)];
+        assert_eq!(scanner.scan().unwrap(), vec![]);
+        assert_eq!(scanner.scan().unwrap(), vec![(1, 2)]);
+        // Held keys are not reported again.
+        assert_eq!(scanner.scan().unwrap(), vec![]);
+
+        scanner.lines.pressed = vec![(1, 2), (3, 0)];
+        assert_eq!(scanner.scan().unwrap(), vec![]);
+        assert_eq!(scanner.scan().unwrap(), vec![(3, 0)]);
+
+        // Released and pressed again.
+        scanner.lines.pressed = vec![];
+        assert_eq!(scanner.scan().unwrap(), vec![]);
+        assert_eq!(scanner.scan().unwrap(), vec![]);
+        scanner.lines.pressed = vec![(1, 2)];
+        assert_eq!(scanner.scan().unwrap(), vec![]);
+        assert_eq!(scanner.scan().unwrap(), vec![(1, 2)]);
+    }
+
+    #[test]
+    fn bounce_is_filtered_out() {
+        let mut scanner = scanner();
+        for pressed in [true, false, true, false] {
+            scanner.lines.pressed = if pressed { vec![(0, 0)] } else { vec![] };
+            assert_eq!(scanner.scan().unwrap(), vec![]);
+        }
+
+        // A held key bouncing open for a single scan is not pressed anew.
+        scanner.lines.pressed = vec![(0, 0)];
+        scanner.scan().unwrap();
+        assert_eq!(scanner.scan().unwrap(), vec![(0, 0)]);
+        scanner.lines.pressed = vec![];
+        assert_eq!(scanner.scan().unwrap(), vec![]);
+        scanner.lines.pressed = vec![(0, 0)];
+        assert_eq!(scanner.scan().unwrap(), vec![]);
+        assert_eq!(scanner.scan().unwrap(), vec![]);
+    }
+
+    #[test]
+    fn keys_map_to_the_layout() {
+        let keypad = keypad();
+        assert_eq!(keypad.key(0, 0), Some('1'));
+        assert_eq!(keypad.key(2, 1), Some('8'));
+        assert_eq!(keypad.key(3, 2), Some('#'));
+        assert_eq!(keypad.key(4, 0), None);
+        assert_eq!(keypad.key(0, 3), None);
+    }
+
+    // Presses the given keys one after another, each a second after the previous one.
+    fn press(
+        interpreter: &mut KeyInterpreter,
+        keypad: &Keypad,
+        keys: &str,
+        start: Instant,
+    ) -> Vec<KeypadInput> {
+        keys.chars()
+            .enumerate()
+            .filter_map(|(i, key)| {
+                let now = start + Duration::from_secs(i as u64);
+                interpreter.key(keypad, key, now)
+            })
+            .collect()
+    }
+
+    fn number(digits: &str) -> KeypadInput {
+        KeypadInput::Number(Number(digits.to_string()))
+    }
+
+    #[test]
+    fn numbers_are_confirmed_with_the_enter_key() {
+        let keypad = keypad();
+        let mut interpreter = KeyInterpreter::new();
+        let start = Instant::now();
+        assert_eq!(
+            press(&mut interpreter, &keypad, "12#", start),
+            vec![number("12")]
+        );
+        // Leading zeros are kept.
+        assert_eq!(
+            press(&mut interpreter, &keypad, "012#", start),
+            vec![number("012")]
+        );
+        // Nothing entered.
+        assert_eq!(press(&mut interpreter, &keypad, "#", start), vec![]);
+        // Digits beyond the maximum are ignored.
+        assert_eq!(
+            press(&mut interpreter, &keypad, "123456789#", start),
+            vec![number("123456")]
+        );
+    }
+
+    #[test]
+    fn numbers_are_confirmed_after_the_timeout() {
+        let keypad = keypad();
+        let mut interpreter = KeyInterpreter::new();
+        let start = Instant::now();
+        assert_eq!(press(&mut interpreter, &keypad, "07", start), vec![]);
+        let last_digit = start + Duration::from_secs(1);
+        let timeout = Duration::from_millis(keypad.entry_timeout_ms);
+        assert_eq!(
+            interpreter.timeout(&keypad, last_digit + timeout - Duration::from_millis(1)),
+            None
+        );
+        assert_eq!(
+            interpreter.timeout(&keypad, last_digit + timeout),
+            Some(number("07"))
+        );
+        assert_eq!(interpreter.timeout(&keypad, last_digit + timeout * 2), None);
+    }
+
+    #[test]
+    fn command_keys_trigger_commands() {
+        let keypad = keypad();
+        let mut interpreter = KeyInterpreter::new();
+        let start = Instant::now();
+        assert_eq!(
+            press(&mut interpreter, &keypad, "4*2#", start),
+            vec![KeypadInput::Command(Command::PauseContinue), number("42")]
+        );
+    }
+}
@@ -1,5 +1,11 @@
 pub mod button;
+pub mod evdev;
+pub mod gesture;
+pub mod keypad;
+pub mod potentiometer;
 pub mod rfid_playback;
+pub mod rotary_encoder;
+pub mod touch_pads;
 
 use std::convert::From;
 
@@ -9,6 +15,8 @@ use crate::player::PlaybackRequest;
 pub enum Input {
     Button(button::Command),
     Playback(PlaybackRequest),
+    Volume(potentiometer::VolumeLevel),
+    Number(keypad::Number),
 }
 
 impl From<button::Command> for Input {
@@ -22,3 +30,15 @@ impl From<PlaybackRequest> for Input {
         Input::Playback(req)
     }
 }
+
+impl From<potentiometer::VolumeLevel> for Input {
+    fn from(level: potentiometer::VolumeLevel) -> Self {
+        Input::Volume(level)
+    }
+}
+
+impl From<keypad::Number> for Input {
+    fn from(number: keypad::Number) -> Self {
+        Input::Number(number)
+    }
+}
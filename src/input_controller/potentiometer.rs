@@ -0,0 +1,264 @@
+use serde::Deserialize;
+
+use crate::components::mcp3x08;
+
+// A volume potentiometer connected to an MCP3008 or MCP3208 ADC is configured in the
+// main configuration as e.g.:
+//
+// potentiometer:
+//   spi_device: /dev/spidev0.1
+//   adc: mcp3208
+//   channel: 0
+//   hysteresis: 2
+//
+// The full turn of the knob maps onto the volume levels up to the configured maximum.
+// The level only follows the knob once it has been turned by more than `hysteresis`
+// levels, so that a reading jittering between two levels does not change the volume.
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Potentiometer {
+    #[serde(default = "default_spi_device")]
+    pub spi_device: String,
+    #[serde(default)]
+    pub adc: mcp3x08::Model,
+    #[serde(default)]
+    pub channel: u8,
+    #[serde(default = "default_hysteresis")]
+    pub hysteresis: u32,
+}
+
+// The RFID reader occupies the first chip select.
+fn default_spi_device() -> String {
+    "/dev/spidev0.1".to_string()
+}
+
+fn default_hysteresis() -> u32 {
+    2
+}
+
+// Absolute volume level in percent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VolumeLevel(pub u32);
+
+// Maps ADC readings onto volume levels.
+#[derive(Debug, Clone)]
+pub struct VolumeKnob {
+    hysteresis: u32,
+    level: Option<u32>,
+}
+
+impl VolumeKnob {
+    pub fn new(hysteresis: u32) -> Self {
+        VolumeKnob {
+            hysteresis,
+            level: None,
+        }
+    }
+
+    // Returns the new level, if the reading deviates sufficiently from the current one.
+    pub fn update(&mut self, reading: u16, full_scale: u16, max_level: u32) -> Option<u32> {
+        let exact = reading.min(full_scale) as f64 * max_level as f64 / full_scale as f64;
+        let level = exact.round() as u32;
+        if let Some(current) = self.level {
+            if level == current {
+                return None;
+            }
+            // The ends of the range are always reachable.
+            let at_end = level == 0 || level == max_level;
+            if !at_end && (exact - current as f64).abs() <= self.hysteresis as f64 {
+                return None;
+            }
+        }
+        self.level = Some(level);
+        Some(level)
+    }
+}
+
+pub mod spi {
+    use std::time::Duration;
+
+    use anyhow::{Context, Result};
+    use crossbeam_channel::Sender;
+    use linux_embedded_hal::SpidevDevice;
+    use tracing::{error, info, trace};
+
+    use super::*;
+    use crate::components::config::ConfigLoaderHandle;
+    use crate::components::mcp3x08::Mcp3x08;
+    use crate::components::spi;
+
+    const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+    struct Adc {
+        potentiometer: Potentiometer,
+        mcp3x08: Mcp3x08<SpidevDevice>,
+        knob: VolumeKnob,
+    }
+
+    impl Adc {
+        fn open(potentiometer: Potentiometer) -> Result<Self> {
+            info!(
+                "Reading volume potentiometer via {:?} on {}, channel {}",
+                potentiometer.adc, potentiometer.spi_device, potentiometer.channel
+            );
+            let spi = spi::open(&potentiometer.spi_device)?;
+            let mcp3x08 = Mcp3x08::new(spi, potentiometer.adc);
+            let knob = VolumeKnob::new(potentiometer.hysteresis);
+            Ok(Adc {
+                potentiometer,
+                mcp3x08,
+                knob,
+            })
+        }
+
+        fn open_configured(potentiometer: Option<Potentiometer>) -> Option<Self> {
+            match Adc::open(potentiometer?) {
+                Ok(adc) => Some(adc),
+                Err(err) => {
+                    error!("Failed to open volume potentiometer: {:#}", err);
+                    None
+                }
+            }
+        }
+    }
+
+    // Polls the potentiometer and transmits the volume level whenever the knob is turned.
+    // The ADC is opened anew whenever the potentiometer configuration changes.
+    fn run<T: From<VolumeLevel>>(config: ConfigLoaderHandle, tx: Sender<T>) {
+        let updates = config.subscribe();
+        let mut config = config.get();
+        let mut adc = Adc::open_configured(config.potentiometer.clone());
+        loop {
+            if let Some(update) = updates.try_iter().last() {
+                if update.potentiometer != adc.as_ref().map(|adc| adc.potentiometer.clone()) {
+                    info!("Potentiometer configuration changed");
+                    adc = Adc::open_configured(update.potentiometer.clone());
+                }
+                config = update;
+            }
+            let adc = match adc.as_mut() {
+                Some(adc) => adc,
+                None => {
+                    // Nothing to poll until the configuration changes.
+                    match updates.recv() {
+                        Ok(update) => {
+                            adc = Adc::open_configured(update.potentiometer.clone());
+                            config = update;
+                            continue;
+                        }
+                        Err(_) => return,
+                    }
+                }
+            };
+            match adc.mcp3x08.read(adc.potentiometer.channel) {
+                Ok(reading) => {
+                    let full_scale = adc.mcp3x08.model().full_scale();
+                    if let Some(level) = adc.knob.update(reading, full_scale, config.max_volume) {
+                        trace!("Potentiometer reading {} maps to {}%", reading, level);
+                        if let Err(err) = tx.send(VolumeLevel(level).into()) {
+                            error!("Failed to transmit volume level: {}", err);
+                        }
+                    }
+                }
+                Err(err) => error!("Failed to read volume potentiometer: {}", err),
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    pub fn spawn<T: From<VolumeLevel> + Send + 'static>(
+        config: ConfigLoaderHandle,
+        tx: Sender<T>,
+    ) -> Result<()> {
+        std::thread::Builder::new()
+            .name("potentiometer".to_string())
+            .spawn(move || run(config, tx))
+            .context("Spawning potentiometer reader")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Full scale of the 12 bit MCP3208, about 41 per volume level.
+    const FULL_SCALE: u16 = 4095;
+
+    fn readings(knob: &mut VolumeKnob, readings: &[u16], max_level: u32) -> Vec<u32> {
+        readings
+            .iter()
+            .filter_map(|reading| knob.update(*reading, FULL_SCALE, max_level))
+            .collect()
+    }
+
+    #[test]
+    fn first_reading_sets_the_level() {
+        let mut knob = VolumeKnob::new(2);
+        assert_eq!(knob.update(2048, FULL_SCALE, 100), Some(50));
+        assert_eq!(knob.update(2048, FULL_SCALE, 100), None);
+    }
+
+    #[test]
+    fn jitter_around_a_step_boundary_is_ignored() {
+        let mut knob = VolumeKnob::new(2);
+        // 2068 lies right between the levels 50 and 51.
+        assert_eq!(knob.update(2068, FULL_SCALE, 100), Some(51));
+        let jitter = [2060, 2076, 2049, 2090, 2068, 2055, 2081, 2066, 2070];
+        assert!(readings(&mut knob, &jitter, 100).is_empty());
+    }
+
+    #[test]
+    fn movement_changes_the_level_once() {
+        let mut knob = VolumeKnob::new(2);
+        assert_eq!(knob.update(2048, FULL_SCALE, 100), Some(50));
+        assert_eq!(
+            readings(&mut knob, &[3000, 3010, 2990, 3000], 100),
+            vec![73]
+        );
+        // Just beyond the hysteresis.
+        assert_eq!(readings(&mut knob, &[2880, 2870], 100), vec![70]);
+    }
+
+    #[test]
+    fn full_scale_maps_to_max_level() {
+        let mut knob = VolumeKnob::new(2);
+        assert_eq!(knob.update(FULL_SCALE, FULL_SCALE, 100), Some(100));
+        assert_eq!(knob.update(0, FULL_SCALE, 100), Some(0));
+        assert_eq!(knob.update(FULL_SCALE, FULL_SCALE, 60), Some(60));
+        // Readings beyond full scale are clamped.
+        assert_eq!(knob.update(u16::MAX, FULL_SCALE, 60), None);
+        assert_eq!(knob.update(2048, FULL_SCALE, 60), Some(30));
+    }
+
+    #[test]
+    fn ends_are_reachable_within_hysteresis() {
+        let mut knob = VolumeKnob::new(2);
+        assert_eq!(knob.update(4040, FULL_SCALE, 100), Some(99));
+        assert_eq!(knob.update(FULL_SCALE, FULL_SCALE, 100), Some(100));
+        assert_eq!(knob.update(50, FULL_SCALE, 100), Some(1));
+        assert_eq!(knob.update(0, FULL_SCALE, 100), Some(0));
+    }
+
+    #[test]
+    fn slow_turns_follow_the_knob() {
+        let mut knob = VolumeKnob::new(2);
+        let sweep: Vec<u16> = (0..=FULL_SCALE)
+            .step_by(5)
+            .chain(Some(FULL_SCALE))
+            .collect();
+        let levels = readings(&mut knob, &sweep, 100);
+        assert_eq!(levels.first(), Some(&0));
+        assert_eq!(levels.last(), Some(&100));
+        for pair in levels.windows(2) {
+            assert!(pair[0] < pair[1], "{:?}", levels);
+            assert!(pair[1] - pair[0] <= 3, "{:?}", levels);
+        }
+        // Without hysteresis, every level is passed.
+        let mut knob = VolumeKnob::new(0);
+        assert_eq!(
+            readings(&mut knob, &sweep, 100),
+            (0..=100).collect::<Vec<_>>()
+        );
+    }
+}
@@ -29,6 +29,7 @@ pub mod rfid {
     where
         T: From<PlaybackRequest>,
     {
+        #[allow(clippy::new_ret_no_self)]
         pub fn new(inputs_tx: Sender<T>) -> Result<()> {
             let picc = RfidController::new().context("Creating RfidController")?;
             let transmitter = Self {
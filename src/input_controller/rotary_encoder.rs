@@ -0,0 +1,535 @@
+use serde::Deserialize;
+
+use crate::input_controller::button::{Bias, Button, Command};
+
+// A rotary encoder is configured in the main configuration as e.g.:
+//
+// rotary_encoder:
+//   line_a: 17
+//   line_b: 27
+//   action: volume
+//   switch:
+//     line: 22
+//     short_press: pause_continue
+//     long_press: sleep_timer
+//
+// The lines are requested from /dev/gpiochip0 unless `chip` is given. The push switch
+// is configured like a button. If the knob turns the wrong way, set `reversed: true`.
+// The encoder lines are pulled up by default, as bare encoders connect them to ground
+// and leave them floating otherwise. Set `bias` like for buttons to change that, e.g.
+// to `as_is` for modules with pull-up resistors on board.
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RotaryAction {
+    #[default]
+    Volume,
+    Track,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Clockwise,
+    CounterClockwise,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct RotaryEncoder {
+    #[serde(default = "default_chip")]
+    pub chip: String,
+    pub line_a: u32,
+    pub line_b: u32,
+    #[serde(default)]
+    pub action: RotaryAction,
+    #[serde(default)]
+    pub reversed: bool,
+    // Quadrature steps between two detents of the knob.
+    #[serde(default = "default_steps_per_detent")]
+    pub steps_per_detent: u32,
+    #[serde(default = "default_bias")]
+    pub bias: Bias,
+    #[serde(default)]
+    pub switch: Option<Button>,
+}
+
+fn default_chip() -> String {
+    "/dev/gpiochip0".to_string()
+}
+
+fn default_steps_per_detent() -> u32 {
+    4
+}
+
+fn default_bias() -> Bias {
+    Bias::PullUp
+}
+
+impl RotaryEncoder {
+    pub fn command(&self, direction: Direction) -> Command {
+        let direction = match (direction, self.reversed) {
+            (direction, false) => direction,
+            (Direction::Clockwise, true) => Direction::CounterClockwise,
+            (Direction::CounterClockwise, true) => Direction::Clockwise,
+        };
+        match (self.action, direction) {
+            (RotaryAction::Volume, Direction::Clockwise) => Command::VolumeUp,
+            (RotaryAction::Volume, Direction::CounterClockwise) => Command::VolumeDown,
+            (RotaryAction::Track, Direction::Clockwise) => Command::NextTrack,
+            (RotaryAction::Track, Direction::CounterClockwise) => Command::PreviousTrack,
+        }
+    }
+}
+
+// Decodes the levels of the two encoder lines. Turning clockwise, the lines run
+// through 00, 10, 11, 01 (A, B). Transitions skipping a state are ignored, contact
+// bounce cancels itself out.
+#[derive(Debug, Clone)]
+pub struct QuadratureDecoder {
+    steps_per_detent: i32,
+    state: Option<u8>,
+    steps: i32,
+}
+
+impl QuadratureDecoder {
+    pub fn new(steps_per_detent: u32) -> Self {
+        QuadratureDecoder {
+            steps_per_detent: steps_per_detent.max(1) as i32,
+            state: None,
+            steps: 0,
+        }
+    }
+
+    fn step(from: u8, to: u8) -> i32 {
+        match (from, to) {
+            (0b00, 0b10) | (0b10, 0b11) | (0b11, 0b01) | (0b01, 0b00) => 1,
+            (0b00, 0b01) | (0b01, 0b11) | (0b11, 0b10) | (0b10, 0b00) => -1,
+            _ => 0,
+        }
+    }
+
+    // Returns the direction once the knob has been turned by a detent.
+    pub fn update(&mut self, a: bool, b: bool) -> Option<Direction> {
+        let state = (a as u8) << 1 | b as u8;
+        let previous = self.state.replace(state)?;
+        self.steps += Self::step(previous, state);
+        if self.steps >= self.steps_per_detent {
+            self.steps = 0;
+            Some(Direction::Clockwise)
+        } else if self.steps <= -self.steps_per_detent {
+            self.steps = 0;
+            Some(Direction::CounterClockwise)
+        } else {
+            None
+        }
+    }
+}
+
+pub mod cdev_gpio {
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread::JoinHandle;
+    use std::time::Instant;
+
+    use anyhow::{Context, Result};
+    use crossbeam_channel::{self, Sender};
+    use gpio_cdev::{Chip, EventRequestFlags, LineEventHandle};
+    use tracing::{error, info, trace, warn};
+
+    use super::*;
+    use crate::components::config::ConfigLoaderHandle;
+    use crate::input_controller::button::{self, cdev_gpio::request_flags, Error};
+    use crate::input_controller::gesture::{Edge, GestureTiming};
+    use crate::model;
+
+    // Interval at which the listener checks whether it is to stop.
+    const POLL_INTERVAL_MS: libc::c_int = 200;
+
+    // Levels of the push switch are handed to a gesture detector, see
+    // `button::cdev_gpio`.
+    struct Switch {
+        events: LineEventHandle,
+        button: Button,
+        edges_tx: Sender<(Edge, Instant)>,
+    }
+
+    #[derive(Debug)]
+    struct Listener {
+        stop: Arc<AtomicBool>,
+        thread: JoinHandle<()>,
+    }
+
+    // Requests the lines of the configured rotary encoder and requests them anew whenever
+    // its configuration changes. Without an encoder configured, no lines are requested.
+    #[derive(Debug)]
+    pub struct CdevRotaryEncoder<T> {
+        encoder: Option<RotaryEncoder>,
+        timing: GestureTiming,
+        tx: Sender<T>,
+        listener: Option<Listener>,
+    }
+
+    fn request_events(
+        chip: &mut Chip,
+        line: u32,
+        bias: Bias,
+        consumer: &str,
+    ) -> Result<LineEventHandle> {
+        let line = chip
+            .get_line(line)
+            .map_err(|err| Error::IO(format!("Failed to get GPIO line: {:?}", err)))?;
+        let events = line
+            .events(request_flags(bias), EventRequestFlags::BOTH_EDGES, consumer)
+            .map_err(|err| {
+                Error::IO(format!(
+                    "Failed to request events from GPIO line {}: {}",
+                    line.offset(),
+                    err
+                ))
+            })?;
+        Ok(events)
+    }
+
+    fn timing(config: &model::config::Config) -> GestureTiming {
+        GestureTiming {
+            long_press: std::time::Duration::from_millis(config.long_press_ms),
+            double_press: std::time::Duration::from_millis(config.double_press_ms),
+            ..GestureTiming::default()
+        }
+    }
+
+    impl<T: Send + 'static> CdevRotaryEncoder<T>
+    where
+        T: From<Command>,
+    {
+        #[allow(clippy::new_ret_no_self)]
+        pub fn new(config: ConfigLoaderHandle, input_tx: Sender<T>) -> Result<()> {
+            let updates = config.subscribe();
+            let config = config.get();
+            let mut encoder = CdevRotaryEncoder {
+                encoder: config.rotary_encoder.clone(),
+                timing: timing(&config),
+                tx: input_tx,
+                listener: None,
+            };
+            if encoder.encoder.is_some() {
+                info!("Using CdevGpio based rotary encoder");
+            }
+            encoder
+                .request_lines()
+                .context("Running rotary encoder listener")?;
+
+            std::thread::Builder::new()
+                .name("rotary-encoder-config-watcher".to_string())
+                .spawn(move || {
+                    for config in updates {
+                        encoder.reconfigure(&config);
+                    }
+                })
+                .context("Spawning rotary encoder configuration watcher")?;
+            Ok(())
+        }
+
+        fn reconfigure(&mut self, config: &model::config::Config) {
+            let timing = timing(config);
+            if config.rotary_encoder == self.encoder && timing == self.timing {
+                return;
+            }
+            info!("Rotary encoder configuration changed, requesting GPIO lines anew");
+            self.release_lines();
+            self.encoder = config.rotary_encoder.clone();
+            self.timing = timing;
+            if let Err(err) = self.request_lines() {
+                error!("Failed to request GPIO lines of rotary encoder: {:#}", err);
+            }
+        }
+
+        fn run_event_listener(
+            encoder: &RotaryEncoder,
+            mut line_a: LineEventHandle,
+            mut line_b: LineEventHandle,
+            mut switch: Option<Switch>,
+            tx: &Sender<T>,
+            stop: &AtomicBool,
+        ) -> Result<()> {
+            info!(
+                "Listening for rotary encoder events on lines {} and {}",
+                encoder.line_a, encoder.line_b
+            );
+            let mut poll_fds: Vec<libc::pollfd> = [Some(&line_a), Some(&line_b)]
+                .iter()
+                .cloned()
+                .chain(std::iter::once(
+                    switch.as_ref().map(|switch| &switch.events),
+                ))
+                .flatten()
+                .map(|events| libc::pollfd {
+                    fd: events.as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                })
+                .collect();
+            let read_value = |events: &LineEventHandle| -> Result<bool> {
+                let value = events.get_value().map_err(|err| {
+                    Error::IO(format!(
+                        "Failed to read value of GPIO line {}: {}",
+                        events.line().offset(),
+                        err
+                    ))
+                })?;
+                Ok(value != 0)
+            };
+            let read_event = |events: &mut LineEventHandle| {
+                events.get_event().map_err(|err| {
+                    Error::IO(format!(
+                        "Failed to read event from GPIO line {}: {}",
+                        events.line().offset(),
+                        err
+                    ))
+                })
+            };
+
+            let mut decoder = QuadratureDecoder::new(encoder.steps_per_detent);
+            decoder.update(read_value(&line_a)?, read_value(&line_b)?);
+            let mut switch_level = match switch {
+                Some(ref switch) => Some(switch.button.edge(read_value(&switch.events)?)),
+                None => None,
+            };
+            while !stop.load(Ordering::Relaxed) {
+                let res = unsafe {
+                    libc::poll(
+                        poll_fds.as_mut_ptr(),
+                        poll_fds.len() as libc::nfds_t,
+                        POLL_INTERVAL_MS,
+                    )
+                };
+                if res < 0 {
+                    let err = io::Error::last_os_error();
+                    if err.kind() == io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    return Err(err).context("Polling GPIO lines of rotary encoder");
+                }
+                if res > 0 && (poll_fds[0].revents != 0 || poll_fds[1].revents != 0) {
+                    // Both lines are read anew, the events merely signal a change.
+                    for (i, events) in [&mut line_a, &mut line_b].iter_mut().enumerate() {
+                        if poll_fds[i].revents != 0 {
+                            read_event(events)?;
+                        }
+                    }
+                    let (a, b) = (read_value(&line_a)?, read_value(&line_b)?);
+                    trace!("Rotary encoder lines at A={} B={}", a, b);
+                    if let Some(direction) = decoder.update(a, b) {
+                        if let Err(err) = tx.send(encoder.command(direction).into()) {
+                            error!("Failed to transmit rotary encoder command: {}", err);
+                        }
+                    }
+                }
+                if let Some(switch) = switch.as_mut() {
+                    // The level is read again while the switch is idle, to recover from
+                    // lost edges.
+                    let event = res > 0 && poll_fds[2].revents != 0;
+                    if event {
+                        read_event(&mut switch.events)?;
+                    }
+                    let edge = switch.button.edge(read_value(&switch.events)?);
+                    if event || Some(edge) != switch_level {
+                        switch_level = Some(edge);
+                        if switch.edges_tx.send((edge, Instant::now())).is_err() {
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        fn request_lines(&mut self) -> Result<()> {
+            let encoder = match self.encoder {
+                Some(ref encoder) => encoder.clone(),
+                None => return Ok(()),
+            };
+            let mut chip = Chip::new(&encoder.chip).map_err(|err| {
+                Error::IO(format!("Failed to open chip {}: {:?}", encoder.chip, err))
+            })?;
+            let line_a = request_events(&mut chip, encoder.line_a, encoder.bias, "rotary-encoder")?;
+            let line_b = request_events(&mut chip, encoder.line_b, encoder.bias, "rotary-encoder")?;
+            let switch = match encoder.switch {
+                Some(ref switch) => {
+                    let mut switch_chip = Chip::new(&switch.chip).map_err(|err| {
+                        Error::IO(format!("Failed to open chip {}: {:?}", switch.chip, err))
+                    })?;
+                    let events =
+                        request_events(&mut switch_chip, switch.line, switch.bias, "read-input")?;
+                    let (edges_tx, edges_rx) = crossbeam_channel::unbounded();
+                    let button = switch.clone();
+                    let timing = self.timing;
+                    let tx = self.tx.clone();
+                    std::thread::Builder::new()
+                        .name(format!("button-gestures-{}", switch.line))
+                        .spawn(move || button::run_gesture_detector(button, timing, edges_rx, tx))
+                        .context("Spawning gesture detector")?;
+                    Some(Switch {
+                        events,
+                        button: switch.clone(),
+                        edges_tx,
+                    })
+                }
+                None => None,
+            };
+
+            let stop = Arc::new(AtomicBool::new(false));
+            let thread = {
+                let stop = stop.clone();
+                let tx = self.tx.clone();
+                std::thread::Builder::new()
+                    .name("rotary-encoder".to_string())
+                    .spawn(move || {
+                        let res =
+                            Self::run_event_listener(&encoder, line_a, line_b, switch, &tx, &stop);
+                        if let Err(err) = res {
+                            error!("Rotary encoder listener terminated unexpectedly: {:?}", err);
+                        }
+                    })
+                    .context("Spawning rotary encoder listener")?
+            };
+            self.listener = Some(Listener { stop, thread });
+            Ok(())
+        }
+
+        fn release_lines(&mut self) {
+            if let Some(listener) = self.listener.take() {
+                listener.stop.store(true, Ordering::Relaxed);
+                if listener.thread.join().is_err() {
+                    warn!("Rotary encoder listener panicked");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Levels of the lines (A, B) while turning clockwise by one full cycle, starting
+    // and ending at rest.
+    const CLOCKWISE: [(bool, bool); 5] = [
+        (false, false),
+        (true, false),
+        (true, true),
+        (false, true),
+        (false, false),
+    ];
+
+    fn decode(decoder: &mut QuadratureDecoder, levels: &[(bool, bool)]) -> Vec<Direction> {
+        levels
+            .iter()
+            .filter_map(|(a, b)| decoder.update(*a, *b))
+            .collect()
+    }
+
+    fn counter_clockwise() -> Vec<(bool, bool)> {
+        CLOCKWISE.iter().rev().cloned().collect()
+    }
+
+    #[test]
+    fn decodes_detents() {
+        let mut decoder = QuadratureDecoder::new(4);
+        assert_eq!(decode(&mut decoder, &CLOCKWISE), vec![Direction::Clockwise]);
+        assert_eq!(
+            decode(&mut decoder, &counter_clockwise()),
+            vec![Direction::CounterClockwise]
+        );
+
+        // Encoders with two detents per cycle.
+        let mut decoder = QuadratureDecoder::new(2);
+        assert_eq!(
+            decode(&mut decoder, &CLOCKWISE),
+            vec![Direction::Clockwise, Direction::Clockwise]
+        );
+    }
+
+    #[test]
+    fn partial_turns_are_not_reported() {
+        let mut decoder = QuadratureDecoder::new(4);
+        // Turned halfway and back.
+        let levels = [
+            (false, false),
+            (true, false),
+            (true, true),
+            (true, false),
+            (false, false),
+        ];
+        assert_eq!(decode(&mut decoder, &levels), vec![]);
+        assert_eq!(decode(&mut decoder, &CLOCKWISE), vec![Direction::Clockwise]);
+    }
+
+    #[test]
+    fn bounce_cancels_out() {
+        let mut decoder = QuadratureDecoder::new(4);
+        let levels = [
+            (false, false),
+            (true, false),
+            (false, false),
+            (true, false),
+            (true, true),
+            (true, false),
+            (true, true),
+            (true, true),
+            (false, true),
+            (false, false),
+            (false, true),
+            (false, false),
+        ];
+        assert_eq!(decode(&mut decoder, &levels), vec![Direction::Clockwise]);
+    }
+
+    #[test]
+    fn invalid_transitions_are_ignored() {
+        let mut decoder = QuadratureDecoder::new(4);
+        // Both lines changing at once, the direction cannot be told.
+        let levels = [
+            (false, false),
+            (true, true),
+            (false, false),
+            (true, true),
+            (false, false),
+        ];
+        assert_eq!(decode(&mut decoder, &levels), vec![]);
+
+        // A skipped state does not count, the remaining steps do not make a detent.
+        let levels = [(false, false), (true, false), (false, true), (false, false)];
+        assert_eq!(decode(&mut decoder, &levels), vec![]);
+    }
+
+    #[test]
+    fn maps_directions_to_commands() {
+        let mut encoder: RotaryEncoder = serde_yaml::from_str("{line_a: 17, line_b: 27}").unwrap();
+        assert_eq!(encoder.bias, Bias::PullUp);
+        assert_eq!(encoder.command(Direction::Clockwise), Command::VolumeUp);
+        assert_eq!(
+            encoder.command(Direction::CounterClockwise),
+            Command::VolumeDown
+        );
+
+        encoder.reversed = true;
+        encoder.action = RotaryAction::Track;
+        assert_eq!(
+            encoder.command(Direction::Clockwise),
+            Command::PreviousTrack
+        );
+        assert_eq!(
+            encoder.command(Direction::CounterClockwise),
+            Command::NextTrack
+        );
+    }
+
+    #[test]
+    fn bias_is_configurable() {
+        let encoder: RotaryEncoder =
+            serde_yaml::from_str("{line_a: 17, line_b: 27, bias: as_is}").unwrap();
+        assert_eq!(encoder.bias, Bias::AsIs);
+    }
+}
@@ -0,0 +1,308 @@
+use serde::{Deserialize, Deserializer};
+
+use crate::components::mpr121::{self, Thresholds};
+use crate::input_controller::button::Command;
+
+// Touch pads connected to an MPR121 are configured in the main configuration as e.g.:
+//
+// touch_pads:
+//   device: /dev/i2c-1
+//   touch_threshold: 12
+//   release_threshold: 6
+//   pads:
+//     - electrode: 0
+//       command: pause_continue
+//     - electrode: 1
+//       command: volume_up
+//       touch_threshold: 20
+//
+// Commands are triggered when a pad is touched. Lower thresholds make the pads more
+// sensitive, e.g. behind thicker material. Per pad thresholds override the global ones.
+// Electrodes are numbered from 0 to 11.
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct TouchPad {
+    #[serde(deserialize_with = "deserialize_electrode")]
+    pub electrode: u8,
+    pub command: Command,
+    #[serde(default)]
+    pub touch_threshold: Option<u8>,
+    #[serde(default)]
+    pub release_threshold: Option<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct TouchPads {
+    #[serde(default = "default_device")]
+    pub device: String,
+    #[serde(default = "default_address")]
+    pub address: u8,
+    #[serde(default = "default_touch_threshold")]
+    pub touch_threshold: u8,
+    #[serde(default = "default_release_threshold")]
+    pub release_threshold: u8,
+    pub pads: Vec<TouchPad>,
+}
+
+fn deserialize_electrode<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u8, D::Error> {
+    let electrode = u8::deserialize(deserializer)?;
+    if electrode >= mpr121::ELECTRODES {
+        return Err(serde::de::Error::custom(format!(
+            "invalid electrode {}, expected 0 to {}",
+            electrode,
+            mpr121::ELECTRODES - 1
+        )));
+    }
+    Ok(electrode)
+}
+
+fn default_device() -> String {
+    "/dev/i2c-1".to_string()
+}
+
+fn default_address() -> u8 {
+    mpr121::DEFAULT_ADDRESS
+}
+
+fn default_touch_threshold() -> u8 {
+    Thresholds::default().touch
+}
+
+fn default_release_threshold() -> u8 {
+    Thresholds::default().release
+}
+
+impl TouchPads {
+    // Thresholds of all electrodes, including those without a pad.
+    pub fn thresholds(&self) -> Vec<Thresholds> {
+        (0..mpr121::ELECTRODES)
+            .map(|electrode| {
+                let pad = self.pads.iter().find(|pad| pad.electrode == electrode);
+                Thresholds {
+                    touch: pad
+                        .and_then(|pad| pad.touch_threshold)
+                        .unwrap_or(self.touch_threshold),
+                    release: pad
+                        .and_then(|pad| pad.release_threshold)
+                        .unwrap_or(self.release_threshold),
+                }
+            })
+            .collect()
+    }
+
+    // Returns the commands of the pads touched since the previous status.
+    pub fn commands(&self, previous: u16, current: u16) -> Vec<Command> {
+        let touched = current & !previous;
+        self.pads
+            .iter()
+            .filter(|pad| pad.electrode < mpr121::ELECTRODES && touched & (1 << pad.electrode) != 0)
+            .map(|pad| pad.command.clone())
+            .collect()
+    }
+}
+
+pub mod i2c {
+    use std::time::Duration;
+
+    use anyhow::{Context, Result};
+    use crossbeam_channel::Sender;
+    use linux_embedded_hal::I2cdev;
+    use tracing::{debug, error, info};
+
+    use super::*;
+    use crate::components::config::ConfigLoaderHandle;
+    use crate::components::mpr121::Mpr121;
+
+    const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+    struct Controller {
+        touch_pads: TouchPads,
+        mpr121: Mpr121<I2cdev>,
+        touched: u16,
+    }
+
+    impl Controller {
+        fn open(touch_pads: TouchPads) -> Result<Self> {
+            info!(
+                "Reading touch pads via MPR121 at {:#x} on {}",
+                touch_pads.address, touch_pads.device
+            );
+            let i2c = I2cdev::new(&touch_pads.device)
+                .with_context(|| format!("Opening I2C device {}", touch_pads.device))?;
+            let mut mpr121 = Mpr121::new(i2c, touch_pads.address);
+            mpr121
+                .init(&touch_pads.thresholds())
+                .context("Initializing MPR121")?;
+            Ok(Controller {
+                touch_pads,
+                mpr121,
+                touched: 0,
+            })
+        }
+
+        fn open_configured(touch_pads: Option<TouchPads>) -> Option<Self> {
+            match Controller::open(touch_pads?) {
+                Ok(controller) => Some(controller),
+                Err(err) => {
+                    error!("Failed to open touch pads: {:#}", err);
+                    None
+                }
+            }
+        }
+    }
+
+    // Polls the touch status and transmits the commands of touched pads. The MPR121 is
+    // initialized anew whenever the touch pad configuration changes.
+    fn run<T: From<Command>>(config: ConfigLoaderHandle, tx: Sender<T>) {
+        let updates = config.subscribe();
+        let mut controller = Controller::open_configured(config.get().touch_pads);
+        loop {
+            if let Some(update) = updates.try_iter().last() {
+                let touch_pads = controller.as_ref().map(|c| c.touch_pads.clone());
+                if update.touch_pads != touch_pads {
+                    info!("Touch pad configuration changed");
+                    controller = Controller::open_configured(update.touch_pads);
+                }
+            }
+            let controller = match controller.as_mut() {
+                Some(controller) => controller,
+                None => {
+                    // Nothing to poll until the configuration changes.
+                    match updates.recv() {
+                        Ok(update) => {
+                            controller = Controller::open_configured(update.touch_pads);
+                            continue;
+                        }
+                        Err(_) => return,
+                    }
+                }
+            };
+            match controller.mpr121.touched() {
+                Ok(touched) => {
+                    if touched != controller.touched {
+                        debug!("Touch status changed to {:#014b}", touched);
+                    }
+                    let commands = controller.touch_pads.commands(controller.touched, touched);
+                    controller.touched = touched;
+                    for cmd in commands {
+                        if let Err(err) = tx.send(cmd.into()) {
+                            error!("Failed to transmit touch pad command: {}", err);
+                        }
+                    }
+                }
+                Err(err) => error!("Failed to read touch status: {}", err),
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    pub fn spawn<T: From<Command> + Send + 'static>(
+        config: ConfigLoaderHandle,
+        tx: Sender<T>,
+    ) -> Result<()> {
+        std::thread::Builder::new()
+            .name("touch-pads".to_string())
+            .spawn(move || run(config, tx))
+            .context("Spawning touch pad reader")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn touch_pads(yaml: &str) -> TouchPads {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    const CONFIG: &str = r#"
+touch_threshold: 12
+release_threshold: 6
+pads:
+  - electrode: 0
+    command: pause_continue
+  - electrode: 1
+    command: volume_up
+    touch_threshold: 20
+  - electrode: 11
+    command: volume_down
+    release_threshold: 3
+"#;
+
+    #[test]
+    fn pads_are_deserialized() {
+        let pads = touch_pads(CONFIG);
+        assert_eq!(pads.device, "/dev/i2c-1");
+        assert_eq!(pads.address, mpr121::DEFAULT_ADDRESS);
+        assert_eq!(
+            pads.pads[1],
+            TouchPad {
+                electrode: 1,
+                command: Command::VolumeUp,
+                touch_threshold: Some(20),
+                release_threshold: None,
+            }
+        );
+
+        let defaults = touch_pads("pads: []");
+        assert_eq!(defaults.touch_threshold, Thresholds::default().touch);
+        assert_eq!(defaults.release_threshold, Thresholds::default().release);
+    }
+
+    #[test]
+    fn invalid_electrodes_are_rejected() {
+        let err = serde_yaml::from_str::<TouchPads>("pads: [{electrode: 12, command: stop}]")
+            .unwrap_err();
+        assert!(err.to_string().contains("invalid electrode 12"), "{}", err);
+        assert!(
+            serde_yaml::from_str::<TouchPads>("pads: [{electrode: -1, command: stop}]").is_err()
+        );
+        assert!(
+            serde_yaml::from_str::<TouchPads>("pads: [{electrode: 11, command: stop}]").is_ok()
+        );
+    }
+
+    #[test]
+    fn pad_thresholds_override_global_ones() {
+        let thresholds = touch_pads(CONFIG).thresholds();
+        assert_eq!(thresholds.len(), mpr121::ELECTRODES as usize);
+        let global = Thresholds {
+            touch: 12,
+            release: 6,
+        };
+        assert_eq!(thresholds[0], global);
+        assert_eq!(
+            thresholds[1],
+            Thresholds {
+                touch: 20,
+                release: 6,
+            }
+        );
+        // Electrodes without pads use the global thresholds as well.
+        assert_eq!(thresholds[5], global);
+        assert_eq!(
+            thresholds[11],
+            Thresholds {
+                touch: 12,
+                release: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn only_touches_trigger_commands() {
+        let pads = touch_pads(CONFIG);
+        assert_eq!(pads.commands(0, 0b1), vec![Command::PauseContinue]);
+        // Held down.
+        assert!(pads.commands(0b1, 0b1).is_empty());
+        // Released.
+        assert!(pads.commands(0b1, 0).is_empty());
+        assert_eq!(
+            pads.commands(0b1, 0b1000_0000_0011),
+            vec![Command::VolumeUp, Command::VolumeDown]
+        );
+        // Electrodes without pads.
+        assert!(pads.commands(0, 0b0111_1111_1100).is_empty());
+    }
+}
@@ -1,3 +1,4 @@
+pub mod app;
 pub mod components;
 pub mod effects;
 pub mod input_controller;
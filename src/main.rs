@@ -1,24 +1,24 @@
 use std::path::Path;
-use std::sync::{Arc, RwLock};
+use std::sync::Arc;
 
 use anyhow::{Context, Result};
-use crossbeam_channel::{self, Receiver, Sender};
-use rustberry::effects::InterpreterState;
-use tracing::{error, debug, info, warn};
+use tracing::{error, info, warn};
 use tracing_subscriber::{filter, fmt, prelude::*, reload};
 
+use rustberry::app;
+use rustberry::components::bookmarks::BookmarkStore;
+use rustberry::components::clock::SystemClock;
 use rustberry::components::config::ConfigLoader;
-use rustberry::components::config::ConfigLoaderHandle;
-use rustberry::components::tag_mapper::{TagMapper, TagMapperHandle};
-use rustberry::effects::{Effect, Interpreter, ProdInterpreter};
+use rustberry::components::listening_time::ListeningTime;
+use rustberry::components::tag_mapper::TagMapper;
+use rustberry::components::volume::Volume;
+use rustberry::effects::{Effect, Interpreter, PlaybackEvent, ProdInterpreter};
 use rustberry::input_controller::{
-    button::{self, cdev_gpio::CdevGpio},
+    button::cdev_gpio::CdevGpio, evdev, keypad, potentiometer,
     rfid_playback::rfid::PlaybackRequestTransmitterRfid,
-    Input,
+    rotary_encoder::cdev_gpio::CdevRotaryEncoder, touch_pads,
 };
 
-use rustberry::player::Player;
-
 const DEFAULT_JUKEBOX_CONFIG_FILE: &str = "/etc/jukebox/conf.yaml";
 
 #[tokio::main]
@@ -33,7 +33,10 @@ async fn main() -> Result<()> {
     info!("Starting application");
 
     info!("Using configuration file: {}", DEFAULT_JUKEBOX_CONFIG_FILE);
-    let config_loader = ConfigLoader::new(Path::new(DEFAULT_JUKEBOX_CONFIG_FILE), reload_handle.clone())?;
+    let config_loader = ConfigLoader::new(
+        Path::new(DEFAULT_JUKEBOX_CONFIG_FILE),
+        reload_handle.clone(),
+    )?;
     let config = config_loader.get();
 
     let mut fltr = filter::LevelFilter::INFO;
@@ -50,116 +53,90 @@ async fn main() -> Result<()> {
         .context("Creating tag_mapper")?;
     tag_mapper.debug_dump();
 
-    let interpreter_state = Arc::new(RwLock::new(InterpreterState::new()));
-    let interpreter_state_copy = interpreter_state.clone();
+    info!("Loading bookmarks");
+    let bookmarks = BookmarkStore::new(config.bookmarks_file.as_ref().map(Path::new))
+        .context("Loading bookmarks")?;
+
+    info!("Loading volume level");
+    let volume = Volume::new(&config).context("Loading volume level")?;
+
+    info!("Loading listening time");
+    let listening_time = ListeningTime::new(&config).context("Loading listening time")?;
 
     // Prepare input channel.
     let (inputs_tx, inputs_rx) = crossbeam_channel::bounded(10);
 
     info!("Creating Button Controller");
-    let _button_controller_handle =
-        CdevGpio::new_from_env(inputs_tx.clone()).context("Creating button controller")?;
+    CdevGpio::new(config_loader.clone(), inputs_tx.clone())
+        .context("Creating button controller")?;
+
+    info!("Creating rotary encoder controller");
+    CdevRotaryEncoder::new(config_loader.clone(), inputs_tx.clone())
+        .context("Creating rotary encoder controller")?;
+
+    info!("Creating volume potentiometer controller");
+    potentiometer::spi::spawn(config_loader.clone(), inputs_tx.clone())
+        .context("Creating volume potentiometer controller")?;
+
+    info!("Creating keypad controller");
+    keypad::cdev_gpio::spawn(config_loader.clone(), inputs_tx.clone())
+        .context("Creating keypad controller")?;
+
+    info!("Creating touch pad controller");
+    touch_pads::i2c::spawn(config_loader.clone(), inputs_tx.clone())
+        .context("Creating touch pad controller")?;
+
+    info!("Creating evdev controller");
+    evdev::device::spawn(config_loader.clone(), inputs_tx.clone())
+        .context("Creating evdev controller")?;
 
     if config.enable_rfid_controller {
         info!("Creating PlayBackRequestTransmitter");
-        let _playback_controller_handle = PlaybackRequestTransmitterRfid::new(inputs_tx.clone())
+        PlaybackRequestTransmitterRfid::new(inputs_tx.clone())
             .context("Creating playback controller")?;
     } else {
         warn!("Skipping creation of PlayBackRequestTransmitter: RFID controller disabled.");
     }
+    // The input channel is closed once all controllers have terminated.
+    drop(inputs_tx);
+
+    // Playback events emitted by the audio backend. Unbounded, as they are sent from
+    // within the audio thread.
+    let (playback_tx, playback_rx) = crossbeam_channel::unbounded::<PlaybackEvent>();
 
     // Effect interpreter.
     let (effect_tx, effect_rx) = crossbeam_channel::bounded::<Effect>(50);
     let config_loader_copy = config_loader.clone();
     tokio::task::spawn_blocking(move || {
         // Create Effects Channel and Interpreter.
-        let mut interpreter =
-            ProdInterpreter::new(config_loader_copy, interpreter_state_copy).context("Creating production interpreter").unwrap();
+        let mut interpreter = ProdInterpreter::new(config_loader_copy, playback_tx)
+            .context("Creating production interpreter")
+            .unwrap();
 
         info!("Waiting for interpreter readiness");
         interpreter
             .wait_until_ready()
-            .context("Waiting for interpreter readiness").unwrap();
-        for effect in effect_rx {
-            debug!("interpreting effect {:?}", effect);
-            if let Err(err) = interpreter.interprete(effect.clone()) {
-                error!("interpreting effect {:?} failed: {}", effect, err);
-            }
-        }
+            .context("Waiting for interpreter readiness")
+            .unwrap();
+        app::run_interpreter(&mut interpreter, effect_rx);
     });
 
     // Execute Application Logic.
     info!("Running application");
-    let _res = run(
+    app::run(
         config_loader,
         inputs_rx,
+        playback_rx,
         effect_tx,
-        tag_mapper,
-        interpreter_state,
-    )
-    .unwrap();
-    unreachable!();
-}
-
-fn run(
-    config: ConfigLoaderHandle,
-    input: Receiver<Input>,
-    effect_tx: Sender<Effect>,
-    tag_mapper: TagMapperHandle,
-    interpreter_state: Arc<RwLock<InterpreterState>>,
-) -> Result<()> {
-    let mut player = Player::new(effect_tx.clone(), config.clone(), tag_mapper, interpreter_state)?;
-    for input_ev in input {
-        debug!("Processing winput event: {:?}", input_ev);
-        let res = process_ev(config.clone(), &mut player, input_ev.clone(), effect_tx.clone());
-        match res {
-            Err(err) => {
-                error!("Failed to process input event {:?}: {}", input_ev, err);
-            }
-            Ok(effects) => {
-                for effect in effects {
-                    if let Err(err) = effect_tx.send(effect.clone()) {
-                        error!("Failed to send output effect {:?}: {}", effect, err);
-                    }
-                }
-            }
-        }
-    }
-    unreachable!()
-}
-
-fn process_ev(
-    config_loader: ConfigLoaderHandle,
-    player: &mut Player,
-    input: Input,
-    _output: Sender<Effect>,
-) -> Result<Vec<Effect>> {
-    let config = config_loader.get();
-
-    match input {
-        Input::Button(cmd) => match cmd {
-            button::Command::VolumeUp => {
-                let cmd = config
-                    .volume_up_command
-                    .clone()
-                    .unwrap_or_else(|| "pactl set-sink-volume 0 +10%".to_string());
-                return Ok(vec![Effect::GenericCommand(cmd)]);
-            }
-            button::Command::VolumeDown => {
-                let cmd = config
-                    .volume_up_command
-                    .clone()
-                    .unwrap_or_else(|| "pactl set-sink-volume 0 -10%".to_string());
-                return Ok(vec![Effect::GenericCommand(cmd)]);
-            }
-            button::Command::PauseContinue => {
-                player.pause_continue_command()?;
-                return Ok(vec![]);
-            }
+        app::Components {
+            tag_mapper,
+            bookmarks,
+            volume,
+            listening_time,
+            clock: Arc::new(SystemClock),
         },
-        Input::Playback(request) => {
-            player.playback(request.clone())?;
-            return Ok(vec![]);
-        }
-    }
+    )
+    .context("Running application")?;
+    warn!("All input controllers have terminated, shutting down");
+    Ok(())
 }
@@ -1,18 +1,83 @@
 use serde::Deserialize;
 use std::default::Default;
 
-#[derive(Deserialize, Debug, Clone)]
+use crate::components::schedule::ScheduleWindow;
+use crate::input_controller::button::Button;
+use crate::input_controller::evdev::Evdev;
+use crate::input_controller::keypad::Keypad;
+use crate::input_controller::potentiometer::Potentiometer;
+use crate::input_controller::rotary_encoder::RotaryEncoder;
+use crate::input_controller::touch_pads::TouchPads;
+
+// Which ReplayGain value is applied to files carrying both.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplayGainMode {
+    Off,
+    #[default]
+    Track,
+    Album,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
 pub struct Config {
     pub enable_spotify: bool,
     pub post_init_command: Option<String>,
+    // Executed in addition to changing the volume of the audio pipeline, e.g. for
+    // controlling an external amplifier.
     pub volume_up_command: Option<String>,
     pub volume_down_command: Option<String>,
+    // Executed by the shutdown button after stopping playback, e.g. "sudo poweroff".
+    pub shutdown_command: Option<String>,
+    // Volume levels in percent. Without a startup volume, the level persisted in the
+    // volume file is restored.
+    pub volume_step: u32,
+    pub startup_volume: Option<u32>,
+    pub max_volume: u32,
+    pub volume_file: Option<String>,
     pub trigger_only_mode: bool,
     pub tag_mapper_configuration_file: String,
     pub audio_base_directory: String,
     pub debug: bool,
     pub enable_rfid_controller: bool,
     pub audio_output_device: Option<String>,
+    pub bookmarks_file: Option<String>,
+    // Duration of the sleep timer when armed via button.
+    pub sleep_timer_minutes: u64,
+    // Skipped by the seek buttons.
+    pub skip_seconds: u64,
+    // Volume ramps applied when playback starts or resumes, and when it is paused,
+    // stopped or replaced.
+    pub fade_in_ms: u64,
+    pub fade_out_ms: u64,
+    // Duration over which consecutive tracks are mixed, can be overridden per tag. Zero
+    // for gapless playback without crossfade.
+    pub crossfade_ms: u64,
+    // Files without ReplayGain tags are normalized according to a loudness analysis,
+    // which is cached in the audio base directory.
+    pub replay_gain: ReplayGainMode,
+    // Quiet hours, see `components::schedule`.
+    pub schedule: Vec<ScheduleWindow>,
+    // Playback time per day, unlimited if not set. Once it is used up, the notice is
+    // played and playback is refused until the next day.
+    pub daily_listening_minutes: Option<u64>,
+    pub listening_time_file: Option<String>,
+    pub listening_time_notice: Option<String>,
+    // Buttons, see `input_controller::button`. Without them, buttons are configured via
+    // environment variables.
+    pub buttons: Option<Vec<Button>>,
+    pub long_press_ms: u64,
+    pub double_press_ms: u64,
+    // See `input_controller::rotary_encoder`.
+    pub rotary_encoder: Option<RotaryEncoder>,
+    // See `input_controller::potentiometer`.
+    pub potentiometer: Option<Potentiometer>,
+    // See `input_controller::keypad`.
+    pub keypad: Option<Keypad>,
+    // See `input_controller::touch_pads`.
+    pub touch_pads: Option<TouchPads>,
+    // See `input_controller::evdev`.
+    pub evdev: Option<Evdev>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -21,12 +86,36 @@ pub struct PartialConfig {
     pub post_init_command: Option<String>,
     pub volume_up_command: Option<String>,
     pub volume_down_command: Option<String>,
+    pub shutdown_command: Option<String>,
+    pub volume_step: Option<u32>,
+    pub startup_volume: Option<u32>,
+    pub max_volume: Option<u32>,
+    pub volume_file: Option<String>,
     pub trigger_only_mode: Option<bool>,
     pub tag_mapper_configuration_file: Option<String>,
     pub audio_base_directory: Option<String>,
     pub debug: Option<bool>,
     pub enable_rfid_controller: Option<bool>,
     pub audio_output_device: Option<String>,
+    pub bookmarks_file: Option<String>,
+    pub sleep_timer_minutes: Option<u64>,
+    pub skip_seconds: Option<u64>,
+    pub fade_in_ms: Option<u64>,
+    pub fade_out_ms: Option<u64>,
+    pub crossfade_ms: Option<u64>,
+    pub replay_gain: Option<ReplayGainMode>,
+    pub schedule: Option<Vec<ScheduleWindow>>,
+    pub daily_listening_minutes: Option<u64>,
+    pub listening_time_file: Option<String>,
+    pub listening_time_notice: Option<String>,
+    pub buttons: Option<Vec<Button>>,
+    pub long_press_ms: Option<u64>,
+    pub double_press_ms: Option<u64>,
+    pub rotary_encoder: Option<RotaryEncoder>,
+    pub potentiometer: Option<Potentiometer>,
+    pub keypad: Option<Keypad>,
+    pub touch_pads: Option<TouchPads>,
+    pub evdev: Option<Evdev>,
 }
 
 impl Default for Config {
@@ -36,17 +125,49 @@ impl Default for Config {
             post_init_command: None,
             volume_up_command: None,
             volume_down_command: None,
+            shutdown_command: None,
+            volume_step: 10,
+            startup_volume: None,
+            max_volume: 100,
+            volume_file: None,
             trigger_only_mode: false,
             tag_mapper_configuration_file: "".to_string(),
             audio_base_directory: "".to_string(),
             debug: false,
             enable_rfid_controller: true,
             audio_output_device: None,
+            bookmarks_file: None,
+            sleep_timer_minutes: 30,
+            skip_seconds: 30,
+            fade_in_ms: 300,
+            fade_out_ms: 300,
+            crossfade_ms: 0,
+            replay_gain: ReplayGainMode::default(),
+            schedule: vec![],
+            daily_listening_minutes: None,
+            listening_time_file: None,
+            listening_time_notice: None,
+            buttons: None,
+            long_press_ms: 1000,
+            double_press_ms: 300,
+            rotary_encoder: None,
+            potentiometer: None,
+            keypad: None,
+            touch_pads: None,
+            evdev: None,
         }
     }
 }
 
 impl Config {
+    // Settings missing in cfg take their default values, so that removing a setting from
+    // the configuration file undoes it on reload.
+    pub fn from_partial(cfg: PartialConfig) -> Self {
+        let mut config = Config::default();
+        config.merge_partial(cfg);
+        config
+    }
+
     // cfg overwrites values in self.
     pub fn merge_partial(&mut self, cfg: PartialConfig) {
         if let Some(enable_spotify) = cfg.enable_spotify {
@@ -62,6 +183,21 @@ impl Config {
         if let Some(volume_down_command) = cfg.volume_down_command {
             self.volume_down_command = Some(volume_down_command);
         }
+        if let Some(shutdown_command) = cfg.shutdown_command {
+            self.shutdown_command = Some(shutdown_command);
+        }
+        if let Some(volume_step) = cfg.volume_step {
+            self.volume_step = volume_step
+        }
+        if let Some(startup_volume) = cfg.startup_volume {
+            self.startup_volume = Some(startup_volume)
+        }
+        if let Some(max_volume) = cfg.max_volume {
+            self.max_volume = max_volume
+        }
+        if let Some(volume_file) = cfg.volume_file {
+            self.volume_file = Some(volume_file)
+        }
         if let Some(trigger_only_mode) = cfg.trigger_only_mode {
             self.trigger_only_mode = trigger_only_mode
         }
@@ -80,5 +216,96 @@ impl Config {
         if let Some(audio_output_device) = cfg.audio_output_device {
             self.audio_output_device = Some(audio_output_device)
         }
+        if let Some(bookmarks_file) = cfg.bookmarks_file {
+            self.bookmarks_file = Some(bookmarks_file)
+        }
+        if let Some(sleep_timer_minutes) = cfg.sleep_timer_minutes {
+            self.sleep_timer_minutes = sleep_timer_minutes
+        }
+        if let Some(skip_seconds) = cfg.skip_seconds {
+            self.skip_seconds = skip_seconds
+        }
+        if let Some(fade_in_ms) = cfg.fade_in_ms {
+            self.fade_in_ms = fade_in_ms
+        }
+        if let Some(fade_out_ms) = cfg.fade_out_ms {
+            self.fade_out_ms = fade_out_ms
+        }
+        if let Some(crossfade_ms) = cfg.crossfade_ms {
+            self.crossfade_ms = crossfade_ms
+        }
+        if let Some(replay_gain) = cfg.replay_gain {
+            self.replay_gain = replay_gain
+        }
+        if let Some(schedule) = cfg.schedule {
+            self.schedule = schedule
+        }
+        if let Some(daily_listening_minutes) = cfg.daily_listening_minutes {
+            self.daily_listening_minutes = Some(daily_listening_minutes)
+        }
+        if let Some(listening_time_file) = cfg.listening_time_file {
+            self.listening_time_file = Some(listening_time_file)
+        }
+        if let Some(listening_time_notice) = cfg.listening_time_notice {
+            self.listening_time_notice = Some(listening_time_notice)
+        }
+        if let Some(buttons) = cfg.buttons {
+            self.buttons = Some(buttons)
+        }
+        if let Some(long_press_ms) = cfg.long_press_ms {
+            self.long_press_ms = long_press_ms
+        }
+        if let Some(double_press_ms) = cfg.double_press_ms {
+            self.double_press_ms = double_press_ms
+        }
+        if let Some(rotary_encoder) = cfg.rotary_encoder {
+            self.rotary_encoder = Some(rotary_encoder)
+        }
+        if let Some(potentiometer) = cfg.potentiometer {
+            self.potentiometer = Some(potentiometer)
+        }
+        if let Some(keypad) = cfg.keypad {
+            self.keypad = Some(keypad)
+        }
+        if let Some(touch_pads) = cfg.touch_pads {
+            self.touch_pads = Some(touch_pads)
+        }
+        if let Some(evdev) = cfg.evdev {
+            self.evdev = Some(evdev)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(yaml: &str) -> Config {
+        Config::from_partial(serde_yaml::from_str(yaml).unwrap())
+    }
+
+    #[test]
+    fn removed_sections_are_reset() {
+        let config = load(
+            r#"
+max_volume: 60
+shutdown_command: sudo poweroff
+buttons:
+  - line: 14
+    action: pause_continue
+"#,
+        );
+        assert_eq!(config.max_volume, 60);
+        assert_eq!(config.shutdown_command.as_deref(), Some("sudo poweroff"));
+        assert_eq!(config.buttons.map(|buttons| buttons.len()), Some(1));
+
+        assert_eq!(
+            load("max_volume: 60"),
+            Config {
+                max_volume: 60,
+                ..Config::default()
+            }
+        );
+        assert_eq!(load("{}"), Config::default());
     }
 }
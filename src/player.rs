@@ -1,68 +1,502 @@
-use std::sync::{Arc, RwLock};
+use std::fmt;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use anyhow::Result;
 use crossbeam_channel::Sender;
 use serde::{Deserialize, Serialize};
-use tracing::{error, debug, info};
+use tracing::{debug, error, info, warn};
 
+use crate::components::bookmarks::{Bookmark, BookmarkStore};
+use crate::components::clock::Clock;
 use crate::components::config::ConfigLoaderHandle;
-use crate::components::rfid::Tag;
-use crate::components::tag_mapper::{TagConf, TagMapperHandle};
-use crate::effects::{Effect, InterpreterState};
+use crate::components::listening_time::ListeningTime;
+use crate::components::rfid::{Tag, Uid};
+use crate::components::schedule::{self, ScheduleLimit, ScheduleWindow, TimeOfDay};
+use crate::components::sleep_timer::{SleepTimer, SleepTimerAction, FADE_IN_DURATION};
+use crate::components::tag_mapper::{SleepTimerSetting, TagConf, TagMapperHandle};
+use crate::components::volume::Volume;
+use crate::effects::{Effect, PlaybackEvent, PlaybackEventKind, PlaybackPosition};
 
 pub use err::*;
 
-#[derive(Debug, Clone)]
-enum PlayerState {
+// Skipping back within this duration from the start of a track goes to the previous
+// track instead of restarting the current one.
+const RESTART_THRESHOLD: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlayerState {
     Idle,
     Playing {
         tag_conf: TagConf,
-        playing_since: std::time::Instant,
+        // Point in time at which playback of `track` was reported to start at `offset`.
+        playing_since: Instant,
         offset: Duration,
+        track: usize,
+        // Set once the audio backend reports that the playlist has been played completely.
+        complete: bool,
     },
     Paused {
-        at: std::time::Duration,
+        at: Duration,
+        track: usize,
         prev_tag_conf: TagConf,
     },
 }
 
 impl PlayerState {
-    pub fn comparable(&self) -> ComparablePlayerState {
+    fn playing(tag_conf: TagConf, position: PlaybackPosition, now: Instant) -> Self {
+        PlayerState::Playing {
+            tag_conf,
+            playing_since: now,
+            offset: position.offset,
+            track: position.track,
+            complete: false,
+        }
+    }
+
+    // Extrapolates the playback position from the last track start reported by the audio
+    // backend.
+    pub fn position(&self, now: Instant) -> PlaybackPosition {
         match self {
-            PlayerState::Idle => ComparablePlayerState::Idle,
             PlayerState::Playing {
-                tag_conf,
                 playing_since,
                 offset,
+                track,
                 ..
-            } => ComparablePlayerState::Playing {
-                tag_conf: tag_conf.clone(),
-                playing_since: *playing_since,
-                offset: *offset,
+            } => PlaybackPosition {
+                track: *track,
+                offset: *offset + now.saturating_duration_since(*playing_since),
             },
-            PlayerState::Paused {
-                at, prev_tag_conf, ..
-            } => ComparablePlayerState::Paused {
-                at: *at,
-                prev_tag_conf: prev_tag_conf.clone(),
+            PlayerState::Paused { at, track, .. } => PlaybackPosition {
+                track: *track,
+                offset: *at,
             },
+            PlayerState::Idle => PlaybackPosition::default(),
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        matches!(
+            self,
+            PlayerState::Playing {
+                complete: false,
+                ..
+            }
+        )
+    }
+
+    // Returns the configuration of the resource which playback would be (re)started for
+    // on the given input.
+    fn requested_playback<'a>(&'a self, input: &'a PlayerInput) -> Option<&'a TagConf> {
+        match (input, self) {
+            (PlayerInput::Start(tag_conf), _) => Some(tag_conf),
+            (PlayerInput::PauseContinue, PlayerState::Paused { prev_tag_conf, .. }) => {
+                Some(prev_tag_conf)
+            }
+            (
+                PlayerInput::PauseContinue,
+                PlayerState::Playing {
+                    tag_conf,
+                    complete: true,
+                    ..
+                },
+            ) => Some(tag_conf),
+            _ => None,
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
-enum ComparablePlayerState {
-    Idle,
-    Playing {
-        tag_conf: TagConf,
-        playing_since: std::time::Instant,
-        offset: Duration,
-    },
-    Paused {
-        at: std::time::Duration,
-        prev_tag_conf: TagConf,
-    },
+/// Inputs driving the player state machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlayerInput {
+    // A tag has been presented, already resolved into its configuration.
+    Start(TagConf),
+    // The tag has been removed.
+    Stop,
+    PauseContinue,
+    // Reported by the audio backend for the current playback.
+    Playback(PlaybackEventKind),
+    SleepTimerExpired,
+    ListeningTimeExhausted,
+    // Ends playback, unlike pausing it cannot be continued.
+    StopPlayback,
+    NextTrack,
+    PreviousTrack,
+    SeekForward,
+    SeekBackward,
+}
+
+/// Facts about the environment of the player, gathered before each transition.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Observations {
+    pub trigger_only_mode: bool,
+    // Position to resume the presented tag at, if its resume policy permits it.
+    pub bookmark: Option<PlaybackPosition>,
+    // Set during quiet hours without playback. Only lullabies may be played then.
+    pub playback_disabled: bool,
+    pub listening_time_exhausted: bool,
+    // Played when playback is refused since the listening time is used up.
+    pub listening_time_notice: Option<String>,
+    // Duration skipped by seeking.
+    pub skip: Duration,
+    // Duration of the current track, if known.
+    pub track_duration: Option<Duration>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BookmarkUpdate {
+    Set(String, PlaybackPosition),
+    Remove(String),
+}
+
+/// Result of a state transition. Bookmark updates are not interpreter effects, they are
+/// applied by the player itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transition {
+    pub state: PlayerState,
+    pub effects: Vec<Effect>,
+    pub bookmarks: Vec<BookmarkUpdate>,
+}
+
+impl Transition {
+    fn new(state: PlayerState) -> Self {
+        Transition {
+            state,
+            effects: vec![],
+            bookmarks: vec![],
+        }
+    }
+
+    fn set_bookmark(&mut self, tag_conf: &TagConf, position: PlaybackPosition) {
+        if tag_conf.keeps_bookmarks() {
+            self.bookmarks
+                .push(BookmarkUpdate::Set(tag_conf.tag_id.clone(), position));
+        }
+    }
+
+    fn clear_bookmark(&mut self, tag_conf: &TagConf) {
+        if tag_conf.keeps_bookmarks() {
+            self.bookmarks
+                .push(BookmarkUpdate::Remove(tag_conf.tag_id.clone()));
+        }
+    }
+
+    // Starts playback of a newly presented resource. If a bookmark has been observed for
+    // it, playback continues at the bookmarked position.
+    fn start(&mut self, tag_conf: TagConf, bookmark: Option<PlaybackPosition>, now: Instant) {
+        match bookmark {
+            Some(position) => {
+                self.effects
+                    .push(Effect::PlayContinue(tag_conf.clone(), position));
+                self.state = PlayerState::playing(tag_conf, position, now);
+            }
+            None => self.restart(tag_conf, now),
+        }
+    }
+
+    fn restart(&mut self, tag_conf: TagConf, now: Instant) {
+        self.effects.push(Effect::Play(tag_conf.clone()));
+        self.state = PlayerState::playing(tag_conf, PlaybackPosition::default(), now);
+    }
+
+    fn resume(&mut self, tag_conf: TagConf, position: PlaybackPosition, now: Instant) {
+        self.effects
+            .push(Effect::PlayContinue(tag_conf.clone(), position));
+        self.state = PlayerState::playing(tag_conf, position, now);
+    }
+
+    // Stops the current playback, recording a bookmark unless it has completed.
+    fn stop(&mut self, state: &PlayerState, now: Instant) {
+        self.effects.push(Effect::Stop);
+        if let PlayerState::Playing {
+            tag_conf, complete, ..
+        } = state
+        {
+            if *complete {
+                self.clear_bookmark(tag_conf);
+            } else {
+                self.set_bookmark(tag_conf, state.position(now));
+            }
+        }
+    }
+
+    // Continues the current track at the given offset.
+    fn seek(&mut self, tag_conf: &TagConf, track: usize, offset: Duration, now: Instant) {
+        let position = PlaybackPosition { track, offset };
+        self.effects.push(Effect::Seek(position));
+        self.state = PlayerState::playing(tag_conf.clone(), position, now);
+    }
+
+    // Plays a notice without changing the player state.
+    fn notice(&mut self, uri: Option<&String>) {
+        if let Some(uri) = uri {
+            self.effects.push(Effect::PlayNotice(uri.clone()));
+        }
+    }
+
+    fn pause(&mut self, state: &PlayerState, now: Instant) {
+        if let PlayerState::Playing { tag_conf, .. } = state {
+            let position = state.position(now);
+            self.stop(state, now);
+            self.state = PlayerState::Paused {
+                prev_tag_conf: tag_conf.clone(),
+                at: position.offset,
+                track: position.track,
+            };
+        }
+    }
+}
+
+/// The player state machine. Computes the successor of `state` for the given input,
+/// without side effects.
+pub fn transition(
+    state: &PlayerState,
+    input: &PlayerInput,
+    observations: &Observations,
+    now: Instant,
+) -> Transition {
+    use PlayerState::*;
+
+    let mut t = Transition::new(state.clone());
+
+    if let Some(tag_conf) = state.requested_playback(input) {
+        if observations.playback_disabled && !tag_conf.lullaby {
+            info!("Ignoring playback request during quiet hours");
+            return t;
+        }
+        if observations.listening_time_exhausted {
+            info!("Ignoring playback request, listening time is used up");
+            t.notice(observations.listening_time_notice.as_ref());
+            return t;
+        }
+    }
+
+    match (input, state) {
+        (PlayerInput::PauseContinue, Idle) => {}
+
+        (
+            PlayerInput::PauseContinue,
+            Paused {
+                at,
+                track,
+                prev_tag_conf,
+            },
+        ) => {
+            let position = PlaybackPosition {
+                track: *track,
+                offset: *at,
+            };
+            t.resume(prev_tag_conf.clone(), position, now);
+        }
+
+        (
+            PlayerInput::PauseContinue,
+            Playing {
+                tag_conf,
+                complete: true,
+                ..
+            },
+        ) => {
+            // Playback finished already, the command triggers a new playback.
+            t.stop(state, now);
+            t.restart(tag_conf.clone(), now);
+        }
+
+        (PlayerInput::PauseContinue, Playing { .. }) => t.pause(state, now),
+
+        (PlayerInput::Start(tag_conf), Idle) => {
+            t.start(tag_conf.clone(), observations.bookmark, now);
+        }
+
+        (
+            PlayerInput::Start(tag_conf),
+            Playing {
+                tag_conf: current_tag_conf,
+                complete,
+                ..
+            },
+        ) => {
+            if !observations.trigger_only_mode || current_tag_conf != tag_conf {
+                // Outside of trigger-only mode, this code path should actually not happen.
+                // It means that the player has received two consecutive Playback-Start-Requests,
+                // i.e. without a Playback-Stop-Request in between. The main application logic should
+                // guarantee that this does not happen.
+                // Nevertheless we handle the case here inside the player: We keep it simple and
+                // replace the playback, as in trigger-only mode when a different tag is presented.
+                t.stop(state, now);
+                t.start(tag_conf.clone(), observations.bookmark, now);
+            } else if *complete {
+                // Same resource presented after playback has completed, trigger playback again.
+                t.stop(state, now);
+                t.restart(tag_conf.clone(), now);
+            }
+        }
+
+        (
+            PlayerInput::Start(tag_conf),
+            Paused {
+                at,
+                track,
+                prev_tag_conf,
+            },
+        ) if tag_conf == prev_tag_conf => {
+            if tag_conf.resume.is_some() {
+                // Let the resume policy of the tag decide, based on the bookmark
+                // recorded when pausing.
+                t.start(tag_conf.clone(), observations.bookmark, now);
+            } else {
+                // Currently paused, last resource is presented again, continue playing.
+                let position = PlaybackPosition {
+                    track: *track,
+                    offset: *at,
+                };
+                t.resume(tag_conf.clone(), position, now);
+            }
+        }
+
+        (PlayerInput::Start(tag_conf), Paused { .. }) => {
+            // New resource.
+            t.effects.push(Effect::Stop);
+            t.start(tag_conf.clone(), observations.bookmark, now);
+        }
+
+        (PlayerInput::Stop, Playing { complete, .. }) if !observations.trigger_only_mode => {
+            // RFID tag removed.
+            if *complete {
+                t.stop(state, now);
+                t.state = Idle;
+            } else {
+                t.pause(state, now);
+            }
+        }
+
+        (PlayerInput::Stop, _) => {}
+
+        (
+            PlayerInput::Playback(PlaybackEventKind::TrackStarted { track, offset, .. }),
+            Playing { tag_conf, .. },
+        ) => {
+            let position = PlaybackPosition {
+                track: *track,
+                offset: *offset,
+            };
+            t.state = PlayerState::playing(tag_conf.clone(), position, now);
+        }
+
+        (PlayerInput::Playback(PlaybackEventKind::QueueExhausted), Playing { tag_conf, .. }) => {
+            t.clear_bookmark(tag_conf);
+            if let Playing {
+                ref mut complete, ..
+            } = t.state
+            {
+                *complete = true;
+            }
+        }
+
+        (PlayerInput::Playback(_), _) => {}
+
+        (
+            PlayerInput::SleepTimerExpired,
+            Playing {
+                complete: false, ..
+            },
+        ) => t.pause(state, now),
+
+        (PlayerInput::SleepTimerExpired, _) => {}
+
+        (
+            PlayerInput::ListeningTimeExhausted,
+            Playing {
+                complete: false, ..
+            },
+        ) => {
+            t.pause(state, now);
+            t.notice(observations.listening_time_notice.as_ref());
+        }
+
+        (PlayerInput::ListeningTimeExhausted, _) => {}
+
+        (PlayerInput::StopPlayback, Playing { .. }) => {
+            t.stop(state, now);
+            t.state = Idle;
+        }
+
+        (PlayerInput::StopPlayback, Paused { .. }) => t.state = Idle,
+
+        (PlayerInput::StopPlayback, Idle) => {}
+
+        (
+            PlayerInput::NextTrack,
+            Playing {
+                complete: false, ..
+            },
+        ) => {
+            t.effects.push(Effect::NextTrack);
+        }
+
+        (
+            PlayerInput::PreviousTrack,
+            Playing {
+                tag_conf,
+                complete: false,
+                ..
+            },
+        ) => {
+            let position = state.position(now);
+            if position.offset > RESTART_THRESHOLD {
+                t.seek(tag_conf, position.track, Duration::from_secs(0), now);
+            } else {
+                t.effects.push(Effect::PreviousTrack);
+            }
+        }
+
+        (
+            PlayerInput::SeekForward,
+            Playing {
+                tag_conf,
+                complete: false,
+                ..
+            },
+        ) => {
+            let position = state.position(now);
+            let offset = position.offset + observations.skip;
+            match observations.track_duration {
+                Some(duration) if offset >= duration => t.effects.push(Effect::NextTrack),
+                _ => t.seek(tag_conf, position.track, offset, now),
+            }
+        }
+
+        (
+            PlayerInput::SeekBackward,
+            Playing {
+                tag_conf,
+                complete: false,
+                ..
+            },
+        ) => {
+            let position = state.position(now);
+            let offset = position.offset.saturating_sub(observations.skip);
+            t.seek(tag_conf, position.track, offset, now);
+        }
+
+        (
+            PlayerInput::NextTrack
+            | PlayerInput::PreviousTrack
+            | PlayerInput::SeekForward
+            | PlayerInput::SeekBackward,
+            _,
+        ) => {}
+    }
+
+    if t.state.is_playing() != state.is_playing() {
+        t.effects.push(if t.state.is_playing() {
+            Effect::LedOn
+        } else {
+            Effect::LedOff
+        });
+    }
+
+    t
 }
 
 pub struct Player {
@@ -70,7 +504,64 @@ pub struct Player {
     state: PlayerState,
     config: ConfigLoaderHandle,
     tag_mapper: TagMapperHandle,
-    interpreter_state: Arc<RwLock<InterpreterState>>,
+    bookmarks: BookmarkStore,
+    clock: Arc<dyn Clock>,
+    // Number of playback requests sent to the interpreter, used for matching playback
+    // events against the current playback.
+    playback_requests: u64,
+    sleep_timer: SleepTimer,
+    // Duration of the current track, if known.
+    track_duration: Option<Duration>,
+    // Set while a control tag, e.g. for the sleep timer, is presented. Its removal must
+    // not affect playback.
+    control_tag_present: bool,
+    volume: Volume,
+    // Volume level last sent to the interpreter.
+    applied_volume: Option<u32>,
+    schedule_window: Option<ScheduleWindow>,
+    listening_time: ListeningTime,
+    // Point in time up to which playback has been accounted for.
+    listening_time_accounted: Option<Instant>,
+}
+
+/// Summary of the player's state, for status output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlayerStatus {
+    pub state: PlayerState,
+    pub volume: Option<u32>,
+    pub sleep_timer: SleepTimer,
+    pub schedule_window: Option<ScheduleWindow>,
+    pub listening_time_left: Option<Duration>,
+}
+
+impl fmt::Display for PlayerStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.state {
+            PlayerState::Idle => write!(f, "idle")?,
+            PlayerState::Playing {
+                track,
+                complete: false,
+                ..
+            } => write!(f, "playing track {}", track)?,
+            PlayerState::Playing { complete: true, .. } => write!(f, "playback complete")?,
+            PlayerState::Paused { track, at, .. } => {
+                write!(f, "paused in track {} at {:?}", track, at)?
+            }
+        }
+        if let Some(volume) = self.volume {
+            write!(f, ", volume {}%", volume)?;
+        }
+        if self.sleep_timer.is_armed() {
+            write!(f, ", sleep timer armed")?;
+        }
+        if let Some(ref window) = self.schedule_window {
+            write!(f, ", schedule window {}", window)?;
+        }
+        if let Some(left) = self.listening_time_left {
+            write!(f, ", {} min listening time left", left.as_secs() / 60)?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -82,385 +573,418 @@ pub enum PlaybackRequest {
 pub type PlaybackResource = Tag;
 
 impl Player {
-    fn play_resource(&self, tag_conf: &TagConf) -> Result<()> {
-        let effect = Effect::Play(tag_conf.clone());
-        if let Err(err) = self.effect_tx.send(effect.clone()) {
-            error!("Failed to send effect {:?}: {}", effect, err);
+    fn observe(&self, input: &PlayerInput) -> Observations {
+        let config = self.config.get();
+        let bookmark = match input {
+            PlayerInput::Start(tag_conf) => tag_conf.resume.and_then(|policy| {
+                self.bookmarks
+                    .get(&tag_conf.tag_id)
+                    .filter(|bookmark| {
+                        policy.allows_resume(bookmark.paused_at, self.clock.system_time())
+                    })
+                    .map(|bookmark| bookmark.position())
+            }),
+            _ => None,
+        };
+        let playback_disabled = matches!(
+            self.schedule_window,
+            Some(ScheduleWindow {
+                limit: ScheduleLimit::NoPlayback,
+                ..
+            })
+        );
+        Observations {
+            trigger_only_mode: config.trigger_only_mode,
+            bookmark,
+            playback_disabled,
+            listening_time_exhausted: self.listening_time_left() == Some(Duration::ZERO),
+            listening_time_notice: config.listening_time_notice.clone(),
+            skip: Duration::from_secs(config.skip_seconds),
+            track_duration: self.track_duration,
         }
-        Ok(())
     }
 
-    // fn playing_led(
-    //     &self,
-    //     is_playing: bool,
-    // ) -> Result<()> {
-
-    //    let effect =if is_playing {
-    //     Effect::LedOn
-    //     } else {
-    //         Effect::LedOff
-    //     };
-    //     if let Err(err) = self.effect_tx.send(effect.clone()) {
-    //         error!("Failed to send effect {:?}: {}", effect, err);
-    //     }
-    //     Ok(())
-    // }
+    fn listening_time_left(&self) -> Option<Duration> {
+        let today = self.clock.local_time().day;
+        self.listening_time.remaining(&self.config.get(), today)
+    }
 
-    // External entry point.
-    pub fn pause_continue_command(&mut self) -> Result<()> {
-        debug!("Player: pause/continue");
-        let state = self.state.clone();
-        let res = self.handle_pause_continue_command();
-        if let Err(err) = res {
-            error!(
-                "Player State Transition Failure: {}, staying in State {:?}",
-                err, &state
+    // Records the time played since the last call.
+    fn account_listening_time(&mut self) {
+        let now = self.clock.now();
+        if let (true, Some(since)) = (self.state.is_playing(), self.listening_time_accounted) {
+            let today = self.clock.local_time().day;
+            self.listening_time.record(
+                &self.config.get(),
+                now.saturating_duration_since(since),
+                today,
             );
-            return Err(err.into());
-        } else if self.state.comparable() != state.comparable() {
-            info!("Player State Transition: {:?} -> {:?}", state, self.state);
         }
-        Ok(())
+        self.listening_time_accounted = Some(now);
     }
 
-    fn handle_pause_continue_command(&mut self) -> Result<()> {
-        use PlayerState::*;
-
-        match self.state.clone() {
-            Idle => {}
+    fn volume_cap(&self) -> Option<u32> {
+        match self.schedule_window {
+            Some(ScheduleWindow {
+                limit: ScheduleLimit::MaxVolume(level),
+                ..
+            }) => Some(level),
+            _ => None,
+        }
+    }
 
-            Paused { at, prev_tag_conf } => {
-                if let Err(err) = self.effect_tx.send(Effect::PlayContinue(at)) {
-                    error!("Failed to continue playback: {}", err);
-                    return Err(err.into());
-                }
+    // Sends the volume level to the interpreter, limited by the active schedule window.
+    fn apply_volume(&mut self) {
+        let level = self
+            .volume
+            .output_level(&self.config.get(), self.volume_cap());
+        if self.applied_volume != Some(level) {
+            debug!("Applying volume level {}%", level);
+            self.send_effect(Effect::SetVolume(level));
+            self.applied_volume = Some(level);
+        }
+    }
 
-                self.state = Playing {
-                    playing_since: Instant::now(),
-                    offset: at,
-                    tag_conf: prev_tag_conf,
-                };
+    // Determines the active schedule window and adjusts the volume to it.
+    fn update_schedule(&mut self) {
+        let now = TimeOfDay::from_seconds(self.clock.local_time().seconds);
+        let window = schedule::active_window(&self.config.get().schedule, now);
+        if window != self.schedule_window {
+            match window {
+                Some(ref window) => info!("Schedule window {} active", window),
+                None => info!("No schedule window active"),
             }
+            self.schedule_window = window;
+        }
+        self.apply_volume();
+    }
 
-            Playing {
-                playing_since,
-                offset,
-                tag_conf,
-            } => {
-                let interpreter_state = {
-                    let r = *self.interpreter_state.read().unwrap();
-                    r
-                };
-                let is_complete = !interpreter_state.currently_playing;
-
-                if is_complete {
-                    // playback finished already, event should trigger new playback.
+    fn apply_bookmark_update(&self, update: BookmarkUpdate) {
+        let res = match update {
+            BookmarkUpdate::Set(ref tag_id, position) => self
+                .bookmarks
+                .set(tag_id, Bookmark::new(position, self.clock.system_time())),
+            BookmarkUpdate::Remove(ref tag_id) => self.bookmarks.remove(tag_id),
+        };
+        if let Err(err) = res {
+            error!("Failed to apply bookmark update {:?}: {}", update, err);
+        }
+    }
 
-                    if let Err(err) = self.effect_tx.send(Effect::Stop) {
-                        error!("Failed to stop playback: {}", err);
-                        return Err(err.into());
-                    }
+    fn send_effect(&self, effect: Effect) {
+        if let Err(err) = self.effect_tx.send(effect.clone()) {
+            error!("Failed to send effect {:?}: {}", effect, err);
+        }
+    }
 
-                    match self.play_resource(&tag_conf) {
-                        Err(err) => {
-                            error!("Failed to initiate new playback: {}", err);
-                            self.state = Idle;
-                            return Err(err);
-                        }
-                        Ok(_) => {
-                            self.state = Playing {
-                                playing_since: Instant::now(),
-                                offset: Duration::from_secs(0),
-                                tag_conf,
-                            };
-                        }
-                    }
-                } else {
-                    let played_pos = offset + playing_since.elapsed();
+    fn set_sleep_timer(&mut self, sleep_timer: SleepTimer) {
+        if self.sleep_timer.is_fading() && !sleep_timer.is_fading() {
+            self.send_effect(Effect::FadeIn(FADE_IN_DURATION));
+        }
+        self.sleep_timer = sleep_timer;
+    }
 
-                    if let Err(err) = self.effect_tx.send(Effect::Stop) {
-                        error!("Failed to execute playback stop: {}", err);
-                        self.state = Idle;
-                        return Err(err.into());
-                    }
+    fn arm_sleep_timer(&mut self, setting: SleepTimerSetting) {
+        info!("Arming sleep timer: {:?}", setting);
+        let now = self.clock.now();
+        let mut sleep_timer = SleepTimer::armed(setting, now);
+        if self.state.is_playing() {
+            let offset = self.state.position(now).offset;
+            let remaining = self
+                .track_duration
+                .map(|duration| duration.saturating_sub(offset));
+            sleep_timer.track_started(remaining, now);
+        }
+        self.set_sleep_timer(sleep_timer);
+    }
 
-                    self.state = Paused {
-                        prev_tag_conf: tag_conf.clone(),
-                        at: played_pos,
-                    };
+    fn sleep_timer_action(&mut self, action: SleepTimerAction) -> Result<()> {
+        match action {
+            SleepTimerAction::FadeOut(duration) => {
+                info!("Sleep timer: fading out over {:?}", duration);
+                if self.state.is_playing() {
+                    self.send_effect(Effect::FadeOut(duration));
                 }
+                Ok(())
+            }
+            SleepTimerAction::Stop => {
+                info!("Sleep timer expired");
+                self.step(PlayerInput::SleepTimerExpired)
             }
         }
-
-        Ok(())
     }
 
-    // External entry point.
-    pub fn playback(&mut self, request: PlaybackRequest) -> Result<()> {
-        debug!("Player: playback");
-        let state = self.state.clone();
-        let res = self.handle_playback_command(request);
-        if let Err(err) = res {
-            error!(
-                "Player State Transition Failure: {}, staying in State {:?}",
-                err, &state
-            );
-            return Err(err.into());
-        } else if self.state.comparable() != state.comparable() {
-            info!("Player State Transition: {:?} -> {:?}", state, self.state);
-            // Self::playing_led(player.interpreter.clone(), state.is_playing());
+    fn step(&mut self, input: PlayerInput) -> Result<()> {
+        self.update_schedule();
+        self.account_listening_time();
+        let observations = self.observe(&input);
+        let t = transition(&self.state, &input, &observations, self.clock.now());
+        if t.state != self.state {
+            info!("Player State Transition: {:?} -> {:?}", self.state, t.state);
+            self.state = t.state;
+            info!("Player status: {}", self.status());
+            if !self.state.is_playing() {
+                self.listening_time.flush();
+            }
+        }
+        for update in t.bookmarks {
+            self.apply_bookmark_update(update);
+        }
+        for effect in t.effects {
+            if let Effect::PlayNotice(_) = effect {
+                // Replaces the playback in the interpreter, whose events are discarded from
+                // now on. Events of the notice itself are ignored, since nothing is playing.
+                self.playback_requests += 1;
+            }
+            if let Effect::Play(ref tag_conf) | Effect::PlayContinue(ref tag_conf, _) = effect {
+                // The interpreter tags subsequent playback events with the updated count.
+                self.playback_requests += 1;
+                self.track_duration = None;
+                self.sleep_timer.playback_started();
+                if let Some(setting) = tag_conf.sleep_timer {
+                    if !self.sleep_timer.is_armed() {
+                        self.arm_sleep_timer(setting);
+                    }
+                }
+            }
+            if let Err(err) = self.effect_tx.send(effect.clone()) {
+                error!("Failed to send effect {:?}: {}", effect, err);
+                return Err(err.into());
+            }
         }
         Ok(())
     }
 
-    fn handle_playback_command(&mut self, request: PlaybackRequest) -> Result<()> {
-        let mut is_playing = false;
-        use PlayerState::*;
-
-        let config = self.config.get();
-        let interpreter_state = self.interpreter_state.read().unwrap();
-        let is_complete = !interpreter_state.currently_playing;
+    // External entry point.
+    pub fn pause_continue_command(&mut self) -> Result<()> {
+        debug!("Player: pause/continue");
+        self.step(PlayerInput::PauseContinue)
+    }
 
+    // External entry point.
+    pub fn playback(&mut self, request: PlaybackRequest) -> Result<()> {
         info!(
             "Player in state {:?} received playback command {:?}",
             self.state, request
         );
-
-        match request {
+        let input = match request {
             PlaybackRequest::Start(tag) => {
                 let tag_conf = self
                     .tag_mapper
                     .lookup(&tag.uid.to_string())
                     .unwrap_or_default();
+                if let (true, Some(setting)) = (tag_conf.is_sleep_timer_tag(), tag_conf.sleep_timer)
+                {
+                    self.control_tag_present = true;
+                    self.arm_sleep_timer(setting);
+                    return Ok(());
+                }
+                if let (true, Some(minutes)) = (
+                    tag_conf.is_listening_time_tag(),
+                    tag_conf.extra_listening_minutes,
+                ) {
+                    self.control_tag_present = true;
+                    return self.grant_listening_time(Duration::from_secs(minutes * 60));
+                }
+                self.control_tag_present = false;
+                PlayerInput::Start(tag_conf)
+            }
+            PlaybackRequest::Stop if self.control_tag_present => {
+                self.control_tag_present = false;
+                return Ok(());
+            }
+            PlaybackRequest::Stop => PlayerInput::Stop,
+        };
+        self.step(input)
+    }
 
-                match self.state.clone() {
-                    Idle => {
-                        let offset = Duration::from_secs(0);
-                        match self.play_resource(&tag_conf) {
-                            Err(err) => {
-                                error!("Failed to initiate new playback: {}", err);
-                                return Err(err);
-                            }
-                            Ok(_) => {
-                                self.state = Playing {
-                                    playing_since: Instant::now(),
-                                    offset,
-                                    tag_conf,
-                                };
-                            }
-                        }
-                    }
+    // External entry point. Arms the sleep timer with the configured duration or disarms
+    // it, if armed already.
+    pub fn sleep_timer_command(&mut self) -> Result<()> {
+        debug!("Player: sleep timer");
+        if self.sleep_timer.is_armed() {
+            info!("Disarming sleep timer");
+            self.set_sleep_timer(SleepTimer::Disarmed);
+        } else {
+            let minutes = self.config.get().sleep_timer_minutes;
+            self.arm_sleep_timer(SleepTimerSetting::Minutes(minutes));
+        }
+        Ok(())
+    }
 
-                    Playing { .. } if !config.trigger_only_mode => {
-                        // This code path should atually not happen.
-                        // It means that the player has received two consecutive Playback-Start-Requests,
-                        // i.e. without a Playback-Stop-Request in between. The main application logic should
-                        // guarantee that this does not happen.
-                        // Nevertheless we handle the case here inside the player: We keep it simple and update
-                        // the playback.
-                        let offset = Duration::from_secs(0);
-
-                        // Stop current playback.
-                        if let Err(err) = self.effect_tx.send(Effect::Stop) {
-                            error!("Failed to stop playback: {}", err);
-                            return Err(err.into());
-                        }
-
-                        match self.play_resource(&tag_conf) {
-                            Err(err) => {
-                                error!("Failed to initiate new playback: {}", err);
-                                self.state = Idle;
-                                return Err(err);
-                            }
-                            Ok(_) => {
-                                self.state = Playing {
-                                    playing_since: Instant::now(),
-                                    offset,
-                                    tag_conf,
-                                };
-                                is_playing = true;
-                            }
-                        }
-                    }
+    // External entry point.
+    pub fn stop_command(&mut self) -> Result<()> {
+        debug!("Player: stop");
+        self.step(PlayerInput::StopPlayback)
+    }
 
-                    Playing {
-                        tag_conf: current_tag_conf,
-                        ..
-                    } if config.trigger_only_mode && current_tag_conf != tag_conf => {
-                        // Different RFID tag presented, replace playback.
-
-                        if let Err(err) = self.effect_tx.send(Effect::Stop) {
-                            error!("Failed to stop playback: {}", err);
-                            return Err(err.into());
-                        }
-
-                        match self.play_resource(&tag_conf) {
-                            Err(err) => {
-                                error!("Failed to initiate new playback: {}", err);
-                                self.state = Idle;
-                                return Err(err);
-                            }
-                            Ok(_) => {
-                                self.state = Playing {
-                                    playing_since: Instant::now(),
-                                    offset: Duration::from_secs(0),
-                                    tag_conf,
-                                };
-                                // is_playing = true;
-                            }
-                        }
-                    }
+    // External entry point.
+    pub fn next_track_command(&mut self) -> Result<()> {
+        debug!("Player: next track");
+        self.step(PlayerInput::NextTrack)
+    }
 
-                    Playing { .. } => {
-                        // Same resource presented while playing already, trigger playback if already completed,
-                        // otherwise do nothing.
-
-                        if is_complete {
-                            if let Err(err) = self.effect_tx.send(Effect::Stop) {
-                                error!("Failed to stop playback: {}", err);
-                                return Err(err.into());
-                            }
-
-                            match self.play_resource(&tag_conf) {
-                                Err(err) => {
-                                    error!("Failed to initiate new playback: {}", err);
-                                    self.state = Idle;
-                                    return Err(err);
-                                }
-                                Ok(_) => {
-                                    self.state = Playing {
-                                        playing_since: Instant::now(),
-                                        offset: Duration::from_secs(0),
-                                        tag_conf,
-                                    };
-                                }
-                            }
-                        }
-                        is_playing = true;
-                    }
+    // External entry point.
+    pub fn previous_track_command(&mut self) -> Result<()> {
+        debug!("Player: previous track");
+        self.step(PlayerInput::PreviousTrack)
+    }
 
-                    Paused { at, prev_tag_conf } if tag_conf == prev_tag_conf => {
-                        // Currently paused, last resource is presented again, continue playing.
-                        if is_complete {
-                            if let Err(err) = self.effect_tx.send(Effect::Stop) {
-                                error!("Failed to stop playback: {}", err);
-                                return Err(err.into());
-                            }
-                            match self.play_resource(&tag_conf) {
-                                Err(err) => {
-                                    error!("Failed to initiate new playback: {}", err);
-                                    self.state = Idle;
-                                    return Err(err);
-                                }
-                                Ok(_) => {
-                                    self.state = Playing {
-                                        playing_since: Instant::now(),
-                                        offset: Duration::from_secs(0),
-                                        tag_conf,
-                                    };
-                                }
-                            }
-                        } else {
-                            info!(
-                                "Same resource, not completed, continuing with pause state {:?}",
-                                &at
-                            );
-                            if let Err(err) = self.effect_tx.send(Effect::PlayContinue(at)) {
-                                error!("Failed to continue playback: {}", err);
-                                self.state = Paused { at, prev_tag_conf };
-                                return Err(err.into());
-                            }
-                            self.state = Playing {
-                                playing_since: Instant::now(),
-                                offset: at,
-                                tag_conf,
-                            };
-                        }
-                        is_playing = true;
-                    }
+    // External entry point.
+    pub fn seek_forward_command(&mut self) -> Result<()> {
+        debug!("Player: seek forward");
+        self.step(PlayerInput::SeekForward)
+    }
 
-                    Paused { at, prev_tag_conf } => {
-                        // new resource
-                        info!("New resource, playing from beginning");
-                        if let Err(err) = self.effect_tx.send(Effect::Stop) {
-                            error!("Failed to stop playback: {}", err);
-                            self.state = Paused { at, prev_tag_conf };
-                            return Err(err.into());
-                        }
-
-                        match self.play_resource(&tag_conf) {
-                            Err(err) => {
-                                error!("Failed to initiate new playback: {}", err);
-                                self.state = Idle;
-                                return Err(err);
-                            }
-                            Ok(_) => {
-                                self.state = Playing {
-                                    playing_since: Instant::now(),
-                                    offset: Duration::from_secs(0),
-                                    tag_conf,
-                                };
-                            }
-                        }
-                    }
-                }
-            }
+    // External entry point.
+    pub fn seek_backward_command(&mut self) -> Result<()> {
+        debug!("Player: seek backward");
+        self.step(PlayerInput::SeekBackward)
+    }
 
-            PlaybackRequest::Stop => {
-                // RFID tag removed.
+    // External entry point.
+    pub fn volume_up_command(&mut self) -> Result<()> {
+        let cap = self.volume_cap();
+        self.volume.up(&self.config.get(), cap);
+        self.apply_volume();
+        Ok(())
+    }
 
-                match self.state.clone() {
-                    Idle => {}
+    // External entry point.
+    pub fn volume_down_command(&mut self) -> Result<()> {
+        let cap = self.volume_cap();
+        self.volume.down(&self.config.get(), cap);
+        self.apply_volume();
+        Ok(())
+    }
 
-                    Paused { .. } => {}
+    // External entry point. Sets an absolute volume level, e.g. from a potentiometer. The
+    // level is kept above the cap of the active schedule window, so that it is restored
+    // once the window ends.
+    pub fn set_volume_command(&mut self, level: u32) -> Result<()> {
+        self.volume.set(&self.config.get(), level, None);
+        self.apply_volume();
+        Ok(())
+    }
 
-                    Playing {
-                        playing_since,
-                        offset,
-                        tag_conf,
-                    } => {
-                        if config.trigger_only_mode {
-                            is_playing = true;
-                        } else {
-                            let played_pos = offset + playing_since.elapsed();
-
-                            if let Err(err) = self.effect_tx.send(Effect::Stop) {
-                                error!("Failed to execute playback pause: {}", err);
-                                self.state = Idle;
-                                return Err(err.into());
-                            }
-
-                            if is_complete {
-                                self.state = Idle;
-                            } else {
-                                self.state = Paused {
-                                    prev_tag_conf: tag_conf.clone(),
-                                    at: played_pos,
-                                };
-                            }
-                        }
-                    }
-                }
-            }
+    // External entry point. Plays the tag mapper entry keyed by a number entered on the
+    // keypad, as if its tag had been placed on the reader.
+    pub fn number_command(&mut self, number: &str) -> Result<()> {
+        let tag_id = number.to_string();
+        if self.tag_mapper.lookup(&tag_id).is_none() {
+            warn!("Ignoring number {}, no tag mapped to it", number);
+            return Ok(());
         }
+        let uid = Uid::from_id(tag_id);
+        self.playback(PlaybackRequest::Start(Tag { uid }))
+    }
 
+    // External entry point. Grants listening time on top of today's budget.
+    pub fn grant_listening_time(&mut self, extra: Duration) -> Result<()> {
+        let today = self.clock.local_time().day;
+        self.listening_time.grant(extra, today);
+        info!("Player status: {}", self.status());
         Ok(())
     }
 
+    // External entry point.
+    pub fn status(&self) -> PlayerStatus {
+        PlayerStatus {
+            state: self.state.clone(),
+            volume: self.applied_volume,
+            sleep_timer: self.sleep_timer,
+            schedule_window: self.schedule_window,
+            listening_time_left: self.listening_time_left(),
+        }
+    }
+
+    // External entry point, to be called before exiting. Persists what is otherwise
+    // persisted periodically.
+    pub fn shutdown(&mut self) {
+        self.volume.persist();
+    }
+
+    // External entry point, to be called periodically.
+    pub fn tick(&mut self) -> Result<()> {
+        self.volume.persist();
+        self.update_schedule();
+        self.account_listening_time();
+        if self.state.is_playing() && self.listening_time_left() == Some(Duration::ZERO) {
+            info!("Listening time used up");
+            self.step(PlayerInput::ListeningTimeExhausted)?;
+        }
+        match self.sleep_timer.tick(self.clock.now()) {
+            Some(action) => self.sleep_timer_action(action),
+            None => Ok(()),
+        }
+    }
+
+    // External entry point.
+    pub fn playback_event(&mut self, event: PlaybackEvent) -> Result<()> {
+        debug!("Player: playback event {:?}", event);
+        if event.request != self.playback_requests {
+            debug!(
+                "Discarding event of superseded playback request {}",
+                event.request
+            );
+            return Ok(());
+        }
+        let mut sleep_timer_action = None;
+        match event.kind {
+            PlaybackEventKind::TrackStarted {
+                offset, duration, ..
+            } => {
+                self.track_duration = duration;
+                let remaining = duration.map(|duration| duration.saturating_sub(offset));
+                self.sleep_timer.track_started(remaining, self.clock.now());
+            }
+            PlaybackEventKind::TrackFinished { track } => {
+                debug!("Finished playing track {}", track);
+                sleep_timer_action = self.sleep_timer.track_finished();
+            }
+            PlaybackEventKind::QueueExhausted => info!("Playback complete"),
+            PlaybackEventKind::DecodeError { track, ref error } => {
+                warn!("Failed to play track {}: {}", track, error);
+            }
+            PlaybackEventKind::StreamMetadata(ref metadata) => {
+                info!("Stream metadata: {:?}", metadata);
+            }
+        }
+        self.step(PlayerInput::Playback(event.kind))?;
+        match sleep_timer_action {
+            Some(action) => self.sleep_timer_action(action),
+            None => Ok(()),
+        }
+    }
+
     // Creates a new Player object and returns a handle to it.
     pub fn new(
         effect_tx: Sender<Effect>,
         config: ConfigLoaderHandle,
         tag_mapper: TagMapperHandle,
-        interpreter_state: Arc<RwLock<InterpreterState>>,
+        bookmarks: BookmarkStore,
+        volume: Volume,
+        listening_time: ListeningTime,
+        clock: Arc<dyn Clock>,
     ) -> Result<Player> {
-        let player = Player {
+        let mut player = Player {
             effect_tx,
             state: PlayerState::Idle,
             config,
             tag_mapper,
-            interpreter_state,
+            bookmarks,
+            clock,
+            playback_requests: 0,
+            sleep_timer: SleepTimer::Disarmed,
+            track_duration: None,
+            control_tag_present: false,
+            volume,
+            applied_volume: None,
+            schedule_window: None,
+            listening_time,
+            listening_time_accounted: None,
         };
+        player.update_schedule();
         Ok(player)
     }
 }
@@ -493,3 +1017,743 @@ pub mod err {
     }
     impl std::error::Error for Error {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::clock::SimulatedClock;
+    use crate::components::tag_mapper::ResumePolicy;
+    use crate::model::config::Config;
+    use proptest::prelude::*;
+    use std::time::UNIX_EPOCH;
+
+    const ELAPSED: Duration = Duration::from_secs(10);
+
+    fn tag(id: &str) -> TagConf {
+        TagConf {
+            tag_id: id.to_string(),
+            uris: vec![format!("{}.mp3", id)],
+            ..TagConf::default()
+        }
+    }
+
+    fn resumable(id: &str) -> TagConf {
+        TagConf {
+            resume: Some(ResumePolicy::Always),
+            ..tag(id)
+        }
+    }
+
+    fn position(track: usize, offset: Duration) -> PlaybackPosition {
+        PlaybackPosition { track, offset }
+    }
+
+    fn playing(tag_conf: TagConf, track: usize, offset: Duration, since: Instant) -> PlayerState {
+        PlayerState::Playing {
+            tag_conf,
+            playing_since: since,
+            offset,
+            track,
+            complete: false,
+        }
+    }
+
+    fn complete(tag_conf: TagConf, since: Instant) -> PlayerState {
+        PlayerState::Playing {
+            tag_conf,
+            playing_since: since,
+            offset: Duration::ZERO,
+            track: 0,
+            complete: true,
+        }
+    }
+
+    fn paused(tag_conf: TagConf, track: usize, at: Duration) -> PlayerState {
+        PlayerState::Paused {
+            at,
+            track,
+            prev_tag_conf: tag_conf,
+        }
+    }
+
+    struct Case {
+        name: &'static str,
+        state: PlayerState,
+        input: PlayerInput,
+        observations: Observations,
+        expected_state: PlayerState,
+        expected_effects: Vec<Effect>,
+    }
+
+    fn check(cases: Vec<Case>, now: Instant) {
+        for case in cases {
+            let t = transition(&case.state, &case.input, &case.observations, now);
+            assert_eq!(t.state, case.expected_state, "state after: {}", case.name);
+            assert_eq!(
+                t.effects, case.expected_effects,
+                "effects of: {}",
+                case.name
+            );
+        }
+    }
+
+    #[test]
+    fn transitions_in_normal_mode() {
+        let start = Instant::now();
+        let now = start + ELAPSED;
+        let (a, b) = (tag("a"), tag("b"));
+        let normal = Observations::default();
+        let cases = vec![
+            Case {
+                name: "tag presented while idle",
+                state: PlayerState::Idle,
+                input: PlayerInput::Start(a.clone()),
+                observations: normal.clone(),
+                expected_state: playing(a.clone(), 0, Duration::ZERO, now),
+                expected_effects: vec![Effect::Play(a.clone()), Effect::LedOn],
+            },
+            Case {
+                name: "tag presented while idle, with bookmark",
+                state: PlayerState::Idle,
+                input: PlayerInput::Start(a.clone()),
+                observations: Observations {
+                    bookmark: Some(position(2, ELAPSED)),
+                    ..normal.clone()
+                },
+                expected_state: playing(a.clone(), 2, ELAPSED, now),
+                expected_effects: vec![
+                    Effect::PlayContinue(a.clone(), position(2, ELAPSED)),
+                    Effect::LedOn,
+                ],
+            },
+            Case {
+                name: "tag removed while playing",
+                state: playing(a.clone(), 1, Duration::from_secs(5), start),
+                input: PlayerInput::Stop,
+                observations: normal.clone(),
+                expected_state: paused(a.clone(), 1, Duration::from_secs(15)),
+                expected_effects: vec![Effect::Stop, Effect::LedOff],
+            },
+            Case {
+                name: "tag removed after playback completed",
+                state: complete(a.clone(), start),
+                input: PlayerInput::Stop,
+                observations: normal.clone(),
+                expected_state: PlayerState::Idle,
+                expected_effects: vec![Effect::Stop],
+            },
+            Case {
+                name: "same tag presented again while paused",
+                state: paused(a.clone(), 1, ELAPSED),
+                input: PlayerInput::Start(a.clone()),
+                observations: normal.clone(),
+                expected_state: playing(a.clone(), 1, ELAPSED, now),
+                expected_effects: vec![
+                    Effect::PlayContinue(a.clone(), position(1, ELAPSED)),
+                    Effect::LedOn,
+                ],
+            },
+            Case {
+                name: "other tag presented while paused",
+                state: paused(a.clone(), 1, ELAPSED),
+                input: PlayerInput::Start(b.clone()),
+                observations: normal.clone(),
+                expected_state: playing(b.clone(), 0, Duration::ZERO, now),
+                expected_effects: vec![Effect::Stop, Effect::Play(b.clone()), Effect::LedOn],
+            },
+            Case {
+                name: "pause",
+                state: playing(a.clone(), 0, Duration::ZERO, start),
+                input: PlayerInput::PauseContinue,
+                observations: normal.clone(),
+                expected_state: paused(a.clone(), 0, ELAPSED),
+                expected_effects: vec![Effect::Stop, Effect::LedOff],
+            },
+            Case {
+                name: "continue",
+                state: paused(a.clone(), 3, ELAPSED),
+                input: PlayerInput::PauseContinue,
+                observations: normal.clone(),
+                expected_state: playing(a.clone(), 3, ELAPSED, now),
+                expected_effects: vec![
+                    Effect::PlayContinue(a.clone(), position(3, ELAPSED)),
+                    Effect::LedOn,
+                ],
+            },
+            Case {
+                name: "pause/continue after playback completed",
+                state: complete(a.clone(), start),
+                input: PlayerInput::PauseContinue,
+                observations: normal.clone(),
+                expected_state: playing(a.clone(), 0, Duration::ZERO, now),
+                expected_effects: vec![Effect::Stop, Effect::Play(a.clone()), Effect::LedOn],
+            },
+            Case {
+                name: "pause/continue while idle",
+                state: PlayerState::Idle,
+                input: PlayerInput::PauseContinue,
+                observations: normal.clone(),
+                expected_state: PlayerState::Idle,
+                expected_effects: vec![],
+            },
+            Case {
+                name: "track started",
+                state: playing(a.clone(), 0, Duration::ZERO, start),
+                input: PlayerInput::Playback(PlaybackEventKind::TrackStarted {
+                    track: 1,
+                    offset: Duration::ZERO,
+                    duration: None,
+                }),
+                observations: normal.clone(),
+                expected_state: playing(a.clone(), 1, Duration::ZERO, now),
+                expected_effects: vec![],
+            },
+            Case {
+                name: "queue exhausted",
+                state: playing(a.clone(), 0, Duration::ZERO, start),
+                input: PlayerInput::Playback(PlaybackEventKind::QueueExhausted),
+                observations: normal.clone(),
+                expected_state: PlayerState::Playing {
+                    tag_conf: a.clone(),
+                    playing_since: start,
+                    offset: Duration::ZERO,
+                    track: 0,
+                    complete: true,
+                },
+                expected_effects: vec![Effect::LedOff],
+            },
+            Case {
+                name: "sleep timer expired",
+                state: playing(a.clone(), 0, Duration::ZERO, start),
+                input: PlayerInput::SleepTimerExpired,
+                observations: normal.clone(),
+                expected_state: paused(a.clone(), 0, ELAPSED),
+                expected_effects: vec![Effect::Stop, Effect::LedOff],
+            },
+            Case {
+                name: "stop playback",
+                state: playing(a.clone(), 0, Duration::ZERO, start),
+                input: PlayerInput::StopPlayback,
+                observations: normal.clone(),
+                expected_state: PlayerState::Idle,
+                expected_effects: vec![Effect::Stop, Effect::LedOff],
+            },
+            Case {
+                name: "seek backward past the start of the track",
+                state: playing(a.clone(), 2, Duration::ZERO, start),
+                input: PlayerInput::SeekBackward,
+                observations: Observations {
+                    skip: Duration::from_secs(30),
+                    ..normal.clone()
+                },
+                expected_state: playing(a.clone(), 2, Duration::ZERO, now),
+                expected_effects: vec![Effect::Seek(position(2, Duration::ZERO))],
+            },
+            Case {
+                name: "seek forward past the end of the track",
+                state: playing(a.clone(), 2, Duration::ZERO, start),
+                input: PlayerInput::SeekForward,
+                observations: Observations {
+                    skip: Duration::from_secs(30),
+                    track_duration: Some(Duration::from_secs(20)),
+                    ..normal.clone()
+                },
+                expected_state: playing(a.clone(), 2, Duration::ZERO, start),
+                expected_effects: vec![Effect::NextTrack],
+            },
+            Case {
+                name: "playback disabled during quiet hours",
+                state: PlayerState::Idle,
+                input: PlayerInput::Start(a.clone()),
+                observations: Observations {
+                    playback_disabled: true,
+                    ..normal.clone()
+                },
+                expected_state: PlayerState::Idle,
+                expected_effects: vec![],
+            },
+        ];
+        check(cases, now);
+    }
+
+    #[test]
+    fn transitions_in_trigger_only_mode() {
+        let start = Instant::now();
+        let now = start + ELAPSED;
+        let (a, b) = (tag("a"), tag("b"));
+        let trigger_only = Observations {
+            trigger_only_mode: true,
+            ..Observations::default()
+        };
+        let cases = vec![
+            Case {
+                name: "tag presented while idle",
+                state: PlayerState::Idle,
+                input: PlayerInput::Start(a.clone()),
+                observations: trigger_only.clone(),
+                expected_state: playing(a.clone(), 0, Duration::ZERO, now),
+                expected_effects: vec![Effect::Play(a.clone()), Effect::LedOn],
+            },
+            Case {
+                name: "tag removed while playing",
+                state: playing(a.clone(), 0, Duration::ZERO, start),
+                input: PlayerInput::Stop,
+                observations: trigger_only.clone(),
+                expected_state: playing(a.clone(), 0, Duration::ZERO, start),
+                expected_effects: vec![],
+            },
+            Case {
+                name: "same tag presented while playing",
+                state: playing(a.clone(), 0, Duration::ZERO, start),
+                input: PlayerInput::Start(a.clone()),
+                observations: trigger_only.clone(),
+                expected_state: playing(a.clone(), 0, Duration::ZERO, start),
+                expected_effects: vec![],
+            },
+            Case {
+                name: "other tag presented while playing",
+                state: playing(a.clone(), 0, Duration::ZERO, start),
+                input: PlayerInput::Start(b.clone()),
+                observations: trigger_only.clone(),
+                expected_state: playing(b.clone(), 0, Duration::ZERO, now),
+                expected_effects: vec![Effect::Stop, Effect::Play(b.clone())],
+            },
+            Case {
+                name: "same tag presented after playback completed",
+                state: complete(a.clone(), start),
+                input: PlayerInput::Start(a.clone()),
+                observations: trigger_only.clone(),
+                expected_state: playing(a.clone(), 0, Duration::ZERO, now),
+                expected_effects: vec![Effect::Stop, Effect::Play(a.clone()), Effect::LedOn],
+            },
+            Case {
+                name: "pause",
+                state: playing(a.clone(), 0, Duration::ZERO, start),
+                input: PlayerInput::PauseContinue,
+                observations: trigger_only.clone(),
+                expected_state: paused(a.clone(), 0, ELAPSED),
+                expected_effects: vec![Effect::Stop, Effect::LedOff],
+            },
+        ];
+        check(cases, now);
+    }
+
+    #[test]
+    fn bookmarks_are_recorded_for_resumable_tags() {
+        let start = Instant::now();
+        let now = start + ELAPSED;
+        let a = resumable("a");
+        let t = transition(
+            &playing(a.clone(), 1, Duration::ZERO, start),
+            &PlayerInput::Stop,
+            &Observations::default(),
+            now,
+        );
+        assert_eq!(
+            t.bookmarks,
+            vec![BookmarkUpdate::Set("a".to_string(), position(1, ELAPSED))]
+        );
+
+        let t = transition(
+            &playing(a.clone(), 1, Duration::ZERO, start),
+            &PlayerInput::Playback(PlaybackEventKind::QueueExhausted),
+            &Observations::default(),
+            now,
+        );
+        assert_eq!(t.bookmarks, vec![BookmarkUpdate::Remove("a".to_string())]);
+
+        let t = transition(
+            &playing(tag("b"), 1, Duration::ZERO, start),
+            &PlayerInput::Stop,
+            &Observations::default(),
+            now,
+        );
+        assert!(t.bookmarks.is_empty());
+
+        let never = TagConf {
+            resume: Some(ResumePolicy::Never),
+            ..tag("c")
+        };
+        let t = transition(
+            &playing(never.clone(), 1, Duration::ZERO, start),
+            &PlayerInput::Stop,
+            &Observations::default(),
+            now,
+        );
+        assert!(t.bookmarks.is_empty());
+        let t = transition(
+            &playing(never, 1, Duration::ZERO, start),
+            &PlayerInput::Playback(PlaybackEventKind::QueueExhausted),
+            &Observations::default(),
+            now,
+        );
+        assert!(t.bookmarks.is_empty());
+    }
+
+    const NOTICE: &str = "notice.mp3";
+
+    // A player starting at 08:00 UTC with the given tags mapped.
+    fn player(
+        config: Config,
+        mappings: Vec<TagConf>,
+    ) -> (Player, crossbeam_channel::Receiver<Effect>, SimulatedClock) {
+        let clock = SimulatedClock::starting_at(UNIX_EPOCH + Duration::from_secs(8 * 60 * 60));
+        let mappings = mappings
+            .into_iter()
+            .map(|tag_conf| (tag_conf.tag_id.clone(), tag_conf))
+            .collect();
+        let (effect_tx, effect_rx) = crossbeam_channel::unbounded();
+        let player = Player::new(
+            effect_tx,
+            ConfigLoaderHandle::from_config(config.clone()),
+            TagMapperHandle::from_mappings(mappings),
+            BookmarkStore::new(None).unwrap(),
+            Volume::new(&config).unwrap(),
+            ListeningTime::new(&config).unwrap(),
+            Arc::new(clock.clone()),
+        )
+        .unwrap();
+        (player, effect_rx, clock)
+    }
+
+    // A player limited to one minute of listening time per day.
+    fn limited_player(
+        mappings: Vec<TagConf>,
+    ) -> (Player, crossbeam_channel::Receiver<Effect>, SimulatedClock) {
+        let config = Config {
+            daily_listening_minutes: Some(1),
+            listening_time_notice: Some(NOTICE.to_string()),
+            ..Config::default()
+        };
+        player(config, mappings)
+    }
+
+    fn present(player: &mut Player, id: &str) {
+        let uid = Uid::from_id(id.to_string());
+        player
+            .playback(PlaybackRequest::Start(Tag { uid }))
+            .unwrap();
+    }
+
+    fn remove(player: &mut Player) {
+        player.playback(PlaybackRequest::Stop).unwrap();
+    }
+
+    // Playback related effects sent since the last call.
+    fn playback_effects(effect_rx: &crossbeam_channel::Receiver<Effect>) -> Vec<Effect> {
+        effect_rx
+            .try_iter()
+            .filter(|effect| !matches!(effect, Effect::SetVolume(_)))
+            .collect()
+    }
+
+    fn left(player: &Player) -> Option<Duration> {
+        player.status().listening_time_left
+    }
+
+    #[test]
+    fn listening_time_is_only_accounted_while_playing() {
+        let (mut player, effect_rx, clock) = limited_player(vec![tag("a")]);
+        assert_eq!(left(&player), Some(Duration::from_secs(60)));
+
+        present(&mut player, "a");
+        clock.advance(Duration::from_secs(20));
+        player.tick().unwrap();
+        assert_eq!(left(&player), Some(Duration::from_secs(40)));
+
+        remove(&mut player);
+        clock.advance(Duration::from_secs(600));
+        player.tick().unwrap();
+        assert_eq!(left(&player), Some(Duration::from_secs(40)));
+
+        present(&mut player, "a");
+        clock.advance(Duration::from_secs(30));
+        player.tick().unwrap();
+        assert_eq!(left(&player), Some(Duration::from_secs(10)));
+        assert_eq!(
+            playback_effects(&effect_rx),
+            vec![
+                Effect::Play(tag("a")),
+                Effect::LedOn,
+                Effect::Stop,
+                Effect::LedOff,
+                Effect::PlayContinue(tag("a"), position(0, Duration::from_secs(20))),
+                Effect::LedOn,
+            ]
+        );
+    }
+
+    #[test]
+    fn exhausted_listening_time_pauses_and_plays_the_notice() {
+        let (mut player, effect_rx, clock) = limited_player(vec![tag("a")]);
+        present(&mut player, "a");
+        clock.advance(Duration::from_secs(60));
+        player.tick().unwrap();
+        assert_eq!(left(&player), Some(Duration::ZERO));
+        assert_eq!(
+            player.status().state,
+            paused(tag("a"), 0, Duration::from_secs(60))
+        );
+        assert_eq!(
+            playback_effects(&effect_rx),
+            vec![
+                Effect::Play(tag("a")),
+                Effect::LedOn,
+                Effect::Stop,
+                Effect::PlayNotice(NOTICE.to_string()),
+                Effect::LedOff,
+            ]
+        );
+
+        // Events of the replaced playback are discarded, those of the notice do not
+        // affect the player state.
+        for request in [1, 2] {
+            let event = PlaybackEvent {
+                request,
+                kind: PlaybackEventKind::TrackStarted {
+                    track: 0,
+                    offset: Duration::ZERO,
+                    duration: None,
+                },
+            };
+            player.playback_event(event).unwrap();
+        }
+        assert_eq!(
+            player.status().state,
+            paused(tag("a"), 0, Duration::from_secs(60))
+        );
+        assert_eq!(player.status().sleep_timer, SleepTimer::Disarmed);
+
+        // Further requests are refused with the notice.
+        remove(&mut player);
+        present(&mut player, "a");
+        player.pause_continue_command().unwrap();
+        assert_eq!(
+            playback_effects(&effect_rx),
+            vec![
+                Effect::PlayNotice(NOTICE.to_string()),
+                Effect::PlayNotice(NOTICE.to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn listening_time_resets_the_next_day_and_can_be_extended() {
+        let admin = TagConf {
+            tag_id: "admin".to_string(),
+            uris: vec![],
+            extra_listening_minutes: Some(2),
+            ..TagConf::default()
+        };
+        let (mut player, effect_rx, clock) = limited_player(vec![tag("a"), admin]);
+        present(&mut player, "a");
+        clock.advance(Duration::from_secs(90));
+        player.tick().unwrap();
+        assert_eq!(left(&player), Some(Duration::ZERO));
+
+        // The admin tag does not replace the paused playback.
+        present(&mut player, "admin");
+        remove(&mut player);
+        assert_eq!(left(&player), Some(Duration::from_secs(90)));
+        assert_eq!(
+            player.status().state,
+            paused(tag("a"), 0, Duration::from_secs(90))
+        );
+
+        clock.advance(Duration::from_secs(24 * 60 * 60));
+        player.tick().unwrap();
+        assert_eq!(left(&player), Some(Duration::from_secs(60)));
+        playback_effects(&effect_rx);
+        present(&mut player, "a");
+        assert_eq!(
+            playback_effects(&effect_rx),
+            vec![
+                Effect::PlayContinue(tag("a"), position(0, Duration::from_secs(90))),
+                Effect::LedOn,
+            ]
+        );
+    }
+
+    #[test]
+    fn numbers_keep_leading_zeros() {
+        let (mut player, effect_rx, _) = player(Config::default(), vec![tag("12"), tag("012")]);
+        player.number_command("012").unwrap();
+        assert_eq!(
+            playback_effects(&effect_rx),
+            vec![Effect::Play(tag("012")), Effect::LedOn]
+        );
+
+        // Unmapped numbers are ignored.
+        player.number_command("0012").unwrap();
+        assert_eq!(playback_effects(&effect_rx), vec![]);
+    }
+
+    fn tag_conf_strategy() -> impl Strategy<Value = TagConf> {
+        prop_oneof![Just(tag("a")), Just(tag("b")), Just(resumable("c"))]
+    }
+
+    fn offset_strategy() -> impl Strategy<Value = Duration> {
+        (0u64..3_600_000).prop_map(Duration::from_millis)
+    }
+
+    fn state_strategy(since: Instant) -> impl Strategy<Value = PlayerState> {
+        prop_oneof![
+            Just(PlayerState::Idle),
+            (
+                tag_conf_strategy(),
+                0usize..10,
+                offset_strategy(),
+                any::<bool>()
+            )
+                .prop_map(move |(tag_conf, track, offset, complete)| {
+                    PlayerState::Playing {
+                        tag_conf,
+                        playing_since: since,
+                        offset,
+                        track,
+                        complete,
+                    }
+                }),
+            (tag_conf_strategy(), 0usize..10, offset_strategy())
+                .prop_map(|(tag_conf, track, at)| paused(tag_conf, track, at)),
+        ]
+    }
+
+    fn input_strategy() -> impl Strategy<Value = PlayerInput> {
+        prop_oneof![
+            tag_conf_strategy().prop_map(PlayerInput::Start),
+            Just(PlayerInput::Stop),
+            Just(PlayerInput::PauseContinue),
+            Just(PlayerInput::SleepTimerExpired),
+            Just(PlayerInput::StopPlayback),
+            Just(PlayerInput::NextTrack),
+            Just(PlayerInput::PreviousTrack),
+            Just(PlayerInput::Playback(PlaybackEventKind::QueueExhausted)),
+        ]
+    }
+
+    fn observations_strategy() -> impl Strategy<Value = Observations> {
+        (
+            any::<bool>(),
+            proptest::option::of((0usize..10, offset_strategy())),
+        )
+            .prop_map(|(trigger_only_mode, bookmark)| Observations {
+                trigger_only_mode,
+                bookmark: bookmark.map(|(track, offset)| position(track, offset)),
+                ..Observations::default()
+            })
+    }
+
+    fn elapsed_strategy() -> impl Strategy<Value = Duration> {
+        (0u64..86_400_000).prop_map(Duration::from_millis)
+    }
+
+    proptest! {
+        // Pausing and continuing picks up playback where it was paused.
+        #[test]
+        fn pause_then_continue_resumes_at_pause_position(
+            tag_conf in tag_conf_strategy(),
+            track in 0usize..10,
+            offset in offset_strategy(),
+            elapsed in elapsed_strategy(),
+            trigger_only_mode in any::<bool>(),
+        ) {
+            let start = Instant::now();
+            let now = start + elapsed;
+            let observations = Observations { trigger_only_mode, ..Observations::default() };
+            let state = playing(tag_conf.clone(), track, offset, start);
+
+            let paused = transition(&state, &PlayerInput::PauseContinue, &observations, now);
+            prop_assert_eq!(&paused.state, &self::paused(tag_conf.clone(), track, offset + elapsed));
+            prop_assert_eq!(&paused.effects, &vec![Effect::Stop, Effect::LedOff]);
+
+            let resumed = transition(&paused.state, &PlayerInput::PauseContinue, &observations, now);
+            let position = position(track, offset + elapsed);
+            prop_assert_eq!(&resumed.state, &playing(tag_conf.clone(), track, offset + elapsed, now));
+            prop_assert_eq!(
+                &resumed.effects,
+                &vec![Effect::PlayContinue(tag_conf, position), Effect::LedOn]
+            );
+        }
+
+        // Removing the tag pauses playback, unless in trigger-only mode, where it is
+        // ignored altogether.
+        #[test]
+        fn tag_removal_pauses_or_is_ignored(
+            state in state_strategy(Instant::now()),
+            elapsed in elapsed_strategy(),
+            trigger_only_mode in any::<bool>(),
+        ) {
+            let now = match state {
+                PlayerState::Playing { playing_since, .. } => playing_since + elapsed,
+                _ => Instant::now(),
+            };
+            let observations = Observations { trigger_only_mode, ..Observations::default() };
+            let t = transition(&state, &PlayerInput::Stop, &observations, now);
+            prop_assert!(trigger_only_mode || !t.state.is_playing());
+            match state {
+                PlayerState::Playing { complete: false, ref tag_conf, .. } if !trigger_only_mode => {
+                    let position = state.position(now);
+                    prop_assert_eq!(
+                        &t.state,
+                        &paused(tag_conf.clone(), position.track, position.offset)
+                    );
+                    prop_assert_eq!(&t.effects, &vec![Effect::Stop, Effect::LedOff]);
+                }
+                PlayerState::Playing { complete: true, .. } if !trigger_only_mode => {
+                    prop_assert_eq!(&t.state, &PlayerState::Idle);
+                    prop_assert_eq!(&t.effects, &vec![Effect::Stop]);
+                }
+                _ => {
+                    if trigger_only_mode {
+                        prop_assert_eq!(&t.state, &state);
+                    }
+                    prop_assert!(t.effects.is_empty());
+                }
+            }
+        }
+
+        // An expiring sleep timer never starts playback and pauses playback in progress.
+        #[test]
+        fn sleep_timer_expiry_pauses_playback(
+            state in state_strategy(Instant::now()),
+            observations in observations_strategy(),
+            elapsed in elapsed_strategy(),
+        ) {
+            let now = Instant::now() + elapsed;
+            let t = transition(&state, &PlayerInput::SleepTimerExpired, &observations, now);
+            prop_assert!(!t.state.is_playing());
+            let starts_playback = t.effects.iter().any(|effect| {
+                matches!(effect, Effect::Play(_) | Effect::PlayContinue(_, _) | Effect::LedOn)
+            });
+            prop_assert!(!starts_playback);
+            if state.is_playing() {
+                let position = state.position(now);
+                let paused_at_position = matches!(
+                    t.state,
+                    PlayerState::Paused { track, at, .. }
+                        if track == position.track && at == position.offset
+                );
+                prop_assert!(paused_at_position);
+                prop_assert!(t.effects.contains(&Effect::Stop));
+            } else {
+                prop_assert_eq!(&t.state, &state);
+                prop_assert!(t.effects.is_empty());
+            }
+        }
+
+        // The LED is switched exactly when the transition starts or ends playback.
+        #[test]
+        fn led_follows_playback(
+            state in state_strategy(Instant::now()),
+            input in input_strategy(),
+            observations in observations_strategy(),
+        ) {
+            let t = transition(&state, &input, &observations, Instant::now() + ELAPSED);
+            let led_on = t.effects.iter().filter(|effect| **effect == Effect::LedOn).count();
+            let led_off = t.effects.iter().filter(|effect| **effect == Effect::LedOff).count();
+            prop_assert_eq!(led_on, (!state.is_playing() && t.state.is_playing()) as usize);
+            prop_assert_eq!(led_off, (state.is_playing() && !t.state.is_playing()) as usize);
+        }
+    }
+}